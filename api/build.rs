@@ -68,6 +68,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             "Backend",
             "Data",
             "Policy",
+            "MaskMode",
+            "Generalization",
         ],
         block: [
             "Policy",
@@ -231,6 +231,9 @@ impl Client {
             get_job_output_schema: GetJobOutputSchemaRequest, GetJobOutputSchemaResponse,
             get_job_output_rows: GetJobOutputRowsRequest, Streaming<GetJobOutputRowsResponse>,
             query_job: QueryJobRequest, QueryJobResponse,
+            detokenize_job: DetokenizeJobRequest, DetokenizeJobResponse,
+            list_audit_entries: ListAuditEntriesRequest, ListAuditEntriesResponse,
+            trace_jobs: TraceJobsRequest, TraceJobsResponse,
         }
     }
 
@@ -250,6 +253,7 @@ impl Client {
             get_virtual_datasets: GetVirtualDatasetsRequest, GetVirtualDatasetsResponse,
             get_virtual_tables: GetVirtualTablesRequest, GetVirtualTablesResponse,
             get_virtual_table_schema: GetVirtualTableSchemaRequest, GetVirtualTableSchemaResponse,
+            get_virtual_column_statistics: GetVirtualColumnStatisticsRequest, GetVirtualColumnStatisticsResponse,
         }
     }
 }
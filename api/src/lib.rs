@@ -20,6 +20,7 @@ pub use proto::parallax::service::{
         *,
     },
     job::v1::{
+        audit_entry::Decision as AuditDecision,
         job_service_client::JobServiceClient,
         job_service_server::{JobService, JobServiceServer},
         job_status::JobState,
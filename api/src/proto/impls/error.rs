@@ -174,6 +174,14 @@ impl ScopeError {
             ..Default::default()
         }
     }
+
+    pub fn changed<S: ToString + ?Sized>(source: &S) -> Self {
+        Self {
+            kind: ScopeErrorKind::Changed as i32,
+            source: source.to_string(),
+            description: "the resource was written concurrently".to_string(),
+        }
+    }
 }
 
 impl std::fmt::Display for ScopeError {
@@ -25,3 +25,31 @@ impl Job {
         }
     }
 }
+
+impl AuditEntry {
+    /// The hash of this entry: the hex encoded SHA-256 of the entry serialized
+    /// to JSON, with an empty hash. As entries hold the hash of the entry
+    /// before them, altering an entry changes the hashes of the entries after it.
+    pub fn digest(&self) -> String {
+        use sha2::{Digest, Sha256};
+        let unhashed = Self {
+            hash: String::new(),
+            ..self.clone()
+        };
+        // serializing a protobuf message to JSON cannot fail
+        let as_json = serde_json::to_vec(&unhashed).unwrap();
+        format!("{:x}", Sha256::digest(&as_json))
+    }
+
+    /// Whether this entry follows `previous` in the audit log, and was not
+    /// altered since it was hashed
+    pub fn follows(&self, previous: Option<&Self>) -> bool {
+        let chained = match previous {
+            Some(previous) => {
+                self.sequence == previous.sequence + 1 && self.previous_hash == previous.hash
+            }
+            None => self.previous_hash.is_empty(),
+        };
+        chained && self.hash == self.digest()
+    }
+}
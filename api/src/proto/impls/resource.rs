@@ -64,15 +64,17 @@ macro_rules! impl_try_into {
 use resource_v1::{
     backend::Backend as BackendEnum, data::Data as DataEnum, policy::Policy as PolicyEnum,
     resource::Resource as ResourceEnum, Backend, BigQueryBackend as BigQuery,
-    CollectionData as Collection, Data, Dataset, DrillBackend as Drill, Group, HashPolicy as Hash,
-    MongoBackend as Mongo, ObfuscatePolicy as Obfuscate, Policy, Resource, TableData as Table,
-    User, WhitelistPolicy as Whitelist,
+    CollectionData as Collection, ConsentPolicy as Consent, Data, Dataset, DrillBackend as Drill,
+    ExclusionList, ExpressionPolicy as Expression, GeneralizePolicy as Generalize, Group,
+    HashPolicy as Hash, MaskPolicy as Mask, MongoBackend as Mongo, ObfuscatePolicy as Obfuscate,
+    Policy, PrivacyUnit, RedactPolicy as Redact, Resource, Secret, TableData as Table,
+    TemplatePolicy as Template, TokenizePolicy as Tokenize, User, WhitelistPolicy as Whitelist,
 };
 
-impl_try_into!(Resource.ResourceEnum as resource -> { Backend, User, Group, Dataset, });
+impl_try_into!(Resource.ResourceEnum as resource -> { Backend, Secret, User, Group, Dataset, PrivacyUnit, ExclusionList, });
 
 impl_try_into!(Backend.BackendEnum as backend -> { Mongo, Drill, BigQuery, });
 
 impl_try_into!(Data.DataEnum as data -> { Table, Collection, });
 
-impl_try_into!(Policy.PolicyEnum as policy -> { Whitelist, Hash, Obfuscate, });
+impl_try_into!(Policy.PolicyEnum as policy -> { Whitelist, Hash, Obfuscate, Mask, Generalize, Tokenize, Expression, Consent, Template, Redact, });
@@ -0,0 +1,87 @@
+use anyhow::{Error, Result};
+
+use std::io::{Read, Write};
+
+use tonic::Request;
+
+use prettytable::Table;
+
+use parallax_api::{
+    client::Client, AuditEntry, ListAuditEntriesRequest, TraceJobsRequest, TracedJob,
+};
+
+pub async fn list_audit_entries(client: &mut Client, from: u64) -> Result<Vec<AuditEntry>> {
+    let req = Request::new(ListAuditEntriesRequest { from });
+    let entries = client.list_audit_entries(req).await?.into_inner().entries;
+    Ok(entries)
+}
+
+/// Checks that each of `entries` follows the one before it and was not
+/// altered since it was appended to the audit log
+pub fn verify_chain(entries: &[AuditEntry]) -> Result<()> {
+    let mut previous = None;
+    for entry in entries.iter() {
+        let intact = match previous {
+            // the first entry listed may follow entries which were not
+            Some(_) => entry.follows(previous),
+            None if entry.sequence == 0 => entry.follows(None),
+            None => entry.hash == entry.digest(),
+        };
+        if !intact {
+            return Err(Error::msg(format!(
+                "the audit log does not verify at entry {}",
+                entry.sequence
+            )));
+        }
+        previous = Some(entry);
+    }
+    Ok(())
+}
+
+/// Writes `entries` to `w` as JSON lines
+pub fn write_json_lines<W: Write>(mut w: W, entries: &[AuditEntry]) -> Result<()> {
+    for entry in entries.iter() {
+        serde_json::to_writer(&mut w, entry)?;
+        writeln!(w)?;
+    }
+    Ok(())
+}
+
+/// The values of the CSV read from `r`
+pub fn read_values<R: Read>(r: R) -> Result<Vec<String>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(r);
+    let mut values = Vec::new();
+    for record in reader.records() {
+        values.extend(record?.iter().map(|value| value.to_string()));
+    }
+    Ok(values)
+}
+
+/// The jobs authorized between `from` and `to` whose watermarks `values`
+/// carry, most matches first
+pub async fn trace(
+    client: &mut Client,
+    values: Vec<String>,
+    from: &str,
+    to: &str,
+) -> Result<Vec<TracedJob>> {
+    let req = Request::new(TraceJobsRequest {
+        values,
+        from: from.to_string(),
+        to: to.to_string(),
+    });
+    let jobs = client.trace_jobs(req).await?.into_inner().jobs;
+    Ok(jobs)
+}
+
+pub fn print_trace(jobs: &[TracedJob]) {
+    let mut table = Table::new();
+    table.add_row(row!["JOB", "USER", "MATCHES"]);
+    for job in jobs.iter() {
+        table.add_row(row![job.job_id, job.user, job.matches]);
+    }
+    table.printstd();
+}
@@ -1,8 +1,9 @@
 use anyhow::{Error, Result};
 use parallax_api::client::Client;
 use parallax_api::{
-    GetVirtualDatasetsRequest, GetVirtualDatasetsResponse, GetVirtualTableSchemaRequest,
-    GetVirtualTableSchemaResponse, GetVirtualTablesRequest, GetVirtualTablesResponse,
+    GetVirtualColumnStatisticsRequest, GetVirtualDatasetsRequest, GetVirtualDatasetsResponse,
+    GetVirtualTableSchemaRequest, GetVirtualTableSchemaResponse, GetVirtualTablesRequest,
+    GetVirtualTablesResponse,
 };
 use prettytable::{Cell, Row, Table};
 
@@ -96,3 +97,56 @@ async fn get_virtual_table_schema(
     table.printstd();
     Ok(())
 }
+
+/// Prints the statistics of a column given as <dataset>.<table>.<column>
+pub async fn stats(client: &mut Client, column: String) -> Result<()> {
+    let substrings: Vec<_> = column.split('.').collect();
+    let req = match substrings.as_slice() {
+        [dataset, table, column] => GetVirtualColumnStatisticsRequest {
+            dataset_id: dataset.to_string(),
+            table_id: table.to_string(),
+            column_id: column.to_string(),
+        },
+        _ => {
+            return Err(Error::msg(
+                "Column should be of the form <dataset>.<table>.<column>",
+            ))
+        }
+    };
+    let statistics_resp = client.get_virtual_column_statistics(req).await?;
+    let statistics = statistics_resp
+        .into_inner()
+        .statistics
+        .ok_or(Error::msg("no statistics in response"))?;
+
+    let mut table = Table::new();
+    table.add_row(Row::new(vec![
+        Cell::new("DISTINCT_COUNT"),
+        Cell::new(&statistics.distinct_count.to_string()),
+    ]));
+    table.add_row(Row::new(vec![
+        Cell::new("NULL_FRACTION"),
+        Cell::new(&statistics.null_fraction.to_string()),
+    ]));
+    table.add_row(Row::new(vec![
+        Cell::new("HISTOGRAM"),
+        Cell::new(&statistics.histogram.join(", ")),
+    ]));
+    table.printstd();
+
+    let mut table = Table::new();
+    table.add_row(Row::new(vec![Cell::new("VALUE"), Cell::new("COUNT")]));
+    for top_value in statistics.top_values {
+        let value = if top_value.is_null {
+            "NULL".to_string()
+        } else {
+            top_value.value
+        };
+        table.add_row(Row::new(vec![
+            Cell::new(&value),
+            Cell::new(&top_value.count.to_string()),
+        ]));
+    }
+    table.printstd();
+    Ok(())
+}
@@ -2,6 +2,8 @@ use colored::Colorize;
 
 use anyhow::{Error, Result};
 
+use chrono::{DateTime, Duration, Utc};
+
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::Read;
@@ -11,7 +13,7 @@ use tonic::Request;
 
 use parallax_api::{
     client::Client, Block, BlockType, CreateResourceRequest, DeleteResourceRequest,
-    ListResourcesRequest, ListResourcesResponse, Resource, UpdateResourceRequest,
+    ListResourcesRequest, ListResourcesResponse, Resource, ResourceEnum, UpdateResourceRequest,
 };
 
 type Scope = HashMap<BlockType, Resource>;
@@ -195,6 +197,49 @@ impl Plan {
     }
 }
 
+/// Warns about the policy bindings of `scope` which have expired or will
+/// expire within `days` days.
+pub fn expiry_warnings(scope: &Scope, days: i64) -> Result<Vec<String>> {
+    let now = Utc::now();
+    let horizon = now + Duration::days(days);
+
+    let mut warnings = Vec::new();
+    for resource in scope.values() {
+        let dataset = match &resource.resource {
+            Some(ResourceEnum::Dataset(dataset)) => dataset,
+            _ => continue,
+        };
+        for binding in dataset.policy_bindings.iter() {
+            let valid_until = match &binding.validity {
+                Some(validity) if !validity.valid_until.is_empty() => &validity.valid_until,
+                _ => continue,
+            };
+            let valid_until = DateTime::parse_from_rfc3339(valid_until)
+                .map_err(|e| {
+                    Error::msg(format!(
+                        "binding `{}` of dataset `{}` has an invalid `valid_until`: {}",
+                        binding.name, dataset.name, e
+                    ))
+                })?
+                .with_timezone(&Utc);
+            if valid_until <= now {
+                warnings.push(format!(
+                    "binding `{}` of dataset `{}` expired on {}",
+                    binding.name, dataset.name, valid_until
+                ));
+            } else if valid_until <= horizon {
+                warnings.push(format!(
+                    "binding `{}` of dataset `{}` expires on {}",
+                    binding.name, dataset.name, valid_until
+                ));
+            }
+        }
+    }
+    warnings.sort();
+
+    Ok(warnings)
+}
+
 /* FIXME: reintroduce in due time
 struct LockWrapper {
     lock: LockId,
@@ -308,3 +353,58 @@ pub async fn get_remote_scope(client: &mut Client) -> Result<Scope> {
 
     Ok(scope)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use parallax_api::{Dataset, PolicyBinding, ValidityWindow};
+
+    fn scope_with(valid_until: &[(&str, String)]) -> Scope {
+        let dataset = Dataset {
+            name: "patient_data".to_string(),
+            policy_bindings: valid_until
+                .iter()
+                .map(|(name, valid_until)| PolicyBinding {
+                    name: name.to_string(),
+                    validity: Some(ValidityWindow {
+                        valid_until: valid_until.clone(),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        };
+        let resource = Resource {
+            resource: Some(ResourceEnum::Dataset(dataset)),
+        };
+        let mut scope = HashMap::new();
+        scope.insert(resource.block_type().unwrap(), resource);
+        scope
+    }
+
+    #[test]
+    fn expiry_warnings_for_lapsing_bindings() {
+        let in_days = |days: i64| (Utc::now() + Duration::days(days)).to_rfc3339();
+        let scope = scope_with(&[
+            ("expired", in_days(-1)),
+            ("expiring", in_days(3)),
+            ("renewed", in_days(365)),
+            ("open", String::new()),
+        ]);
+        let warnings = expiry_warnings(&scope, 30).unwrap();
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].starts_with("binding `expired` of dataset `patient_data` expired"));
+        assert!(warnings[1].starts_with("binding `expiring` of dataset `patient_data` expires"));
+
+        // without a horizon, only expired bindings are reported
+        assert_eq!(expiry_warnings(&scope, 0).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn expiry_warnings_reject_invalid_bounds() {
+        let scope = scope_with(&[("invalid", "end of june".to_string())]);
+        assert!(expiry_warnings(&scope, 30).is_err());
+    }
+}
@@ -13,10 +13,10 @@ use arrow::csv::writer::Writer as CsvWriter;
 use arrow::ipc::reader::StreamReader;
 
 use parallax_api::{
-    client::Client, ArrowRecordBatch, ArrowSchema, GetJobOutputRowsRequest,
-    GetJobOutputRowsResponse, GetJobOutputSchemaRequest, GetJobOutputSchemaResponse, GetJobRequest,
-    GetJobResponse, InsertJobRequest, InsertJobResponse, Job, JobState, JobStatus, ListJobsRequest,
-    QueryJobRequest, QueryJobResponse,
+    client::Client, ArrowRecordBatch, ArrowSchema, DetokenizeJobRequest, DetokenizeJobResponse,
+    GetJobOutputRowsRequest, GetJobOutputRowsResponse, GetJobOutputSchemaRequest,
+    GetJobOutputSchemaResponse, GetJobRequest, GetJobResponse, InsertJobRequest, InsertJobResponse,
+    Job, JobState, JobStatus, ListJobsRequest, QueryJobRequest, QueryJobResponse,
 };
 use std::pin::Pin;
 use tonic::codegen::BoxStream;
@@ -48,9 +48,10 @@ pub async fn find_job(client: &mut Client, pattern: &str) -> Result<Job> {
     }
 }
 
-pub async fn insert_job(client: &mut Client, query: &str) -> Result<Job> {
+pub async fn insert_job(client: &mut Client, query: &str, purpose: Option<String>) -> Result<Job> {
     let job = Job {
         query: query.to_string(),
+        purpose: purpose.unwrap_or_default(),
         ..Default::default()
     };
     let req = Request::new(InsertJobRequest { job: Some(job) });
@@ -59,6 +60,23 @@ pub async fn insert_job(client: &mut Client, query: &str) -> Result<Job> {
     Ok(job)
 }
 
+pub async fn detokenize(
+    client: &mut Client,
+    dataset: &str,
+    policy: &str,
+    tokens: Vec<String>,
+    justification: &str,
+) -> Result<Vec<String>> {
+    let req = Request::new(DetokenizeJobRequest {
+        dataset: dataset.to_string(),
+        policy: policy.to_string(),
+        tokens,
+        justification: justification.to_string(),
+    });
+    let DetokenizeJobResponse { values } = client.detokenize_job(req).await?.into_inner();
+    Ok(values)
+}
+
 pub async fn list_jobs(client: &mut Client) -> Result<Vec<Job>> {
     let req = Request::new(ListJobsRequest {});
     let jobs = client.list_jobs(req).await?.into_inner().jobs;
@@ -1,6 +1,7 @@
 #![feature(async_closure)]
 
 use anyhow::{Error, Result};
+use colored::Colorize;
 use structopt::StructOpt;
 
 use std::fs::File;
@@ -32,6 +33,8 @@ mod job;
 
 mod catalog;
 
+mod audit;
+
 use crate::job::Fetch;
 use parallax_api::Job;
 use parallax_api::JobState;
@@ -106,6 +109,11 @@ enum Command {
         #[structopt(subcommand)]
         subcmd: CatalogSubCommand,
     },
+    #[structopt(about = "Inspect the audit log of the deployment")]
+    Audit {
+        #[structopt(subcommand)]
+        subcmd: AuditSubCommand,
+    },
 }
 
 #[derive(Debug, Clone, StructOpt)]
@@ -158,7 +166,14 @@ enum AuthSubCommand {
 #[derive(Debug, Clone, StructOpt)]
 enum GovSubCommand {
     #[structopt(about = "Shows the operations that would be carried on by `parallax gov apply`")]
-    Plan,
+    Plan {
+        #[structopt(
+            long,
+            help = "warn about policy bindings which expire within this many days",
+            default_value = "30"
+        )]
+        expiry_warning: i64,
+    },
     #[structopt(about = "Reconciles the remote state with local project files")]
     Apply {
         #[structopt(long, help = "whether to do a dry-run")]
@@ -188,6 +203,44 @@ enum CatalogSubCommand {
         )]
         pattern: Option<String>,
     },
+    #[structopt(about = "Show statistics of the values of a column (super users only)")]
+    Stats {
+        #[structopt(about = "the column, of the form <dataset>.<table>.<column>")]
+        column: String,
+    },
+}
+
+#[derive(Debug, Clone, StructOpt)]
+enum AuditSubCommand {
+    #[structopt(about = "Verifies and exports the audit log as JSON lines")]
+    Export {
+        #[structopt(
+            long,
+            help = "the position in the audit log of the first entry to export",
+            default_value = "0"
+        )]
+        from: u64,
+        #[structopt(long, help = "the file to export the audit log to (stdout if not set)")]
+        output: Option<PathBuf>,
+    },
+    #[structopt(
+        about = "Finds the jobs a leaked extract of watermarked results likely comes from"
+    )]
+    Trace {
+        #[structopt(help = "the leaked extract, as CSV")]
+        file: PathBuf,
+        #[structopt(
+            long,
+            help = "the RFC 3339 timestamp from which on the extract may have been produced"
+        )]
+        from: String,
+        #[structopt(
+            long,
+            help = "the RFC 3339 timestamp until which the extract may have been produced \
+                    (now if not set)"
+        )]
+        to: Option<String>,
+    },
 }
 
 #[derive(Debug, Clone, StructOpt)]
@@ -201,6 +254,8 @@ enum JobSubCommand {
     Insert {
         #[structopt(long, help = "the query to run (or stdin if not set)")]
         query: Option<String>,
+        #[structopt(long, help = "the purpose of use declared for the query")]
+        purpose: Option<String>,
     },
     #[structopt(about = "Gets more information about a job previously submitted")]
     Get {
@@ -231,6 +286,8 @@ enum JobSubCommand {
     Query {
         #[structopt(long, help = "the query to run (or stdin if not set)")]
         query: Option<String>,
+        #[structopt(long, help = "the purpose of use declared for the query")]
+        purpose: Option<String>,
         #[structopt(
             long,
             help = "save the output of the job in the specified file (if not specified, output to stdout)"
@@ -251,6 +308,20 @@ enum JobSubCommand {
         )]
         timeout: u64,
     },
+    #[structopt(about = "Reverses tokens produced by a tokenize policy")]
+    Detokenize {
+        #[structopt(long, help = "the dataset the tokens were produced from")]
+        dataset: String,
+        #[structopt(
+            long,
+            help = "the tokenize policy the tokens were produced with (e.g. `policy.tokenize.my_policy`)"
+        )]
+        policy: String,
+        #[structopt(long, help = "why the tokens need to be reversed")]
+        justification: String,
+        #[structopt(help = "the tokens to reverse")]
+        tokens: Vec<String>,
+    },
 }
 
 #[derive(Clone, Debug, StructOpt)]
@@ -386,13 +457,18 @@ async fn main() -> Result<()> {
             workspace_dir,
             subcmd,
         } => match subcmd {
-            GovSubCommand::Plan => {
+            GovSubCommand::Plan { expiry_warning } => {
                 let mut client = config.new_client(opt.disable_tls).await?;
                 let remote = gov::get_remote_scope(&mut client).await?;
                 let local = gov::read_workspace_dir(workspace_dir.as_path())?;
+                let warnings = gov::expiry_warnings(&local, expiry_warning)?;
                 let plan = gov::Plan::new(remote, local)?;
 
                 println!("{}", plan);
+
+                for warning in warnings.iter() {
+                    println!("{} {}", "Warning:".yellow().bold(), warning);
+                }
             }
             GovSubCommand::Apply {
                 dry_run,
@@ -443,10 +519,10 @@ async fn main() -> Result<()> {
                 let jobs = job::list_jobs(&mut client).await?;
                 job::print_jobs(jobs);
             }
-            JobSubCommand::Insert { query } => {
+            JobSubCommand::Insert { query, purpose } => {
                 let query = query_or_stdin(query)?;
                 let mut client = config.new_client(opt.disable_tls).await?;
-                let job = job::insert_job(&mut client, &query).await?;
+                let job = job::insert_job(&mut client, &query, purpose).await?;
                 println!("{}", job.id);
             }
             JobSubCommand::Get { job_id } => {
@@ -473,6 +549,7 @@ async fn main() -> Result<()> {
             }
             JobSubCommand::Query {
                 query,
+                purpose,
                 output,
                 format,
                 truncate,
@@ -482,17 +559,58 @@ async fn main() -> Result<()> {
                 let mut client = config.new_client(opt.disable_tls).await?;
                 let job = Job {
                     query,
+                    purpose: purpose.unwrap_or_default(),
                     ..Default::default()
                 };
                 let fetch = job::Fetch::query(&mut client, &job, timeout);
                 output_rows(output, format, truncate, fetch).await?
             }
+            JobSubCommand::Detokenize {
+                dataset,
+                policy,
+                justification,
+                tokens,
+            } => {
+                let mut client = config.new_client(opt.disable_tls).await?;
+                let values =
+                    job::detokenize(&mut client, &dataset, &policy, tokens, &justification).await?;
+                for value in values {
+                    println!("{}", value);
+                }
+            }
         },
         Command::Catalog { subcmd } => match subcmd {
             CatalogSubCommand::List { pattern } => {
                 let mut client = config.new_client(opt.disable_tls).await?;
                 catalog::ls(&mut client, pattern).await?;
             }
+            CatalogSubCommand::Stats { column } => {
+                let mut client = config.new_client(opt.disable_tls).await?;
+                catalog::stats(&mut client, column).await?;
+            }
+        },
+        Command::Audit { subcmd } => match subcmd {
+            AuditSubCommand::Export { from, output } => {
+                let mut client = config.new_client(opt.disable_tls).await?;
+                let entries = audit::list_audit_entries(&mut client, from).await?;
+                audit::verify_chain(&entries)?;
+                if let Some(path) = output {
+                    audit::write_json_lines(File::create(path)?, &entries)?;
+                } else {
+                    audit::write_json_lines(std::io::stdout(), &entries)?;
+                }
+            }
+            AuditSubCommand::Trace { file, from, to } => {
+                let mut client = config.new_client(opt.disable_tls).await?;
+                let values = audit::read_values(File::open(file)?)?;
+                let to = to.unwrap_or_default();
+                let jobs = audit::trace(&mut client, values, &from, &to).await?;
+                if jobs.is_empty() {
+                    println!("no watermark of an authorized job was found");
+                } else {
+                    audit::print_trace(&jobs);
+                }
+            }
         },
     }
 
@@ -4,7 +4,7 @@ use crate::common::*;
 use yup_oauth2::GetToken;
 
 use google_bigquery2::{
-    GetQueryResultsResponse, Job as BigQueryJob, TableListTables, TableReference,
+    GetQueryResultsResponse, Job as BigQueryJob, TableListTables, TableReference, TableRow,
 };
 
 use bigquery_storage::client::{Client as BigQueryStorageClient, Error as ClientError};
@@ -24,10 +24,14 @@ use crate::Result;
 
 use crate::opt::expr::Expr;
 use crate::opt::expr::ExprTree;
+use crate::opt::expr::{
+    sql_binary_op, sql_function, sql_number, Bucketing, Detector, FunctionName, Keying,
+    LiteralValue, Masking, Watermark, WrappedKeyset,
+};
 use crate::opt::{
     plan::Step, rel::*, CompositionError, Context, ContextError, ContextKey, DataType, Domain,
-    ExprAnsatz, ExprMeta, ExprT, HashAlgorithm, Mode, RelAnsatz, ToAnsatz, ValidateError,
-    ValidateResult,
+    ExprAnsatz, ExprMeta, ExprT, HashAlgorithm, HashEncoding, Mode, RelAnsatz, ToAnsatz,
+    ValidateError, ValidateResult,
 };
 use sqlparser::ast::Value::SingleQuotedString;
 
@@ -136,6 +140,35 @@ where
         res.map_err(|e| e.into())
     }
 
+    /// The query `step` is computed with, with its key material left out if
+    /// `redact_keys`
+    fn query_of(&self, step: &Step, redact_keys: bool) -> Result<String> {
+        let ctx = step
+            .ctx
+            .iter()
+            .map(|(ck, meta)| {
+                let in_source = meta
+                    .source
+                    .as_ref()
+                    .ok_or(Error::new("a table had no associated context_key"))?;
+                let table_ref = self.in_context(in_source)?;
+                Ok((ck.clone(), table_ref))
+            })
+            .collect::<Result<_>>()?;
+
+        let mut rel_t = BigQueryRelT::wrap(step.rel_t.clone(), &ctx);
+        rel_t.redact_keys = redact_keys;
+        let query: sqlparser::ast::Query = rel_t
+            .to_ansatz()
+            .map_err(|compositon_err| BackendError {
+                kind: BackendErrorKind::Unknown as i32,
+                source: "BigQuery".to_string(),
+                description: compositon_err.to_string(),
+            })?
+            .into();
+        Ok(query.to_string())
+    }
+
     #[deprecated(note = "Visible for testing")]
     pub async fn get_context_from_backend(
         &self,
@@ -203,6 +236,32 @@ where
             .map_err(|e| e.into())
     }
 
+    /// The rows of the results of `query_str`, waiting for the query to
+    /// complete and following all the pages of its results
+    async fn lite_query_rows(&self, query_str: &str) -> Result<Vec<TableRow>> {
+        let mut results = self.lite_query(query_str).await?;
+        let mut rows = Vec::new();
+        loop {
+            let page_token = if results.job_complete == Some(true) {
+                rows.extend(results.rows.unwrap_or_default());
+                match results.page_token {
+                    Some(page_token) => Some(page_token),
+                    None => return Ok(rows),
+                }
+            } else {
+                None
+            };
+            let job_id = results
+                .job_reference
+                .and_then(|jr| jr.job_id)
+                .ok_or_else(|| Error::new("invalid response from BigQuery"))?;
+            results = self
+                .to_inner()
+                .get_query_results_page(&self.staging.project_id, &job_id, page_token.as_deref())
+                .await?;
+        }
+    }
+
     pub(self) fn job_builder(&self) -> JobBuilder {
         let mut builder = JobBuilder::default();
         builder.project_id(&self.staging.project_id);
@@ -217,28 +276,7 @@ where
 {
     async fn compute(&self, step: Step) -> Result<()> {
         // take RelT into a SQL string and execute against BQ
-        let ctx = step
-            .ctx
-            .into_iter()
-            .map(|(ck, meta)| {
-                let in_source = meta
-                    .source
-                    .ok_or(Error::new("a table had no associated context_key"))?;
-                let table_ref = self.in_context(&in_source)?;
-                Ok((ck, table_ref))
-            })
-            .collect::<Result<_>>()?;
-
-        let rel_t = BigQueryRelT::wrap(step.rel_t, &ctx);
-        let query: sqlparser::ast::Query = rel_t
-            .to_ansatz()
-            .map_err(|compositon_err| BackendError {
-                kind: BackendErrorKind::Unknown as i32,
-                source: "BigQuery".to_string(),
-                description: compositon_err.to_string(),
-            })?
-            .into();
-        let query_str = query.to_string();
+        let query_str = self.query_of(&step, false)?;
 
         let output = self.in_staging(&step.promise)?;
 
@@ -252,6 +290,10 @@ where
         Ok(())
     }
 
+    fn render(&self, step: &Step) -> Result<String> {
+        self.query_of(step, true)
+    }
+
     async fn probe<'a>(&'a self, key: &'a ContextKey) -> Result<Box<dyn Probe + 'a>> {
         let table_ref = self.in_context(key)?;
         let probe = BigQueryProbe::new(self, table_ref).await?;
@@ -266,6 +308,16 @@ where
             .map_err(|e| e.into())
     }
 
+    async fn has_output(&self, ctx_key: &ContextKey) -> Result<bool> {
+        let table_ref = self.in_staging(ctx_key)?;
+        // outputs expire with the staging dataset
+        match self.to_inner().get_table(&table_ref).await {
+            Ok(_) => Ok(true),
+            Err(GcpError::NotFound(_)) => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
     async fn get_records(&self, ctx_key: &ContextKey) -> Result<ContentStream<ArrowRecordBatch>> {
         let table_ref = self.in_staging(ctx_key)?;
         self.storage
@@ -273,6 +325,138 @@ where
             .await
             .map_err(|e| e.into())
     }
+
+    async fn detokenize(
+        &self,
+        keyset: &WrappedKeyset,
+        context: &str,
+        tokens: &[String],
+    ) -> Result<Vec<String>> {
+        let tokens_array = tokens
+            .iter()
+            .map(|token| string_literal(token).to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let query_str = format!(
+            "SELECT SAFE.DETERMINISTIC_DECRYPT_STRING(\
+               {keyset}, \
+               SAFE.FROM_BASE64(token), \
+               {context}\
+             ) \
+             FROM UNNEST([{tokens}]) AS token WITH OFFSET AS pos \
+             ORDER BY pos",
+            keyset = keyset_chain(keyset),
+            context = string_literal(context),
+            tokens = tokens_array
+        );
+
+        let results = self.lite_query(&query_str).await?;
+
+        let values: Vec<_> = results
+            .rows
+            .unwrap_or_default()
+            .into_iter()
+            .map(|row| {
+                row.f
+                    .and_then(|mut cells| cells.pop()?.v)
+                    .unwrap_or_default()
+            })
+            .collect();
+
+        if values.len() != tokens.len() {
+            Err(Error::new("invalid response from BigQuery"))
+        } else {
+            Ok(values)
+        }
+    }
+
+    async fn watermark_matches(
+        &self,
+        keyset: &WrappedKeyset,
+        width: u64,
+        seeds: &[String],
+        marked: &[(String, String)],
+    ) -> Result<Vec<u64>> {
+        let mut matches = vec![0; seeds.len()];
+        // jobs and values are inlined in queries, whose length is bounded
+        for (batch, seeds) in seeds.chunks(TRACED_PER_QUERY).enumerate() {
+            for marked in marked.chunks(TRACED_PER_QUERY) {
+                let query_str = watermark_matches_query(keyset, width, seeds, marked);
+                for row in self.lite_query_rows(&query_str).await? {
+                    let cells: Vec<_> = row
+                        .f
+                        .unwrap_or_default()
+                        .into_iter()
+                        .map(|cell| cell.v.unwrap_or_default().parse::<usize>().ok())
+                        .collect();
+                    match cells.as_slice() {
+                        [Some(pos), Some(count)] if *pos < seeds.len() => {
+                            matches[batch * TRACED_PER_QUERY + pos] += *count as u64
+                        }
+                        _ => return Err(Error::new("invalid response from BigQuery")),
+                    }
+                }
+            }
+        }
+        Ok(matches)
+    }
+
+    async fn count_markers(
+        &self,
+        ctx_key: &ContextKey,
+        columns: &[String],
+        markers: &[String],
+    ) -> Result<Vec<u64>> {
+        if columns.is_empty() {
+            return Ok(vec![0; markers.len()]);
+        }
+        let table_ref = self.in_staging(ctx_key)?;
+        // only the columns counted in are read
+        let counts = markers
+            .iter()
+            .map(|marker| {
+                columns
+                    .iter()
+                    .map(|column| {
+                        format!(
+                            "IFNULL(SUM(DIV(LENGTH({column}) - \
+                             LENGTH(REPLACE({column}, {marker}, '')), {length})), 0)",
+                            column = quoted_identifier(column),
+                            marker = string_literal(marker),
+                            length = marker.chars().count()
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" + ")
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        let query_str = format!("SELECT {} FROM {}", counts, table_ref);
+
+        let results = self.lite_query(&query_str).await?;
+
+        let counts = results
+            .rows
+            .unwrap_or_default()
+            .into_iter()
+            .next()
+            .and_then(|row| row.f)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|cell| {
+                cell.v
+                    .unwrap_or_default()
+                    .parse::<u64>()
+                    .map_err(|_| Error::new("invalid response from BigQuery"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        if counts.len() != markers.len() {
+            Err(Error::new("invalid response from BigQuery"))
+        } else {
+            Ok(counts)
+        }
+    }
 }
 
 impl From<GcpError> for Error {
@@ -303,15 +487,22 @@ fn table_ref_to_context_key(table_ref: &TableRef) -> ContextKey {
 pub struct BigQueryRelT<'a> {
     root: RelT,
     ctx: &'a Context<TableRef>,
+    /// Whether key material is left out of the query, for it to be recorded
+    redact_keys: bool,
 }
 
 impl<'a> BigQueryRelT<'a> {
     fn wrap(root: RelT, ctx: &'a Context<TableRef>) -> Self {
-        Self { ctx, root }
+        Self {
+            ctx,
+            root,
+            redact_keys: false,
+        }
     }
 
     fn to_ansatz(self) -> std::result::Result<RelAnsatz, String> {
         let ctx = self.ctx;
+        let redact_keys = self.redact_keys;
         // Hotfix (FIXME): will not work if self.root is sole leaf
         self.root.try_fold(&mut |child| match child {
             GenericRel::Table(Table(key)) => {
@@ -324,7 +515,10 @@ impl<'a> BigQueryRelT<'a> {
                     .unwrap())
             }
             child => child
-                .map_expressions(&|expr_t| BigQueryExprT::wrap(expr_t.clone()))
+                .map_expressions(&|expr_t| BigQueryExprT {
+                    redact_keys,
+                    ..BigQueryExprT::wrap(expr_t.clone())
+                })
                 .to_ansatz()
                 .map_err(|e| e.to_string()),
         })
@@ -333,41 +527,654 @@ impl<'a> BigQueryRelT<'a> {
 
 pub struct BigQueryExprT {
     root: ExprT,
+    /// Whether key material is left out of the expression
+    redact_keys: bool,
 }
 
-impl BigQueryExprT {
-    fn expr_ansatz(node: Expr<ExprAnsatz>) -> std::result::Result<ExprAnsatz, CompositionError> {
-        match node {
-            Expr::Hash(crate::opt::expr::Hash { algo, expr, salt }) => {
-                let salt_literal = ast::Expr::Value(SingleQuotedString(base64::encode(&salt)));
+/// BigQuery string literals interpret backslash escapes and do not support
+/// doubling quotes, so user-provided strings (e.g. regular expressions) need
+/// to be escaped before being rendered.
+fn string_literal(value: &str) -> ast::Expr {
+    let escaped = value.replace('\\', "\\\\").replace('\'', "\\x27");
+    ast::Expr::Value(SingleQuotedString(escaped))
+}
 
-                let ast_expr = expr.into();
+/// A BigQuery quoted identifier, which may name a whole table path (e.g.
+/// `project.dataset.table`)
+fn quoted_identifier(name: &str) -> ast::Expr {
+    let escaped = name.replace('\\', "\\\\").replace('`', "\\`");
+    ast::Expr::Identifier(format!("`{}`", escaped))
+}
 
-                let concat = ast::Expr::Function(ast::Function {
-                    name: ast::ObjectName(vec!["CONCAT".to_string()]),
-                    args: vec![salt_literal, ast_expr],
-                    over: None,
-                    distinct: false,
-                });
+/// A BigQuery bytes literal, which functions taking keysets expect instead of
+/// any expression evaluating to bytes
+fn bytes_literal(bytes: &[u8]) -> ast::Expr {
+    let escaped: String = bytes.iter().map(|b| format!("\\x{:02x}", b)).collect();
+    ast::Expr::Identifier(format!("b'{}'", escaped))
+}
+
+/// What stands for key material in the queries recorded in the audit log
+fn redacted() -> ast::Expr {
+    ast::Expr::Identifier("[redacted]".to_string())
+}
 
-                let bq_algo = match algo {
-                    HashAlgorithm::SHA256 => "SHA256".to_string(),
+/// The mark of `stem` for the job `seed`: the first `width` hex digits of the
+/// digest of `stem` encrypted with `keyset`, bound to `seed`. The digest is
+/// taken as encrypted values all begin with the id of the key. Marks are only
+/// ever computed by BigQuery, when embedded as well as when traced.
+fn watermark_ansatz(stem: ast::Expr, seed: ast::Expr, keyset: ast::Expr, width: u64) -> ast::Expr {
+    let encrypted = sql_function("DETERMINISTIC_ENCRYPT", vec![keyset, stem, seed]);
+    sql_function(
+        "SUBSTR",
+        vec![
+            sql_function("TO_HEX", vec![sql_function("SHA256", vec![encrypted])]),
+            sql_number(1),
+            sql_number(width),
+        ],
+    )
+}
+
+/// The number of jobs, or of traced values, inlined in a query
+const TRACED_PER_QUERY: usize = 1000;
+
+/// The query counting, for each job of `seeds`, how many of the `marked` stems
+/// carry its mark. Only the jobs with matches are listed, by their position.
+fn watermark_matches_query(
+    keyset: &WrappedKeyset,
+    width: u64,
+    seeds: &[String],
+    marked: &[(String, String)],
+) -> String {
+    let seeds = seeds
+        .iter()
+        .map(|seed| string_literal(seed).to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    let marked = marked
+        .iter()
+        .map(|(stem, mark)| {
+            format!(
+                "STRUCT({} AS stem, {} AS mark)",
+                string_literal(stem),
+                string_literal(mark)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    let mark = watermark_ansatz(
+        ast::Expr::Identifier("stem".to_string()),
+        ast::Expr::Identifier("seed".to_string()),
+        keyset_chain(keyset),
+        width,
+    );
+    format!(
+        "SELECT seed_pos, COUNT(*) \
+         FROM UNNEST([{seeds}]) AS seed WITH OFFSET AS seed_pos \
+         CROSS JOIN UNNEST([{marked}]) \
+         WHERE {mark} = mark \
+         GROUP BY seed_pos",
+        seeds = seeds,
+        marked = marked,
+        mark = mark
+    )
+}
+
+/// The keyset `keyset` unwraps to. BigQuery unwraps it with the KMS key, so
+/// the keyset itself is never part of the query.
+fn keyset_chain(keyset: &WrappedKeyset) -> ast::Expr {
+    sql_function(
+        "KEYS.KEYSET_CHAIN",
+        vec![
+            string_literal(&keyset.kms_key),
+            bytes_literal(&keyset.keyset),
+        ],
+    )
+}
+
+impl BigQueryExprT {
+    /// BigQuery has no HMAC function and keys are not to be part of queries,
+    /// so keyed pseudonyms are the hash of the deterministic encryption of
+    /// the values with a keyset BigQuery unwraps itself.
+    fn hash_ansatz(
+        expr: ast::Expr,
+        algo: HashAlgorithm,
+        key: Keying,
+        encoding: HashEncoding,
+        truncate: Option<u64>,
+        watermark: Option<Watermark>,
+        redact_keys: bool,
+    ) -> ast::Expr {
+        let fn_name = match algo {
+            HashAlgorithm::SHA256 => "SHA256",
+            HashAlgorithm::SHA512 => "SHA512",
+        };
+        let hash = match key {
+            Keying::Salt(salt) => {
+                let salt_literal = if redact_keys {
+                    redacted()
+                } else {
+                    ast::Expr::Value(SingleQuotedString(base64::encode(&salt)))
                 };
+                sql_function(
+                    fn_name,
+                    vec![sql_function("CONCAT", vec![salt_literal, expr])],
+                )
+            }
+            Keying::Keyset { keyset, context } => {
+                let as_string = ast::Expr::Cast {
+                    expr: Box::new(expr),
+                    data_type: ast::DataType::Custom(ast::ObjectName(vec!["STRING".to_string()])),
+                };
+                let keyset = if redact_keys {
+                    redacted()
+                } else {
+                    keyset_chain(&keyset)
+                };
+                let encrypted = sql_function(
+                    "DETERMINISTIC_ENCRYPT",
+                    vec![keyset, as_string, string_literal(&context)],
+                );
+                sql_function(fn_name, vec![encrypted])
+            }
+        };
+        let encoded = match encoding {
+            HashEncoding::Base64 => sql_function("TO_BASE64", vec![hash]),
+            HashEncoding::Hex => sql_function("TO_HEX", vec![hash]),
+        };
+        let pseudonym = match truncate {
+            Some(length) => {
+                sql_function("SUBSTR", vec![encoded, sql_number(1), sql_number(length)])
+            }
+            None => encoded,
+        };
+        match watermark {
+            // the last characters of the pseudonym are replaced by the mark
+            // of the rest of it
+            Some(Watermark {
+                seed,
+                width,
+                keyset: Some(keyset),
+                ..
+            }) => {
+                let stem = sql_function(
+                    "SUBSTR",
+                    vec![
+                        pseudonym.clone(),
+                        sql_number(1),
+                        sql_function(
+                            "GREATEST",
+                            vec![
+                                sql_binary_op(
+                                    sql_function("LENGTH", vec![pseudonym]),
+                                    ast::BinaryOperator::Minus,
+                                    sql_number(width),
+                                ),
+                                sql_number(0),
+                            ],
+                        ),
+                    ],
+                );
+                let keyset = if redact_keys {
+                    redacted()
+                } else {
+                    keyset_chain(&keyset)
+                };
+                let mark = watermark_ansatz(stem.clone(), string_literal(&seed), keyset, width);
+                sql_function("CONCAT", vec![stem, mark])
+            }
+            _ => pseudonym,
+        }
+    }
 
-                let hash = ast::Expr::Function(ast::Function {
-                    name: ast::ObjectName(vec![bq_algo]),
-                    args: vec![concat],
-                    over: None,
-                    distinct: false,
-                });
+    fn mask_ansatz(expr: ast::Expr, masking: Masking) -> ast::Expr {
+        let length = || sql_function("LENGTH", vec![expr.clone()]);
+        let masked_length = |count: u64| {
+            sql_function(
+                "GREATEST",
+                vec![
+                    sql_binary_op(length(), ast::BinaryOperator::Minus, sql_number(count)),
+                    sql_number(0),
+                ],
+            )
+        };
+        match masking {
+            Masking::KeepFirst { count, mask } => sql_function(
+                "CONCAT",
+                vec![
+                    sql_function(
+                        "SUBSTR",
+                        vec![expr.clone(), sql_number(1), sql_number(count)],
+                    ),
+                    sql_function("REPEAT", vec![string_literal(&mask), masked_length(count)]),
+                ],
+            ),
+            Masking::KeepLast { count, mask } => {
+                // `SUBSTR` does not accept positions smaller than 1 when
+                // the value is shorter than `count`
+                let start = sql_function(
+                    "GREATEST",
+                    vec![
+                        sql_binary_op(
+                            length(),
+                            ast::BinaryOperator::Minus,
+                            sql_number(count as i64 - 1),
+                        ),
+                        sql_number(1),
+                    ],
+                );
+                sql_function(
+                    "CONCAT",
+                    vec![
+                        sql_function("REPEAT", vec![string_literal(&mask), masked_length(count)]),
+                        sql_function("SUBSTR", vec![expr.clone(), start]),
+                    ],
+                )
+            }
+            Masking::RegexReplace {
+                pattern,
+                replacement,
+            } => sql_function(
+                "REGEXP_REPLACE",
+                vec![
+                    expr.clone(),
+                    string_literal(&pattern),
+                    string_literal(&replacement),
+                ],
+            ),
+            Masking::Format(format) => sql_function(
+                "CONCAT",
+                Masking::format_pieces(&format)
+                    .into_iter()
+                    .map(|piece| match piece {
+                        Ok(pos) => sql_function(
+                            "SUBSTR",
+                            vec![expr.clone(), sql_number(pos), sql_number(1)],
+                        ),
+                        Err(lit) => string_literal(&lit),
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    /// The exponential mechanism choosing the `quantile` of the values of
+    /// `expr` among the candidates from `min` to `max` by `step`. Values are
+    /// counted once in the bin of the first candidate above them, so that the
+    /// rank of every candidate is a running sum over the bins. Gumbel noise is
+    /// added to the utility of every candidate, and the best one kept, which
+    /// samples the mechanism. The values are aggregated once, into the array
+    /// the subquery unnests, which is the only aggregate of the outer query.
+    fn noisy_quantile_ansatz(
+        expr: ast::Expr,
+        quantile: f64,
+        min: f64,
+        max: f64,
+        step: f64,
+        epsilon: f64,
+        sensitivity: f64,
+    ) -> ast::Expr {
+        let ident = |name: &str| ast::Expr::Identifier(name.to_string());
+        let aliased = |expr: ast::Expr, alias: &str| ast::SelectItem::ExprWithAlias {
+            expr,
+            alias: alias.to_string(),
+        };
+        let unnest = |array: ast::Expr, alias: &str| ast::TableFactor::Table {
+            name: ast::ObjectName(vec!["UNNEST".to_string()]),
+            alias: Some(ast::TableAlias {
+                name: alias.to_string(),
+                columns: vec![],
+            }),
+            args: vec![array],
+            with_hints: vec![],
+        };
+        let windowed = |name: &str, arg: ast::Expr, order_by: Vec<ast::OrderByExpr>| {
+            ast::Expr::Function(ast::Function {
+                name: ast::ObjectName(vec![name.to_string()]),
+                args: vec![arg],
+                over: Some(ast::WindowSpec {
+                    partition_by: vec![],
+                    order_by,
+                    window_frame: None,
+                }),
+                distinct: false,
+            })
+        };
+        let count = || sql_function("COUNT", vec![ast::Expr::Wildcard]);
+
+        // values are binned to the candidates, above the last one or to -1
+        // if NULL, so that the array has no NULL elements
+        let last = ((max - min) / step).floor() as i64;
+        let shifted = ast::Expr::Nested(Box::new(sql_binary_op(
+            expr,
+            ast::BinaryOperator::Minus,
+            sql_number(min),
+        )));
+        let bin = ast::Expr::Cast {
+            expr: Box::new(sql_function(
+                "FLOOR",
+                vec![sql_binary_op(
+                    shifted,
+                    ast::BinaryOperator::Divide,
+                    sql_number(step),
+                )],
+            )),
+            data_type: ast::DataType::Custom(ast::ObjectName(vec!["INT64".to_string()])),
+        };
+        let bin = sql_function(
+            "LEAST",
+            vec![
+                sql_function(
+                    "GREATEST",
+                    vec![
+                        sql_binary_op(bin, ast::BinaryOperator::Plus, sql_number(1)),
+                        sql_number(0),
+                    ],
+                ),
+                sql_number(last + 1),
+            ],
+        );
+        let bins = sql_function(
+            "ARRAY_AGG",
+            vec![sql_function("IFNULL", vec![bin, sql_number(-1)])],
+        );
+
+        let mut frequencies: ast::Select = RelAnsatz::from(unnest(bins, "bin")).into();
+        frequencies.projection = vec![
+            ast::SelectItem::UnnamedExpr(ident("bin")),
+            aliased(count(), "frequency"),
+            aliased(windowed("SUM", count(), vec![]), "total"),
+        ];
+        frequencies.selection = Some(sql_binary_op(
+            ident("bin"),
+            ast::BinaryOperator::GtEq,
+            sql_number(0),
+        ));
+        frequencies.group_by = vec![ident("bin")];
+        let frequencies: ast::Query = RelAnsatz::from(frequencies).into();
+
+        let mut ranks: ast::Select = RelAnsatz::from(ast::TableWithJoins {
+            relation: unnest(
+                sql_function("GENERATE_ARRAY", vec![sql_number(0), sql_number(last)]),
+                "candidate",
+            ),
+            joins: vec![ast::Join {
+                relation: ast::TableFactor::Derived {
+                    lateral: false,
+                    subquery: Box::new(frequencies),
+                    alias: None,
+                },
+                join_operator: ast::JoinOperator::LeftOuter(ast::JoinConstraint::On(
+                    sql_binary_op(ident("bin"), ast::BinaryOperator::Eq, ident("candidate")),
+                )),
+            }],
+        })
+        .into();
+        ranks.projection = vec![
+            ast::SelectItem::UnnamedExpr(ident("candidate")),
+            aliased(
+                windowed(
+                    "SUM",
+                    sql_function("IFNULL", vec![ident("frequency"), sql_number(0)]),
+                    vec![ast::OrderByExpr {
+                        expr: ident("candidate"),
+                        asc: None,
+                    }],
+                ),
+                "candidate_rank",
+            ),
+            aliased(
+                sql_function(
+                    "IFNULL",
+                    vec![windowed("MAX", ident("total"), vec![]), sql_number(0)],
+                ),
+                "total",
+            ),
+        ];
+        let ranks: ast::Query = RelAnsatz::from(ranks).into();
+
+        let utility = sql_binary_op(
+            sql_number(-epsilon / (2. * sensitivity)),
+            ast::BinaryOperator::Multiply,
+            sql_function(
+                "ABS",
+                vec![sql_binary_op(
+                    ident("candidate_rank"),
+                    ast::BinaryOperator::Minus,
+                    sql_binary_op(
+                        sql_number(quantile),
+                        ast::BinaryOperator::Multiply,
+                        ident("total"),
+                    ),
+                )],
+            ),
+        );
+        // the Gumbel noise is `-LN(-LN(u))` for `u` uniform in (0, 1), and
+        // `RAND()` may be 0, which has no logarithm
+        let uniform = sql_function(
+            "GREATEST",
+            vec![sql_function("RAND", vec![]), sql_number(f64::EPSILON)],
+        );
+        let gumbel = sql_function(
+            "LN",
+            vec![sql_binary_op(
+                sql_number(-1),
+                ast::BinaryOperator::Multiply,
+                sql_function("LN", vec![uniform]),
+            )],
+        );
 
-                let base_64_wrapper = ast::Expr::Function(ast::Function {
-                    name: ast::ObjectName(vec!["TO_BASE64".to_string()]),
-                    args: vec![hash],
-                    over: None,
+        let mut chosen: ast::Select = RelAnsatz::from(ast::TableFactor::Derived {
+            lateral: false,
+            subquery: Box::new(ranks),
+            alias: None,
+        })
+        .into();
+        chosen.projection = vec![ast::SelectItem::UnnamedExpr(sql_binary_op(
+            sql_number(min),
+            ast::BinaryOperator::Plus,
+            sql_binary_op(
+                ident("candidate"),
+                ast::BinaryOperator::Multiply,
+                sql_number(step),
+            ),
+        ))];
+        let mut chosen: ast::Query = RelAnsatz::from(chosen).into();
+        chosen.order_by = vec![ast::OrderByExpr {
+            expr: sql_binary_op(utility, ast::BinaryOperator::Minus, gumbel),
+            asc: Some(false),
+        }];
+        chosen.limit = Some(sql_number(1));
+        ast::Expr::Subquery(Box::new(chosen))
+    }
+
+    fn expr_ansatz(
+        node: Expr<ExprAnsatz>,
+        redact_keys: bool,
+    ) -> std::result::Result<ExprAnsatz, CompositionError> {
+        match node {
+            // BigQuery only has approximate quantiles, as an array to index
+            // into
+            Expr::Function(crate::opt::expr::Function {
+                name: FunctionName::Median,
+                args,
+                ..
+            }) if args.len() == 1 => {
+                let args: Vec<ast::Expr> = args.into_iter().map(|arg| arg.into()).collect();
+                Ok(ExprAnsatz::Expr(ast::Expr::Identifier(format!(
+                    "APPROX_QUANTILES({}, 2)[OFFSET(1)]",
+                    args[0]
+                ))))
+            }
+            Expr::Function(crate::opt::expr::Function {
+                name: FunctionName::Quantile,
+                args,
+                ..
+            }) if args.len() == 2 => {
+                let args: Vec<ast::Expr> = args.into_iter().map(|arg| arg.into()).collect();
+                Ok(ExprAnsatz::Expr(ast::Expr::Identifier(format!(
+                    "APPROX_QUANTILES({}, 100)[OFFSET(CAST(ROUND({} * 100) AS INT64))]",
+                    args[0], args[1]
+                ))))
+            }
+            Expr::NoisyQuantile(crate::opt::expr::NoisyQuantile {
+                expr,
+                quantile,
+                min,
+                max,
+                step,
+                epsilon,
+                sensitivity,
+            }) => Ok(ExprAnsatz::Expr(Self::noisy_quantile_ansatz(
+                expr.into(),
+                quantile,
+                min,
+                max,
+                step,
+                epsilon,
+                sensitivity,
+            ))),
+            Expr::Hash(crate::opt::expr::Hash {
+                algo,
+                expr,
+                key,
+                encoding,
+                truncate,
+                watermark,
+            }) if watermark
+                .as_ref()
+                .map_or(true, |watermark| watermark.keyset.is_some()) =>
+            {
+                Ok(ExprAnsatz::Expr(Self::hash_ansatz(
+                    expr.into(),
+                    algo,
+                    key,
+                    encoding,
+                    truncate,
+                    watermark,
+                    redact_keys,
+                )))
+            }
+            // marks cannot be made without their key
+            Expr::Hash(..) => Err(CompositionError::Unimplemented),
+            Expr::Mask(crate::opt::expr::Mask { expr, masking }) => {
+                Ok(ExprAnsatz::Expr(Self::mask_ansatz(expr.into(), masking)))
+            }
+            // patterns may have backslashes, which BigQuery takes as escapes
+            Expr::Redact(crate::opt::expr::Redact { expr, detectors }) => {
+                Ok(ExprAnsatz::Expr(detectors.into_iter().fold(
+                    sql_function(
+                        "REGEXP_REPLACE",
+                        vec![
+                            expr.into(),
+                            string_literal(Detector::MARKER_LOOKALIKE),
+                            string_literal(Detector::MARKER_LOOKALIKE_REPLACEMENT),
+                        ],
+                    ),
+                    |redacted, detector| {
+                        sql_function(
+                            "REGEXP_REPLACE",
+                            vec![
+                                redacted,
+                                string_literal(&detector.pattern),
+                                string_literal(&detector.marker()),
+                            ],
+                        )
+                    },
+                )))
+            }
+            // groups are ranked on a fingerprint of the entity along with the
+            // group, so that entities do not all keep the same groups
+            Expr::PartitionRank(crate::opt::expr::PartitionRank { entity, partition }) => {
+                let entity: ast::Expr = entity.into();
+                let mut fields = vec![entity.clone()];
+                fields.extend(partition.into_iter().map(|value| value.into()));
+                let fingerprint = sql_function(
+                    "FARM_FINGERPRINT",
+                    vec![sql_function(
+                        "TO_JSON_STRING",
+                        vec![sql_function("STRUCT", fields)],
+                    )],
+                );
+                Ok(ExprAnsatz::Expr(ast::Expr::Function(ast::Function {
+                    name: ast::ObjectName(vec!["DENSE_RANK".to_string()]),
+                    args: vec![],
+                    over: Some(ast::WindowSpec {
+                        partition_by: vec![entity],
+                        order_by: vec![ast::OrderByExpr {
+                            expr: fingerprint,
+                            asc: None,
+                        }],
+                        window_frame: None,
+                    }),
                     distinct: false,
-                });
-                Ok(ExprAnsatz::Expr(base_64_wrapper))
+                })))
+            }
+            // subqueries are not part of the expression tree, so lookups in
+            // backend tables are rendered as they are
+            Expr::InTable(crate::opt::expr::InTable {
+                expr,
+                table,
+                column,
+                filter,
+                negated,
+            }) => {
+                let expr: ast::Expr = expr.into();
+                let filter = match filter {
+                    Some((filter_column, value)) => format!(
+                        " WHERE {} = {}",
+                        quoted_identifier(&filter_column),
+                        string_literal(&value)
+                    ),
+                    None => String::new(),
+                };
+                Ok(ExprAnsatz::Expr(ast::Expr::Identifier(format!(
+                    "{} {}IN (SELECT {} FROM {}{})",
+                    expr,
+                    if negated { "NOT " } else { "" },
+                    quoted_identifier(&column),
+                    quoted_identifier(&table),
+                    filter
+                ))))
+            }
+            Expr::Tokenize(crate::opt::expr::Tokenize {
+                expr,
+                keyset,
+                context,
+            }) => {
+                let as_string = ast::Expr::Cast {
+                    expr: Box::new(expr.into()),
+                    data_type: ast::DataType::Custom(ast::ObjectName(vec!["STRING".to_string()])),
+                };
+                let keyset = if redact_keys {
+                    redacted()
+                } else {
+                    keyset_chain(&keyset)
+                };
+                let encrypted = sql_function(
+                    "DETERMINISTIC_ENCRYPT",
+                    vec![keyset, as_string, string_literal(&context)],
+                );
+                Ok(ExprAnsatz::Expr(sql_function("TO_BASE64", vec![encrypted])))
+            }
+            // BigQuery takes the date part as an identifier and only truncates
+            // dates with `DATE_TRUNC`
+            Expr::Generalize(crate::opt::expr::Generalize {
+                expr,
+                bucketing: Bucketing::Truncate(part),
+            }) => {
+                let as_date = ast::Expr::Cast {
+                    expr: Box::new(expr.into()),
+                    data_type: ast::DataType::Date,
+                };
+                Ok(ExprAnsatz::Expr(sql_function(
+                    "DATE_TRUNC",
+                    vec![as_date, ast::Expr::Identifier(part.to_string())],
+                )))
+            }
+            // placeholders only stand for literals in templates, and are
+            // never run
+            Expr::Literal(crate::opt::expr::Literal(LiteralValue::Placeholder(..))) => {
+                Err(CompositionError::Unimplemented)
             }
             _ => node.to_ansatz(),
         }
@@ -378,12 +1185,17 @@ impl ToAnsatz for BigQueryExprT {
     type Ansatz = ExprAnsatz;
 
     fn to_ansatz(self) -> std::result::Result<Self::Ansatz, CompositionError> {
-        self.root.try_fold(&mut |root| Self::expr_ansatz(root))
+        let redact_keys = self.redact_keys;
+        self.root
+            .try_fold(&mut |root| Self::expr_ansatz(root, redact_keys))
     }
 }
 impl BigQueryExprT {
     fn wrap(root: ExprT) -> Self {
-        Self { root }
+        Self {
+            root,
+            redact_keys: false,
+        }
     }
 }
 
@@ -533,6 +1345,264 @@ pub mod tests {
         }
     }
 
+    #[test]
+    fn keyed_hash_leaves_keys_out() {
+        let keyset = WrappedKeyset {
+            kms_key: "gcp-kms://projects/p/locations/us/keyRings/r/cryptoKeys/k".to_string(),
+            keyset: vec![0, 42, 255],
+        };
+        let hash = BigQueryExprT::hash_ansatz(
+            ast::Expr::Identifier("care_site_name".to_string()),
+            HashAlgorithm::SHA512,
+            Keying::Keyset {
+                keyset,
+                context: "wheel".to_string(),
+            },
+            HashEncoding::Hex,
+            Some(16),
+            None,
+            false,
+        );
+        assert_eq!(
+            hash.to_string(),
+            "SUBSTR(TO_HEX(SHA512(DETERMINISTIC_ENCRYPT(\
+             KEYS.KEYSET_CHAIN('gcp-kms://projects/p/locations/us/keyRings/r/cryptoKeys/k', \
+             b'\\x00\\x2a\\xff'), CAST(care_site_name AS STRING), 'wheel'))), 1, 16)"
+        );
+    }
+
+    #[test]
+    fn audited_hashes_redact_keys() {
+        let salted = BigQueryExprT::hash_ansatz(
+            ast::Expr::Identifier("person_id".to_string()),
+            HashAlgorithm::SHA256,
+            Keying::Salt(b"pepper".to_vec()),
+            HashEncoding::Base64,
+            None,
+            None,
+            true,
+        );
+        assert!(!salted.to_string().contains(&base64::encode(b"pepper")));
+        assert!(salted.to_string().contains("[redacted]"));
+
+        let keyed = BigQueryExprT::hash_ansatz(
+            ast::Expr::Identifier("person_id".to_string()),
+            HashAlgorithm::SHA256,
+            Keying::Keyset {
+                keyset: WrappedKeyset {
+                    kms_key: "gcp-kms://projects/p/locations/us/keyRings/r/cryptoKeys/k"
+                        .to_string(),
+                    keyset: vec![0, 42, 255],
+                },
+                context: "wheel".to_string(),
+            },
+            HashEncoding::Hex,
+            None,
+            None,
+            true,
+        );
+        assert_eq!(
+            keyed.to_string(),
+            "TO_HEX(SHA256(DETERMINISTIC_ENCRYPT([redacted], CAST(person_id AS STRING), 'wheel')))"
+        );
+    }
+
+    #[test]
+    fn traced_marks_are_embedded_marks() {
+        let keyset = WrappedKeyset {
+            kms_key: "gcp-kms://projects/p/locations/us/keyRings/r/cryptoKeys/k".to_string(),
+            keyset: vec![0, 42, 255],
+        };
+        let watermark = Watermark {
+            seed: "job_a".to_string(),
+            width: 2,
+            key: "resource.secret.watermark_key".to_string(),
+            keyset: Some(keyset.clone()),
+        };
+        let marked = |redact_keys: bool| {
+            BigQueryExprT::hash_ansatz(
+                ast::Expr::Identifier("person_id".to_string()),
+                HashAlgorithm::SHA256,
+                Keying::Salt(b"pepper".to_vec()),
+                HashEncoding::Hex,
+                Some(16),
+                Some(watermark.clone()),
+                redact_keys,
+            )
+            .to_string()
+        };
+        let mark = |stem: &str, seed: &str| {
+            format!(
+                "SUBSTR(TO_HEX(SHA256(DETERMINISTIC_ENCRYPT({}, {}, {}))), 1, 2)",
+                keyset_chain(&keyset),
+                stem,
+                seed
+            )
+        };
+
+        // marks are computed from the pseudonym without its last `width`
+        // characters, which is how traced values are split
+        let pseudonym = "SUBSTR(TO_HEX(SHA256(CONCAT('cGVwcGVy', person_id))), 1, 16)";
+        let stem = format!(
+            "SUBSTR({}, 1, GREATEST(LENGTH({}) - 2, 0))",
+            pseudonym, pseudonym
+        );
+        assert_eq!(
+            marked(false),
+            format!("CONCAT({}, {})", stem, mark(&stem, "'job_a'"))
+        );
+
+        // traces compute marks the same way
+        let query = watermark_matches_query(
+            &keyset,
+            2,
+            &["job_a".to_string()],
+            &[("0123".to_string(), "ab".to_string())],
+        );
+        assert!(query.contains(&format!("WHERE {} = mark", mark("stem", "seed"))));
+
+        // the key of the marks is left out of the audit log
+        assert!(!marked(true).contains("KEYSET_CHAIN"));
+    }
+
+    #[test]
+    fn noisy_quantile_ranks_candidates_once() {
+        let quantile = BigQueryExprT::noisy_quantile_ansatz(
+            ast::Expr::Identifier("year_of_birth".to_string()),
+            0.5,
+            1900.,
+            2020.,
+            10.,
+            1.,
+            1.,
+        );
+        assert_eq!(
+            quantile.to_string(),
+            "(SELECT 1900 + candidate * 10 \
+             FROM (SELECT candidate, \
+             SUM(IFNULL(frequency, 0)) OVER (ORDER BY candidate) AS candidate_rank, \
+             IFNULL(MAX(total) OVER (), 0) AS total \
+             FROM UNNEST(GENERATE_ARRAY(0, 12)) AS candidate \
+             LEFT JOIN (SELECT bin, COUNT(*) AS frequency, SUM(COUNT(*)) OVER () AS total \
+             FROM UNNEST(ARRAY_AGG(IFNULL(\
+             LEAST(GREATEST(CAST(FLOOR((year_of_birth - 1900) / 10) AS INT64) + 1, 0), 13), \
+             -1))) AS bin \
+             WHERE bin >= 0 \
+             GROUP BY bin) \
+             ON bin = candidate) \
+             ORDER BY -0.5 * ABS(candidate_rank - 0.5 * total) \
+             - LN(-1 * LN(GREATEST(RAND(), 0.0000000000000002220446049250313))) DESC \
+             LIMIT 1)"
+        );
+    }
+
+    #[test]
+    fn bigquery_noisy_quantiles_are_grouped() {
+        let client = mk_big_query();
+        let quantile = BigQueryExprT::noisy_quantile_ansatz(
+            ast::Expr::Identifier("age".to_string()),
+            0.5,
+            0.,
+            100.,
+            10.,
+            1.,
+            1.,
+        );
+        let query_str = format!(
+            "SELECT cohort, {} AS median \
+             FROM UNNEST([\
+             STRUCT('a' AS cohort, 31 AS age), ('a', 42), ('a', NULL), \
+             ('b', 250), ('c', NULL)]) \
+             GROUP BY cohort",
+            quantile
+        );
+        Runtime::new().unwrap().block_on(async {
+            let results = client.lite_query(&query_str).await.unwrap();
+            let rows = results.rows.unwrap_or_default();
+            assert_eq!(rows.len(), 3);
+            for row in rows.into_iter() {
+                let median: f64 = row
+                    .f
+                    .and_then(|mut cells| cells.pop()?.v)
+                    .unwrap()
+                    .parse()
+                    .unwrap();
+                assert!(median >= 0. && median <= 100.);
+            }
+        });
+    }
+
+    #[test]
+    fn lookups_are_quoted() {
+        let consented = ExprT::from(Expr::InTable(crate::opt::expr::InTable {
+            expr: ExprT::from(Expr::Column(crate::opt::expr::Column(
+                ContextKey::with_name("person_id"),
+            ))),
+            table: "project.consent`s.table".to_string(),
+            column: "person_id".to_string(),
+            filter: Some(("purpose".to_string(), "it's research".to_string())),
+            negated: false,
+        }));
+        let rendered: ast::Expr = BigQueryExprT::wrap(consented).to_ansatz().unwrap().into();
+        assert_eq!(
+            rendered.to_string(),
+            "person_id IN (SELECT `person_id` FROM `project.consent\\`s.table` \
+             WHERE `purpose` = 'it\\x27s research')"
+        );
+    }
+
+    #[test]
+    fn redactions_neutralize_lookalikes_first() {
+        let redacted = ExprT::from(Expr::Redact(crate::opt::expr::Redact {
+            expr: ExprT::from(Expr::Column(crate::opt::expr::Column(
+                ContextKey::with_name("notes"),
+            ))),
+            detectors: vec![Detector::builtin("email").unwrap()],
+        }));
+        let rendered: ast::Expr = BigQueryExprT::wrap(redacted).to_ansatz().unwrap().into();
+        assert_eq!(
+            rendered.to_string(),
+            "REGEXP_REPLACE(REGEXP_REPLACE(notes, '[[]REDACTED:', '[redacted:'), \
+             '[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+[.][A-Za-z]{2,}', '[REDACTED:EMAIL]')"
+        );
+    }
+
+    #[test]
+    fn partition_rank() {
+        let column = |name: &str| {
+            ExprT::from(Expr::Column(crate::opt::expr::Column(
+                ContextKey::with_name(name),
+            )))
+        };
+        let rank = ExprT::from(Expr::PartitionRank(crate::opt::expr::PartitionRank {
+            entity: column("person_id"),
+            partition: vec![column("race_concept_id")],
+        }));
+        let rendered: ast::Expr = BigQueryExprT::wrap(rank).to_ansatz().unwrap().into();
+        assert_eq!(
+            rendered.to_string(),
+            "DENSE_RANK() OVER (PARTITION BY person_id \
+             ORDER BY FARM_FINGERPRINT(TO_JSON_STRING(STRUCT(person_id, race_concept_id))))"
+        );
+    }
+
+    #[test]
+    fn approximate_quantiles() {
+        let column = ExprT::from(Expr::Column(crate::opt::expr::Column(
+            ContextKey::with_name("year_of_birth"),
+        )));
+        let median = ExprT::from(Expr::Function(crate::opt::expr::Function {
+            name: FunctionName::Median,
+            args: vec![column],
+            distinct: false,
+        }));
+        let rendered: ast::Expr = BigQueryExprT::wrap(median).to_ansatz().unwrap().into();
+        assert_eq!(
+            rendered.to_string(),
+            "APPROX_QUANTILES(year_of_birth, 2)[OFFSET(1)]"
+        );
+    }
+
     #[test]
     fn bigquery_meta_with_domain() {
         let client = mk_big_query();
@@ -551,4 +1621,23 @@ pub mod tests {
             );
         });
     }
+
+    #[test]
+    fn bigquery_probe_statistics() {
+        let client = mk_big_query();
+        Runtime::new().unwrap().block_on(async {
+            let test_table = ContextKey::with_name("business").and_prefix("yelp");
+            let probe = client.probe(&test_table).await.unwrap();
+            let test_key = ContextKey::with_name("review_count");
+            let distinct_count = probe.distinct_count(&test_key).await.unwrap();
+            assert!(distinct_count.0 > 0);
+            let null_fraction = probe.null_fraction(&test_key).await.unwrap();
+            assert!(null_fraction.0 >= 0. && null_fraction.0 <= 1.);
+            let histogram = probe.histogram(&test_key, &4).await.unwrap();
+            assert_eq!(histogram.0.len(), 5);
+            assert_eq!(histogram.0.first().unwrap(), "3");
+            let top_k = probe.top_k(&test_key, &3).await.unwrap();
+            assert_eq!(top_k.0.len(), 3);
+        });
+    }
 }
@@ -1,6 +1,6 @@
 use crate::common::*;
 
-use crate::backends::{LastUpdated, Probe};
+use crate::backends::{DistinctCount, Histogram, LastUpdated, NullFraction, Probe, TopK};
 use crate::gcp::bigquery::{JobBuilder, TableRef};
 use crate::opt::{
     Context, ContextError, ContextKey, DataType, Domain, ExprMeta, MaximumFrequency, Mode,
@@ -236,4 +236,98 @@ where
             .ok_or(Error::new("invalid response from backend"))
             .map(|last_modified_time| last_modified_time.into())
     }
+    async fn distinct_count(&self, key: &ContextKey) -> Result<DistinctCount> {
+        let query_str = format!(
+            "SELECT APPROX_COUNT_DISTINCT({}) FROM {}",
+            key.name(),
+            self.table_ref
+        );
+
+        let results = self.inner.lite_query(&query_str).await?;
+
+        results
+            .rows
+            .and_then(|mut rows| {
+                let mut cells = rows.pop()?.f?;
+                cells.pop()?.v?.parse::<u64>().ok()
+            })
+            .ok_or(Error::new("invalid response from BigQuery"))
+            .map(DistinctCount)
+    }
+    async fn null_fraction(&self, key: &ContextKey) -> Result<NullFraction> {
+        let query_str = format!(
+            "SELECT IFNULL(SAFE_DIVIDE(COUNTIF({} IS NULL), COUNT(*)), 0) FROM {}",
+            key.name(),
+            self.table_ref
+        );
+
+        let results = self.inner.lite_query(&query_str).await?;
+
+        results
+            .rows
+            .and_then(|mut rows| {
+                let mut cells = rows.pop()?.f?;
+                cells.pop()?.v?.parse::<f64>().ok()
+            })
+            .ok_or(Error::new("invalid response from BigQuery"))
+            .map(NullFraction)
+    }
+    async fn histogram(&self, key: &ContextKey, buckets: &u64) -> Result<Histogram> {
+        // quantiles are unnested into rows, which are easier to read back
+        let query_str = format!(
+            "SELECT CAST(boundary AS STRING) \
+             FROM UNNEST((\
+               SELECT APPROX_QUANTILES({key}, {buckets}) \
+               FROM {table_ref} \
+             )) AS boundary WITH OFFSET AS position \
+             ORDER BY position",
+            key = key.name(),
+            buckets = buckets,
+            table_ref = self.table_ref
+        );
+
+        let results = self.inner.lite_query(&query_str).await?;
+
+        results
+            .rows
+            .unwrap_or_default()
+            .into_iter()
+            .map(|row| {
+                let boundary: Option<_> = try { row.f?.pop()?.v? };
+                boundary.ok_or(Error::new("invalid response from BigQuery"))
+            })
+            .collect::<Result<_>>()
+            .map(Histogram)
+    }
+    async fn top_k(&self, key: &ContextKey, k: &u64) -> Result<TopK> {
+        let query_str = format!(
+            "SELECT CAST(top.value AS STRING), top.count \
+             FROM UNNEST((\
+               SELECT APPROX_TOP_COUNT({key}, {k}) \
+               FROM {table_ref} \
+             )) AS top \
+             ORDER BY top.count DESC",
+            key = key.name(),
+            k = k,
+            table_ref = self.table_ref
+        );
+
+        let results = self.inner.lite_query(&query_str).await?;
+
+        results
+            .rows
+            .unwrap_or_default()
+            .into_iter()
+            .map(|row| {
+                let top: Option<_> = try {
+                    let mut cells = row.f?;
+                    let count = cells.pop()?.v?.parse::<u64>().ok()?;
+                    let value = cells.pop()?.v;
+                    (value, count)
+                };
+                top.ok_or(Error::new("invalid response from BigQuery"))
+            })
+            .collect::<Result<_>>()
+            .map(TopK)
+    }
 }
@@ -3,7 +3,7 @@ use serde::{Deserialize, Serialize};
 use crate::common::*;
 use crate::node::{BlockStore, RedisBlockStore, Shared, SharedScope, SharedState};
 use crate::opt::{
-    plan::Step, Context, ContextKey, ExprMeta, MaximumFrequency, RowCount, TableMeta,
+    plan::Step, Context, ContextKey, ExprMeta, MaximumFrequency, RowCount, TableMeta, WrappedKeyset,
 };
 use crate::Result;
 
@@ -18,12 +18,41 @@ pub use bigquery::BigQuery;
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct LastUpdated(Option<u64>);
 
+impl LastUpdated {
+    /// When the backend does not know when the data was last updated
+    pub fn unknown() -> Self {
+        LastUpdated(None)
+    }
+
+    /// Whether the backend knows when the data was last updated
+    pub fn is_known(&self) -> bool {
+        self.0.is_some()
+    }
+}
+
 impl From<u64> for LastUpdated {
     fn from(val: u64) -> Self {
         LastUpdated(Some(val))
     }
 }
 
+/// The approximate number of distinct values of a column
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DistinctCount(pub u64);
+
+/// The fraction of the values of a column which are null
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NullFraction(pub f64);
+
+/// The approximate boundaries of buckets holding the same number of values of
+/// a column, from its minimum to its maximum
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Histogram(pub Vec<String>);
+
+/// The approximate most frequent values of a column, with their frequency
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TopK(pub Vec<(Option<String>, u64)>);
+
 macro_rules! make_probe {
     {
         $vis:vis trait Probe {
@@ -228,6 +257,14 @@ make_probe! {
         async fn maximum_frequency(&self, key: &'life1 ContextKey) -> Result<MaximumFrequency>;
         async fn row_count(&self) -> Result<RowCount>;
         async fn last_updated(&self) -> Result<LastUpdated>;
+        async fn distinct_count(&self, key: &'life1 ContextKey) -> Result<DistinctCount>;
+        async fn null_fraction(&self, key: &'life1 ContextKey) -> Result<NullFraction>;
+        async fn histogram(
+            &self,
+            key: &'life1 ContextKey,
+            buckets: &'life2 u64
+        ) -> Result<Histogram>;
+        async fn top_k(&self, key: &'life1 ContextKey, k: &'life2 u64) -> Result<TopK>;
     }
 }
 
@@ -235,12 +272,50 @@ make_probe! {
 pub trait Backend: Send + Sync {
     async fn compute(&self, stage: Step) -> Result<()>;
 
+    /// The query `step` is computed with, its key material redacted, as recorded in
+    /// the audit log
+    fn render(&self, step: &Step) -> Result<String>;
+
     async fn probe<'a>(&'a self, key: &'a ContextKey) -> Result<Box<dyn Probe + 'a>>;
 
     /// Retrieve the Results from the remote backend
     async fn get_records(&self, data_id: &ContextKey) -> Result<ContentStream<ArrowRecordBatch>>;
 
     async fn get_schema(&self, data_id: &ContextKey) -> Result<ArrowSchema>;
+
+    /// Whether the output `data_id` is still kept by the remote backend
+    async fn has_output(&self, data_id: &ContextKey) -> Result<bool>;
+
+    /// Reverses tokens that were produced by deterministically encrypting values
+    /// with `keyset` and `context`. Tokens that cannot be reversed map to an
+    /// empty string.
+    async fn detokenize(
+        &self,
+        keyset: &WrappedKeyset,
+        context: &str,
+        tokens: &[String],
+    ) -> Result<Vec<String>>;
+
+    /// Counts, for each job of `seeds`, how many of the `marked` stems carry
+    /// its mark, keyed with `keyset` and `width` characters wide, as embedded
+    /// in the pseudonyms of watermarked datasets. Counts are in the order of
+    /// `seeds`.
+    async fn watermark_matches(
+        &self,
+        keyset: &WrappedKeyset,
+        width: u64,
+        seeds: &[String],
+        marked: &[(String, String)],
+    ) -> Result<Vec<u64>>;
+
+    /// Counts the occurrences of each of `markers` in the string columns
+    /// `columns` of the output `data_id`
+    async fn count_markers(
+        &self,
+        data_id: &ContextKey,
+        columns: &[String],
+        markers: &[String],
+    ) -> Result<Vec<u64>>;
 }
 
 pub struct LazyBackend<C, E: ?Sized> {
@@ -291,6 +366,9 @@ where
     async fn compute(&self, step: Step) -> Result<()> {
         self.to_inner()?.compute(step).await
     }
+    fn render(&self, step: &Step) -> Result<String> {
+        self.to_inner()?.render(step)
+    }
     async fn probe<'a>(&'a self, key: &'a ContextKey) -> Result<Box<dyn Probe + 'a>> {
         let cache = SharedScope::from(RedisBlockStore::with_prefix(
             self.store.clone(),
@@ -309,6 +387,38 @@ where
     async fn get_schema(&self, ctx_key: &ContextKey) -> Result<ArrowSchema> {
         self.to_inner()?.get_schema(ctx_key).await
     }
+    async fn has_output(&self, ctx_key: &ContextKey) -> Result<bool> {
+        self.to_inner()?.has_output(ctx_key).await
+    }
+    async fn detokenize(
+        &self,
+        keyset: &WrappedKeyset,
+        context: &str,
+        tokens: &[String],
+    ) -> Result<Vec<String>> {
+        self.to_inner()?.detokenize(keyset, context, tokens).await
+    }
+    async fn watermark_matches(
+        &self,
+        keyset: &WrappedKeyset,
+        width: u64,
+        seeds: &[String],
+        marked: &[(String, String)],
+    ) -> Result<Vec<u64>> {
+        self.to_inner()?
+            .watermark_matches(keyset, width, seeds, marked)
+            .await
+    }
+    async fn count_markers(
+        &self,
+        data_id: &ContextKey,
+        columns: &[String],
+        markers: &[String],
+    ) -> Result<Vec<u64>> {
+        self.to_inner()?
+            .count_markers(data_id, columns, markers)
+            .await
+    }
 }
 
 #[cfg(test)]
@@ -292,11 +292,31 @@ where
         project_id: &str,
         job_id: &str,
     ) -> Result<GetQueryResultsResponse> {
-        let uri = format!(
+        self.get_query_results_page(project_id, job_id, None).await
+    }
+
+    /// The page of the results of the query job `job_id` at `page_token`, or
+    /// their first page
+    pub async fn get_query_results_page(
+        &self,
+        project_id: &str,
+        job_id: &str,
+        page_token: Option<&str>,
+    ) -> Result<GetQueryResultsResponse> {
+        let mut uri = format!(
             "https://bigquery.googleapis.com/bigquery/v2\
              /projects/{}/queries/{}",
             project_id, job_id
         );
+        if let Some(page_token) = page_token {
+            uri.push_str("?pageToken=");
+            uri.extend(page_token.bytes().map(|byte| match byte {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                    (byte as char).to_string()
+                }
+                _ => format!("%{:02X}", byte),
+            }));
+        }
         self.gcp
             .api_request::<(), _>(Method::GET, uri.parse()?, None)
             .await
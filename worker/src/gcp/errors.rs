@@ -5,6 +5,8 @@ pub enum GcpError {
     InvalidUri(http::uri::InvalidUri),
     SerdeError(serde_json::Error),
     ApiError(String),
+    /// The resource a GCP API call was made on does not exist
+    NotFound(String),
     InvalidResponse(String),
     JobBuilderError(String),
     AuthError(String),
@@ -20,6 +22,9 @@ impl std::fmt::Display for GcpError {
             Self::ApiError(err) => {
                 write!(f, "a GCP API call returned the following error: '{}'", err)
             }
+            Self::NotFound(err) => {
+                write!(f, "a GCP API call did not find its resource: '{}'", err)
+            }
             Self::InvalidResponse(resp) => {
                 write!(f, "a GCP API call returned an invalid response: '{}'", resp)
             }
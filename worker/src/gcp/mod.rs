@@ -142,7 +142,11 @@ where
             // replace with correct errorproto
             let resp_str =
                 String::from_utf8(resp_body).unwrap_or("[invalid response body]".to_string());
-            Err(GcpError::ApiError(resp_str))
+            if status == StatusCode::NOT_FOUND {
+                Err(GcpError::NotFound(resp_str))
+            } else {
+                Err(GcpError::ApiError(resp_str))
+            }
         }
     }
 }
@@ -1,17 +1,23 @@
+use crate::backends::LastUpdated;
 use crate::common::*;
-use crate::node::{Access, Node};
+use crate::node::{Access, Node, Release};
 use crate::Result;
 
+use crate::opt::fingerprint::hex_digest;
 use crate::opt::plan::{PhysicalPlan, PhysicalPlanner, Step};
-use crate::opt::transform;
+use crate::opt::transform::{self, redacted_columns, tables_below, Redaction};
 use crate::opt::validate::Validator;
 use crate::opt::{
-    Context, ContextKey, RelT, RelTransformer, TableMeta, Transformed, ValidateError,
+    Context, ContextKey, Detector, DifferencingAction, Fingerprint, PolicyBinding, RelT,
+    RelTransformer, TableMeta, Transformed, ValidateError,
 };
 
 pub(crate) mod processor;
 pub(crate) use processor::Processor;
 
+pub(crate) mod post;
+use post::PostProcessor;
+
 use crate::common::{
     Block, BlockType, Job as ApiJob, JobState as ApiJobState, JobStatus as ApiJobStatus,
     TokenStream,
@@ -40,6 +46,14 @@ fn closures_match_or_err(left: &Sha256Bytes, right: &Sha256Bytes) -> Result<()>
     }
 }
 
+/// Whether `purpose` may be declared by a job. Purposes are matched against
+/// consent tables in the backend, so they are kept to plain words.
+fn is_valid_purpose(purpose: &str) -> bool {
+    purpose
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == ' ' || c == '-' || c == '_')
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Asset {
     source: ContextKey,
@@ -49,10 +63,39 @@ pub struct Asset {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct QueryInitialisedStage {
     query: String,
+    purpose: Option<String>,
+}
+
+/// The audit log entry of a query refused before it could be authorized
+fn refused<E: std::fmt::Display>(
+    job_id: &str,
+    query: &str,
+    purpose: Option<&str>,
+    err: &E,
+) -> AuditDecision {
+    AuditDecision::Query(QueryDecision {
+        job_id: job_id.to_string(),
+        query: query.to_string(),
+        purpose: purpose.unwrap_or_default().to_string(),
+        outcome: "refused".to_string(),
+        reason: err.to_string(),
+        ..Default::default()
+    })
 }
 
 impl QueryInitialisedStage {
-    async fn validate<A: Access>(self, access: &A) -> Result<QueryValidatedStage> {
+    /// Validates the query, recording it in the audit log if it is refused
+    async fn validate<A: Access>(self, access: &A, job_id: &str) -> Result<QueryValidatedStage> {
+        let query = self.query.clone();
+        let purpose = self.purpose.clone();
+        let validated = self.check(access).await;
+        if let Err(err) = validated.as_ref() {
+            access.audit(refused(job_id, &query, purpose.as_deref(), err))?;
+        }
+        validated
+    }
+
+    async fn check<A: Access>(self, access: &A) -> Result<QueryValidatedStage> {
         debug!("initialised -> validated");
 
         let ctx = access.context().await?;
@@ -67,7 +110,12 @@ impl QueryInitialisedStage {
         match validated.board.as_ref() {
             Ok(_) => {
                 debug!("validated {:?} successfully", validated);
-                Ok(QueryValidatedStage { closure, validated })
+                Ok(QueryValidatedStage {
+                    closure,
+                    validated,
+                    purpose: self.purpose,
+                    query: self.query,
+                })
             }
             Err(err) => {
                 debug!("validation failed {}", err);
@@ -81,10 +129,43 @@ impl QueryInitialisedStage {
 pub struct QueryValidatedStage {
     closure: Sha256Bytes,
     validated: RelT,
+    purpose: Option<String>,
+    /// The query, as submitted
+    query: String,
 }
 
 impl QueryValidatedStage {
-    async fn optimize<A: Access>(self, access: &A) -> Result<QueryOptimizedStage> {
+    /// Authorizes the query, recording the decision in the audit log whatever
+    /// it is
+    async fn optimize<A: Access>(self, access: &A, job_id: &str) -> Result<QueryOptimizedStage> {
+        let mut decision = QueryDecision {
+            job_id: job_id.to_string(),
+            query: self.query.clone(),
+            purpose: self.purpose.clone().unwrap_or_default(),
+            ..Default::default()
+        };
+
+        let optimized = self.authorize(access, &mut decision).await;
+        match optimized.as_ref() {
+            Ok(optimized) if optimized.released().is_some() => {
+                decision.outcome = "released".to_string();
+            }
+            Ok(_) => decision.outcome = "authorized".to_string(),
+            Err(err) => {
+                decision.outcome = "refused".to_string();
+                decision.reason = err.to_string();
+            }
+        }
+        access.audit(AuditDecision::Query(decision))?;
+
+        optimized
+    }
+
+    async fn authorize<A: Access>(
+        self,
+        access: &A,
+        decision: &mut QueryDecision,
+    ) -> Result<QueryOptimizedStage> {
         debug!("validate -> optimize");
 
         let ctx = access.context().await?;
@@ -93,36 +174,203 @@ impl QueryValidatedStage {
         closures_match_or_err(&self.closure, &closure)?;
 
         let default_group = access.default_group();
-        let policies = access.policies_for_group(default_group)?;
+        let policies = access.policies_for_group(default_group, self.purpose.as_deref())?;
         let policies_closure = policies.sha256();
 
+        let fingerprint = Fingerprint::of(&self.validated).map_err(|e| e.into_error())?;
+        for (key, binding) in policies.iter() {
+            let dataset = key.prefix().next().unwrap_or_default();
+            if fingerprint.datasets.iter().any(|read| read == dataset) {
+                decision.bindings.push(key.to_string());
+                decision.policies.extend(binding.references.iter().cloned());
+            }
+        }
+        decision.bindings.sort();
+        decision.policies.sort();
+        decision.policies.dedup();
+
+        // queries differing from a previous one by only a few conditions may
+        // together isolate individuals
+        let mut noisier = HashMap::new();
+        for (dataset, detection, difference) in access.detect_differencing(&fingerprint)? {
+            debug!(
+                "query differs by {} conditions from a previous query on {}",
+                difference, dataset
+            );
+            let action = detection
+                .action
+                .parse::<DifferencingAction>()
+                .map_err(|e| e.into_error())?;
+            access.audit(AuditDecision::Differencing(DifferencingDecision {
+                dataset: dataset.clone(),
+                difference: difference as u64,
+                action: action.to_string(),
+            }))?;
+            match action {
+                DifferencingAction::Refuse => {
+                    return Err(differencing_error(access, &dataset, difference))
+                }
+                DifferencingAction::Noise => {
+                    if detection.noise_multiplier <= 1. {
+                        let err = ScopeError {
+                            kind: ScopeErrorKind::BadObject as i32,
+                            source: dataset,
+                            description: "the noise multiplier of differencing detection \
+                                          needs to be greater than 1"
+                                .to_string(),
+                        };
+                        return Err(err.into());
+                    }
+                    noisier.insert(dataset, (detection.noise_multiplier, difference));
+                }
+                DifferencingAction::Flag => {}
+            }
+        }
+        let job_id = decision.job_id.clone();
+        let policies: Context<PolicyBinding> = policies
+            .into_iter()
+            .map(|(key, mut binding)| {
+                let dataset = key.prefix().next().unwrap_or_default();
+                for policy in binding.policies.iter_mut() {
+                    if let Some((noise_multiplier, _)) = noisier.get(dataset) {
+                        policy.scale_noise(*noise_multiplier);
+                    }
+                    // pseudonyms are marked by the job they are released to
+                    policy.seed_watermark(&job_id);
+                }
+                (key, binding)
+            })
+            .collect();
+
         let audience = block_type!("resource"."group".default_group);
 
         debug!(
             "optimizing (closure: {}, policies: {})",
             closure, policies_closure
         );
-        let transformer = RelTransformer::new(&policies, &audience, access);
-        let optimized = transformer
-            .transform_rel(&self.validated)
-            .await
-            .or_else(|err| match err {
-                transform::Error::NoMatch => Ok(Transformed::default(self.validated)),
-                transform::Error::Validate(err) => Err(err),
-            })
+        let exclusions = access.exclusions()?;
+        let exclusion_versions = exclusions
+            .iter()
+            .map(|exclusion| exclusion.list.clone())
+            .collect();
+        let consents = access.consents()?;
+        let templates = access.templates(default_group, self.purpose.as_deref())?;
+
+        let transformer = RelTransformer::new(&policies, &audience, access)
+            .with_exclusions(&exclusions)
+            .with_consents(&consents, self.purpose.as_deref())
+            .with_templates(&templates);
+        let as_validate_error = |err| match err {
+            transform::Error::NoMatch => {
+                ValidateError::Internal("filtering the rows of the query did not match".to_string())
+            }
+            transform::Error::Validate(err) => err,
+        };
+        // queries matching a template are authorized as they are
+        let templated = transformer
+            .authorize_templates(&self.validated, &ctx)
+            .map_err(as_validate_error)
+            .map_err(|e| e.into_error())?;
+        let filtered = transformer
+            .filter_rows(&self.validated)
+            .map_err(as_validate_error)
             .map_err(|e| e.into_error())?;
+        let optimized = if templated {
+            let mut root = filtered;
+            root.board
+                .as_mut()
+                .map(|board| {
+                    board.audience.insert(audience.clone());
+                })
+                .map_err(|e| e.clone().into_error())?;
+            Transformed::default(root)
+        } else {
+            transformer
+                .transform_rel(&filtered)
+                .await
+                .or_else(|err| match err {
+                    transform::Error::NoMatch => Ok(Transformed::default(filtered.clone())),
+                    transform::Error::Validate(err) => Err(err),
+                })
+                .map_err(|e| e.into_error())?
+        };
 
         match optimized.root.board.as_ref() {
             Ok(board) => {
                 debug!("optimization lead to a valid tree");
                 if board.audience.contains(&audience) {
+                    // suspicious queries with no noise to add are refused
+                    for (dataset, (_, difference)) in noisier.iter() {
+                        let noised = optimized.cost.iter().any(|(key, cost)| {
+                            key.prefix().next() == Some(dataset.as_str()) && cost.epsilon > 0.
+                        });
+                        if !noised {
+                            return Err(differencing_error(access, dataset, *difference));
+                        }
+                    }
+
                     debug!("found a compliant tree, authorizing");
-                    access.expend_to_budget(optimized.cost)?;
+
+                    // the worker redacts again the columns redacted text ends
+                    // up in, which need to be known for sure
+                    let redactions =
+                        redacted_columns(&optimized.root).map_err(|e| e.into_error())?;
+
+                    // the result of a differentially private query is released
+                    // again while the data it was computed on is unchanged
+                    let mut release = None;
+                    if optimized.cost.values().any(|cost| cost.epsilon > 0.) {
+                        let key = hex_digest(&(&optimized.root, &closure, &policies_closure))
+                            .map_err(|e| e.into_error())?;
+                        let last_updated = last_updated(access, &ctx, &optimized.root).await?;
+                        release = Some(match access.released(&key, &last_updated)? {
+                            Some(released) if is_kept(access, &released).await? => released,
+                            _ => Release {
+                                key,
+                                last_updated,
+                                asset: None,
+                            },
+                        });
+                    }
+
+                    decision.audience = board
+                        .audience
+                        .iter()
+                        .map(|audience| audience.to_string())
+                        .collect();
+                    decision.audience.sort();
+                    decision.steps = rendered_steps(access, &ctx, &optimized.root)?;
+
+                    if release
+                        .as_ref()
+                        .and_then(|release| release.asset.as_ref())
+                        .is_some()
+                    {
+                        debug!("the same query was run on the same data, releasing its result");
+                    } else {
+                        decision.costs = optimized
+                            .cost
+                            .iter()
+                            .map(|(binding, cost)| QueryCost {
+                                binding: binding.to_string(),
+                                epsilon: cost.epsilon,
+                                delta: cost.delta,
+                            })
+                            .collect();
+                        decision
+                            .costs
+                            .sort_by(|left, right| left.binding.cmp(&right.binding));
+                        access.expend_to_budget(optimized.cost)?;
+                    }
+                    access.remember_fingerprint(&fingerprint)?;
 
                     Ok(QueryOptimizedStage {
                         closure,
                         policies_closure,
                         optimized: optimized.root,
+                        exclusions: exclusion_versions,
+                        release,
+                        redactions,
                     })
                 } else {
                     debug!("could not find a compliant tree");
@@ -143,14 +391,110 @@ impl QueryValidatedStage {
     }
 }
 
+/// The queries sent to backends to compute `rel_t`
+fn rendered_steps<A: Access>(
+    access: &A,
+    ctx: &Context<TableMeta>,
+    rel_t: &RelT,
+) -> Result<Vec<String>> {
+    let plan = PhysicalPlanner::new(ctx, rel_t.clone())
+        .into_plan()
+        .map_err(|e| e.into_error())?;
+    plan.steps
+        .iter()
+        .map(|step| {
+            let board = step
+                .rel_t
+                .board
+                .as_ref()
+                .map_err(|e| e.clone().into_error())?;
+            let loc = board
+                .loc
+                .as_ref()
+                .ok_or(ValidateError::Insufficient("location".to_string()).into_error())?;
+            access.backend(loc)?.render(step)
+        })
+        .collect()
+}
+
+/// When each of the tables `rel_t` reads from was last updated
+async fn last_updated<A: Access>(
+    access: &A,
+    ctx: &Context<TableMeta>,
+    rel_t: &RelT,
+) -> Result<HashMap<String, LastUpdated>> {
+    let mut tables = Vec::new();
+    tables_below(rel_t, &mut tables);
+
+    let mut last_updated = HashMap::new();
+    for context_key in tables {
+        let table_meta = ctx.get_table(context_key).map_err(|e| e.into_error())?;
+        let (loc, source) = match (table_meta.loc.as_ref(), table_meta.source.as_ref()) {
+            (Some(loc), Some(source)) => (loc, source),
+            _ => {
+                let desc = format!("table {} is missing a backend or a source", context_key);
+                return Err(ValidateError::Insufficient(desc).into_error().into());
+            }
+        };
+        let updated = access
+            .backend(loc)?
+            .probe(source)
+            .await?
+            .last_updated()
+            .await?;
+        last_updated.insert(context_key.to_string(), updated);
+    }
+    Ok(last_updated)
+}
+
+/// Whether the result of `release` is still kept in the staging area of its
+/// backend, for it to be released again
+async fn is_kept<A: Access>(access: &A, release: &Release) -> Result<bool> {
+    match release.asset.as_ref() {
+        Some(asset) => access.backend(&asset.loc)?.has_output(&asset.source).await,
+        None => Ok(false),
+    }
+}
+
+fn differencing_error<A: Access>(access: &A, dataset: &str, difference: usize) -> Error {
+    let err = AccessError {
+        kind: AccessErrorKind::Forbidden as i32,
+        culprit: access.who_am_i().to_string(),
+        description: format!(
+            "query differs from a previous query on `{}` by only {} conditions",
+            dataset, difference
+        ),
+        ..Default::default()
+    };
+    err.into()
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct QueryOptimizedStage {
     closure: Sha256Bytes,
     policies_closure: Sha256Bytes,
     optimized: RelT,
+    /// The versions of the exclusion lists the query was filtered with
+    exclusions: HashMap<String, u64>,
+    /// The release of the result of the query, if it is differentially private
+    release: Option<Release>,
+    /// The output columns redacted text ends up in
+    redactions: Vec<Redaction>,
 }
 
 impl QueryOptimizedStage {
+    /// The result released for the same query on the same data, if any
+    fn released(&self) -> Option<QueryDoneStage> {
+        let asset = self.release.as_ref()?.asset.clone()?;
+        Some(QueryDoneStage {
+            asset,
+            exclusions: self.exclusions.clone(),
+            // only counted when the query is executed
+            detections: HashMap::new(),
+            post_processing: post::post_processing(&self.redactions),
+        })
+    }
+
     async fn plan<A: Access>(self, access: &A) -> Result<QueryPlannedStage> {
         let ctx = access.context().await?;
         let closure = ctx.sha256();
@@ -161,7 +505,13 @@ impl QueryOptimizedStage {
             .into_plan()
             .map_err(|e| e.into_error())?;
 
-        Ok(QueryPlannedStage { closure, plan })
+        Ok(QueryPlannedStage {
+            closure,
+            plan,
+            exclusions: self.exclusions,
+            release: self.release,
+            redactions: self.redactions,
+        })
     }
 }
 
@@ -169,6 +519,10 @@ impl QueryOptimizedStage {
 pub struct QueryPlannedStage {
     pub closure: Sha256Bytes,
     pub plan: PhysicalPlan,
+    pub exclusions: HashMap<String, u64>,
+    pub release: Option<Release>,
+    /// The output columns redacted text ends up in
+    pub redactions: Vec<Redaction>,
 }
 
 impl QueryPlannedStage {
@@ -222,16 +576,63 @@ impl QueryPlannedStage {
         }
 
         if let Some(asset) = current_asset {
-            Ok(QueryDoneStage { asset })
+            if let Some(mut release) = self.release {
+                release.asset = Some(asset.clone());
+                access.remember_release(release)?;
+            }
+            let detections = count_detections(access, &asset, &self.redactions).await?;
+            Ok(QueryDoneStage {
+                asset,
+                exclusions: self.exclusions,
+                detections,
+                post_processing: post::post_processing(&self.redactions),
+            })
         } else {
             Err(Error::new("job had no output"))
         }
     }
 }
 
+/// The number of detections of the detectors of `redactions` redacted in
+/// `asset`, by name
+async fn count_detections<A: Access>(
+    access: &A,
+    asset: &Asset,
+    redactions: &[Redaction],
+) -> Result<HashMap<String, u64>> {
+    let mut detectors: Vec<Detector> = redactions
+        .iter()
+        .flat_map(|(_, detectors)| detectors.iter().cloned())
+        .collect();
+    detectors.sort_by(|left, right| left.name.cmp(&right.name));
+    detectors.dedup_by(|left, right| left.name == right.name);
+    if detectors.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let columns: Vec<_> = redactions
+        .iter()
+        .map(|(column, _)| column.clone())
+        .collect();
+    let markers: Vec<_> = detectors.iter().map(Detector::marker).collect();
+    let counts = access
+        .backend(&asset.loc)?
+        .count_markers(&asset.source, &columns, &markers)
+        .await?;
+    Ok(detectors
+        .into_iter()
+        .map(|detector| detector.name)
+        .zip(counts)
+        .collect())
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct QueryDoneStage {
     asset: Asset,
+    exclusions: HashMap<String, u64>,
+    /// The number of detections redacted in the asset, by detector
+    detections: HashMap<String, u64>,
+    /// What the rows of the asset go through before they are sent back
+    post_processing: Vec<PostProcessor>,
 }
 
 /// The stages of a 'query' job
@@ -253,11 +654,14 @@ impl QueryStages {
         }
     }
 
-    async fn advance<A: Access>(self, access: &A) -> Result<Self> {
+    async fn advance<A: Access>(self, access: &A, job_id: &str) -> Result<Self> {
         let next = match self {
-            Self::Initialised(init) => Self::Validated(init.validate(access).await?),
-            Self::Validated(valid) => Self::Optimized(valid.optimize(access).await?),
-            Self::Optimized(optim) => Self::Planned(optim.plan(access).await?),
+            Self::Initialised(init) => Self::Validated(init.validate(access, job_id).await?),
+            Self::Validated(valid) => Self::Optimized(valid.optimize(access, job_id).await?),
+            Self::Optimized(optim) => match optim.released() {
+                Some(done) => Self::Done(done),
+                None => Self::Planned(optim.plan(access).await?),
+            },
             Self::Planned(planned) => Self::Done(planned.execute(access).await?),
             Self::Done(done) => Self::Done(done),
         };
@@ -270,6 +674,22 @@ impl QueryStages {
             _ => false,
         }
     }
+
+    fn exclusions(&self) -> Option<&HashMap<String, u64>> {
+        match self {
+            Self::Optimized(QueryOptimizedStage { exclusions, .. })
+            | Self::Planned(QueryPlannedStage { exclusions, .. })
+            | Self::Done(QueryDoneStage { exclusions, .. }) => Some(exclusions),
+            _ => None,
+        }
+    }
+
+    fn detections(&self) -> Option<&HashMap<String, u64>> {
+        match self {
+            Self::Done(QueryDoneStage { detections, .. }) => Some(detections),
+            _ => None,
+        }
+    }
 }
 
 /// FSM for job processing
@@ -285,9 +705,9 @@ impl JobStage {
         }
     }
 
-    pub async fn advance<A: Access>(self, access: &A) -> Result<Self> {
+    pub async fn advance<A: Access>(self, access: &A, job_id: &str) -> Result<Self> {
         match self {
-            Self::Query(query) => Ok(Self::Query(query.advance(access).await?)),
+            Self::Query(query) => Ok(Self::Query(query.advance(access, job_id).await?)),
         }
     }
 
@@ -296,6 +716,18 @@ impl JobStage {
             Self::Query(query) => query.is_done(),
         }
     }
+
+    fn exclusions(&self) -> Option<&HashMap<String, u64>> {
+        match self {
+            Self::Query(query) => query.exclusions(),
+        }
+    }
+
+    fn detections(&self) -> Option<&HashMap<String, u64>> {
+        match self {
+            Self::Query(query) => query.detections(),
+        }
+    }
 }
 
 pub type JobState = Result<JobStage>;
@@ -316,6 +748,8 @@ pub struct Job {
     pub(self) state: JobState,
     /// The user on behalf of which this job has been inserted
     pub(self) user: String,
+    /// The purpose the job was declared for, if any
+    pub(self) purpose: Option<String>,
     /// The foreman of this job
     pub(self) foreman: Option<Foreman>,
 }
@@ -350,6 +784,21 @@ impl Job {
             query: String::new(), // FIXME
             status: Some(status),
             timestamp: self.timestamp.to_string(),
+            exclusions: self
+                .state
+                .as_ref()
+                .ok()
+                .and_then(|stage| stage.exclusions())
+                .cloned()
+                .unwrap_or_default(),
+            purpose: self.purpose.clone().unwrap_or_default(),
+            detections: self
+                .state
+                .as_ref()
+                .ok()
+                .and_then(|stage| stage.detections())
+                .cloned()
+                .unwrap_or_default(),
         }
     }
 
@@ -397,3 +846,243 @@ impl Job {
             .unwrap_or(true)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::node::access::unit_tester_access;
+    use crate::node::tests::{mk_manifest_node, mk_node_with, mk_random_node};
+
+    use tokio::runtime::Runtime;
+
+    /// A node with the test manifest, whose datasets detect queries
+    /// differing from a previous one by a single condition
+    fn mk_detecting_node(action: &str, noise_multiplier: f64) -> Arc<Node> {
+        mk_node_with(|resource| {
+            if let Some(ResourceEnum::Dataset(dataset)) = resource.resource.as_mut() {
+                dataset.differencing = Some(DifferencingDetection {
+                    max_difference: 1,
+                    action: action.to_string(),
+                    noise_multiplier,
+                    ..Default::default()
+                });
+            }
+        })
+    }
+
+    /// Validate and authorize `query` as `unit-tester`
+    fn optimize(access: &Arc<Node>, query: &str) -> Result<QueryOptimizedStage> {
+        let access = unit_tester_access(access.clone());
+        let initialised = QueryInitialisedStage {
+            query: query.to_string(),
+            purpose: None,
+        };
+        let job_id = Uuid::new_v4().to_simple().to_string();
+        Runtime::new().unwrap().block_on(async {
+            initialised
+                .validate(&access, &job_id)
+                .await?
+                .optimize(&access, &job_id)
+                .await
+        })
+    }
+
+    fn differencing_decisions(access: &Arc<Node>) -> Vec<DifferencingDecision> {
+        access
+            .audit_entries(0)
+            .unwrap()
+            .into_iter()
+            .filter_map(|entry| match entry.decision {
+                Some(AuditDecision::Differencing(decision)) => Some(decision),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn query_decisions(access: &Arc<Node>) -> Vec<QueryDecision> {
+        access
+            .audit_entries(0)
+            .unwrap()
+            .into_iter()
+            .filter_map(|entry| match entry.decision {
+                Some(AuditDecision::Query(decision)) => Some(decision),
+                _ => None,
+            })
+            .collect()
+    }
+
+    const BY_ID: &str = "\
+        SELECT vocabulary_name FROM patient_data.vocabulary \
+        WHERE vocabulary_id = 'ICD9CM'";
+
+    const BY_ID_AND_REFERENCE: &str = "\
+        SELECT vocabulary_name FROM patient_data.vocabulary \
+        WHERE vocabulary_id = 'ICD9CM' AND vocabulary_reference = 'CMS'";
+
+    #[test]
+    fn invalid_queries_are_audited() {
+        let access = mk_detecting_node("refuse", 0.);
+        let query = "SELECT no_such_column FROM patient_data.vocabulary";
+        assert!(optimize(&access, query).is_err());
+        let decision = query_decisions(&access).pop().unwrap();
+        assert_eq!(decision.query, query);
+        assert_eq!(decision.outcome, "refused");
+        assert!(!decision.reason.is_empty());
+    }
+
+    #[test]
+    fn differencing_queries_are_refused() {
+        let access = mk_detecting_node("refuse", 0.);
+        optimize(&access, BY_ID).unwrap();
+        assert!(differencing_decisions(&access).is_empty());
+
+        // the history is compared with even if the dataset leaves it unset
+        assert!(optimize(&access, BY_ID_AND_REFERENCE).is_err());
+        let decisions = differencing_decisions(&access);
+        assert_eq!(decisions.len(), 1);
+        assert_eq!(decisions[0].dataset, "patient_data");
+        assert_eq!(decisions[0].difference, 1);
+        assert_eq!(decisions[0].action, "refuse");
+        assert_eq!(query_decisions(&access).pop().unwrap().outcome, "refused");
+
+        // identical queries are not suspicious
+        optimize(&access, BY_ID).unwrap();
+        assert_eq!(differencing_decisions(&access).len(), 1);
+    }
+
+    #[test]
+    fn differencing_queries_are_flagged() {
+        let access = mk_detecting_node("flag", 0.);
+        optimize(&access, BY_ID).unwrap();
+        optimize(&access, BY_ID_AND_REFERENCE).unwrap();
+        let decisions = differencing_decisions(&access);
+        assert_eq!(decisions.len(), 1);
+        assert_eq!(decisions[0].action, "flag");
+        assert_eq!(
+            query_decisions(&access).pop().unwrap().outcome,
+            "authorized"
+        );
+    }
+
+    #[test]
+    fn differencing_queries_are_noised() {
+        let access = mk_detecting_node("noise", 2.);
+        let spent =
+            |decision: &QueryDecision| decision.costs.iter().map(|cost| cost.epsilon).sum::<f64>();
+
+        optimize(
+            &access,
+            "\
+            SELECT gender_concept_id, COUNT(person_id) \
+            FROM patient_data.person \
+            GROUP BY gender_concept_id",
+        )
+        .unwrap();
+        optimize(
+            &access,
+            "\
+            SELECT gender_concept_id, COUNT(person_id) \
+            FROM patient_data.person \
+            WHERE year_of_birth > 1950 \
+            GROUP BY gender_concept_id",
+        )
+        .unwrap();
+        assert_eq!(differencing_decisions(&access)[0].action, "noise");
+        // the noise is scaled up by the multiplier, spending less epsilon
+        let decisions = query_decisions(&access);
+        assert!(spent(&decisions[0]) > 0.);
+        assert_eq!(spent(&decisions[1]), spent(&decisions[0]) / 2.);
+
+        // queries with no noise to add are refused instead
+        optimize(&access, BY_ID).unwrap();
+        assert!(optimize(&access, BY_ID_AND_REFERENCE).is_err());
+    }
+
+    fn updated_at(last_updated: LastUpdated) -> HashMap<String, LastUpdated> {
+        vec![("patient_data.person".to_string(), last_updated)]
+            .into_iter()
+            .collect()
+    }
+
+    #[test]
+    fn releases_need_unchanged_data() {
+        let access = Arc::new(mk_random_node());
+        let asset = Asset {
+            source: ContextKey::with_name("released"),
+            loc: block_type!("resource"."backend"."big_query"."synthetic_patient_data"),
+        };
+        access
+            .remember_release(Release {
+                key: "known".to_string(),
+                last_updated: updated_at(1.into()),
+                asset: Some(asset.clone()),
+            })
+            .unwrap();
+        assert!(access
+            .released("known", &updated_at(1.into()))
+            .unwrap()
+            .is_some());
+        // the data was updated since the result was released
+        assert!(access
+            .released("known", &updated_at(2.into()))
+            .unwrap()
+            .is_none());
+
+        // or the backend does not know whether it was
+        access
+            .remember_release(Release {
+                key: "unknown".to_string(),
+                last_updated: updated_at(LastUpdated::unknown()),
+                asset: Some(asset),
+            })
+            .unwrap();
+        assert!(access
+            .released("unknown", &updated_at(LastUpdated::unknown()))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn released_results_cost_nothing() {
+        let access = mk_manifest_node();
+        let query = "\
+            SELECT gender_concept_id, COUNT(person_id) \
+            FROM patient_data.person \
+            GROUP BY gender_concept_id";
+        let spent = || {
+            access
+                .resources(&block_type!("resource"."privacy_unit"."patients"))
+                .unwrap()
+                .pop()
+                .unwrap()
+                .try_downcast::<PrivacyUnit>()
+                .unwrap()
+                .budget
+                .unwrap()
+                .used
+        };
+
+        let account = unit_tester_access(access.clone());
+        let job_id = Uuid::new_v4().to_simple().to_string();
+        let mut stage = QueryStages::Initialised(QueryInitialisedStage {
+            query: query.to_string(),
+            purpose: None,
+        });
+        Runtime::new().unwrap().block_on(async {
+            while !stage.is_done() {
+                stage = stage.advance(&account, &job_id).await.unwrap();
+            }
+        });
+        let first = spent();
+        assert!(first > 0.);
+
+        // the same query on the same data is answered with the staged result
+        let optimized = optimize(&access, query).unwrap();
+        assert!(optimized.released().is_some());
+        let decision = query_decisions(&access).pop().unwrap();
+        assert_eq!(decision.outcome, "released");
+        assert!(decision.costs.is_empty());
+        assert_eq!(spent(), first);
+    }
+}
@@ -0,0 +1,343 @@
+//! Post-processing of the output of jobs by the worker, for transformations
+//! which backends cannot express safely.
+use std::io::Cursor;
+
+use arrow::array::{Array, ArrayRef, StringArray, StringBuilder};
+use arrow::datatypes::{DataType, Schema};
+use arrow::error::ArrowError;
+use arrow::ipc::reader::StreamReader;
+use arrow::ipc::writer::StreamWriter;
+use arrow::record_batch::RecordBatch;
+
+use crate::common::*;
+use crate::opt::transform::Redaction;
+use crate::opt::Detector;
+use crate::Result;
+
+/// The marker BigQuery Storage precedes Arrow messages with, which the Arrow
+/// reader does not expect
+const CONTINUATION: [u8; 4] = [255; 4];
+
+/// The length of the end-of-stream marker the Arrow writer ends streams with
+const END_OF_STREAM_LEN: usize = 4;
+
+fn arrow_error(err: ArrowError) -> Error {
+    Error::new(format!(
+        "could not post-process a batch of output rows: {}",
+        err
+    ))
+}
+
+/// A transformation of the output of a job, applied by the worker to each
+/// batch of rows before they are sent back. Post-processors may change values
+/// and drop rows, but not change the schema of the batches.
+pub trait PostProcess {
+    fn process(&self, batch: RecordBatch) -> Result<RecordBatch>;
+}
+
+/// The post-processors of a job, ready to be applied to its batches
+pub type Pipeline = Vec<Box<dyn PostProcess + Send + Sync>>;
+
+/// Replaces the detections of `detectors` in the string column `column`, if
+/// the output has one. This redacts the column again with the regular
+/// expressions of the worker, whatever the dialect of those of the backend.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RedactColumn {
+    pub column: String,
+    pub detectors: Vec<Detector>,
+}
+
+impl RedactColumn {
+    /// Compiles the regular expressions of the detectors, once for all the
+    /// batches of the output
+    pub fn compile(&self) -> Result<CompiledRedactColumn> {
+        let lookalike = regex::Regex::new(Detector::MARKER_LOOKALIKE)
+            .map_err(|err| Error::new(format!("invalid pattern for marker lookalikes: {}", err)))?;
+        let detectors = self
+            .detectors
+            .iter()
+            .map(|detector| {
+                let regex = regex::Regex::new(&detector.pattern).map_err(|err| {
+                    Error::new(format!("invalid pattern for `{}`: {}", detector.name, err))
+                })?;
+                Ok((regex, detector.marker()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(CompiledRedactColumn {
+            column: self.column.clone(),
+            lookalike,
+            detectors,
+        })
+    }
+}
+
+/// A [`RedactColumn`] with its regular expressions compiled
+pub struct CompiledRedactColumn {
+    column: String,
+    lookalike: regex::Regex,
+    detectors: Vec<(regex::Regex, String)>,
+}
+
+impl PostProcess for CompiledRedactColumn {
+    fn process(&self, batch: RecordBatch) -> Result<RecordBatch> {
+        let idx = match batch.schema().index_of(&self.column) {
+            Ok(idx) if batch.column(idx).data_type() == &DataType::Utf8 => idx,
+            _ => return Ok(batch),
+        };
+
+        let strings = batch
+            .column(idx)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .ok_or(Error::new("expected a string column"))?;
+        let mut builder = StringBuilder::new(strings.len());
+        for row in 0..strings.len() {
+            if strings.is_null(row) {
+                builder.append_null().map_err(arrow_error)?;
+            } else {
+                let mut value = self
+                    .lookalike
+                    .replace_all(
+                        strings.value(row),
+                        regex::NoExpand(Detector::MARKER_LOOKALIKE_REPLACEMENT),
+                    )
+                    .into_owned();
+                for (regex, marker) in self.detectors.iter() {
+                    value = regex
+                        .replace_all(&value, regex::NoExpand(marker))
+                        .into_owned();
+                }
+                builder.append_value(&value).map_err(arrow_error)?;
+            }
+        }
+
+        let mut columns = batch.columns().to_vec();
+        columns[idx] = Arc::new(builder.finish()) as ArrayRef;
+        RecordBatch::try_new(batch.schema().clone(), columns).map_err(arrow_error)
+    }
+}
+
+/// The post-processors which may be attached to the output of a job
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum PostProcessor {
+    RedactColumn(RedactColumn),
+}
+
+impl PostProcessor {
+    fn prepare(&self) -> Result<Box<dyn PostProcess + Send + Sync>> {
+        match self {
+            Self::RedactColumn(redact) => Ok(Box::new(redact.compile()?)),
+        }
+    }
+}
+
+/// The post-processors for an output computed with `redactions`
+pub fn post_processing(redactions: &[Redaction]) -> Vec<PostProcessor> {
+    redactions
+        .iter()
+        .map(|(column, detectors)| {
+            PostProcessor::RedactColumn(RedactColumn {
+                column: column.clone(),
+                detectors: detectors.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Prepares `post_processing` once for all the batches of an output
+pub fn prepare(post_processing: &[PostProcessor]) -> Result<Pipeline> {
+    post_processing
+        .iter()
+        .map(|post_processor| post_processor.prepare())
+        .collect()
+}
+
+fn strip_continuation(msg: &[u8]) -> Result<&[u8]> {
+    match msg.get(0..4) {
+        Some(header) if header == CONTINUATION => Ok(&msg[4..]),
+        _ => Err(Error::new("invalid arrow message")),
+    }
+}
+
+/// The message of `batch` alone, as BigQuery Storage serializes them
+fn serialize_batch(schema: &Schema, batch: &RecordBatch) -> Result<Vec<u8>> {
+    let mut schema_only = Vec::new();
+    StreamWriter::try_new(&mut schema_only, schema)
+        .and_then(|mut writer| writer.finish())
+        .map_err(arrow_error)?;
+
+    let mut with_batch = Vec::new();
+    StreamWriter::try_new(&mut with_batch, schema)
+        .and_then(|mut writer| {
+            writer.write(batch)?;
+            writer.finish()
+        })
+        .map_err(arrow_error)?;
+
+    // both streams start with the message of the schema and end with the
+    // end-of-stream marker
+    let start = schema_only.len() - END_OF_STREAM_LEN;
+    let end = with_batch.len() - END_OF_STREAM_LEN;
+    let mut msg = CONTINUATION.to_vec();
+    msg.extend_from_slice(&with_batch[start..end]);
+    Ok(msg)
+}
+
+/// Applies `pipeline` in order to a batch of output rows, as serialized by the
+/// backend along with the output `schema`
+pub fn process_serialized(
+    pipeline: &[Box<dyn PostProcess + Send + Sync>],
+    schema: &ArrowSchema,
+    batch: ArrowRecordBatch,
+) -> Result<ArrowRecordBatch> {
+    if pipeline.is_empty() {
+        return Ok(batch);
+    }
+
+    let mut buf = strip_continuation(&schema.serialized_schema)?.to_vec();
+    buf.extend_from_slice(strip_continuation(&batch.serialized_record_batch)?);
+
+    let mut reader = StreamReader::try_new(Cursor::new(buf)).map_err(arrow_error)?;
+    let schema = reader.schema();
+    let mut record_batch = reader
+        .next()
+        .map_err(arrow_error)?
+        .ok_or(Error::new("a batch of output rows was empty"))?;
+    for post_processor in pipeline.iter() {
+        record_batch = post_processor.process(record_batch)?;
+    }
+
+    Ok(ArrowRecordBatch {
+        serialized_record_batch: serialize_batch(&schema, &record_batch)?,
+        row_count: record_batch.num_rows() as i64,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use arrow::datatypes::Field;
+
+    /// The messages of `schema` and of `batch`, as BigQuery Storage sends them
+    fn bigquery_messages(schema: &Schema, batch: &RecordBatch) -> (ArrowSchema, ArrowRecordBatch) {
+        let mut schema_only = Vec::new();
+        StreamWriter::try_new(&mut schema_only, schema)
+            .and_then(|mut writer| writer.finish())
+            .unwrap();
+        let mut with_batch = Vec::new();
+        StreamWriter::try_new(&mut with_batch, schema)
+            .and_then(|mut writer| {
+                writer.write(batch)?;
+                writer.finish()
+            })
+            .unwrap();
+
+        let schema_len = schema_only.len() - END_OF_STREAM_LEN;
+        let mut serialized_schema = CONTINUATION.to_vec();
+        serialized_schema.extend_from_slice(&schema_only[..schema_len]);
+        let mut serialized_record_batch = CONTINUATION.to_vec();
+        serialized_record_batch
+            .extend_from_slice(&with_batch[schema_len..with_batch.len() - END_OF_STREAM_LEN]);
+
+        (
+            ArrowSchema { serialized_schema },
+            ArrowRecordBatch {
+                serialized_record_batch,
+                row_count: batch.num_rows() as i64,
+            },
+        )
+    }
+
+    /// The batch of `batch`, read the way clients read BigQuery Storage
+    fn read_batch(schema: &ArrowSchema, batch: &ArrowRecordBatch) -> RecordBatch {
+        let mut buf = schema.serialized_schema[4..].to_vec();
+        assert_eq!(&batch.serialized_record_batch[..4], &CONTINUATION);
+        buf.extend_from_slice(&batch.serialized_record_batch[4..]);
+        let mut reader = StreamReader::try_new(Cursor::new(buf)).unwrap();
+        let record_batch = reader.next().unwrap().unwrap();
+        assert!(reader.next().unwrap().is_none());
+        record_batch
+    }
+
+    #[test]
+    fn serialized_batches_round_trip() {
+        let schema = Schema::new(vec![
+            Field::new("notes", DataType::Utf8, true),
+            Field::new("visits", DataType::Int64, false),
+        ]);
+        let notes: ArrayRef =
+            Arc::new(StringArray::from(vec![Some("write to jane@doe.com"), None]));
+        let visits: ArrayRef = Arc::new(arrow::array::Int64Array::from(vec![3, 5]));
+        let batch = RecordBatch::try_new(Arc::new(schema.clone()), vec![notes, visits]).unwrap();
+        let (serialized_schema, serialized_batch) = bigquery_messages(&schema, &batch);
+
+        let pipeline = prepare(&[PostProcessor::RedactColumn(RedactColumn {
+            column: "notes".to_string(),
+            detectors: vec![Detector::builtin("email").unwrap()],
+        })])
+        .unwrap();
+        let processed =
+            process_serialized(&pipeline, &serialized_schema, serialized_batch).unwrap();
+        assert_eq!(processed.row_count, 2);
+
+        let processed = read_batch(&serialized_schema, &processed);
+        assert_eq!(processed.schema().as_ref(), &schema);
+        let notes = processed
+            .column(0)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(notes.value(0), "write to [REDACTED:EMAIL]");
+        assert!(notes.is_null(1));
+        let visits = processed
+            .column(1)
+            .as_any()
+            .downcast_ref::<arrow::array::Int64Array>()
+            .unwrap();
+        assert_eq!(visits.values(), &[3, 5]);
+    }
+
+    #[test]
+    fn redact_column() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("notes", DataType::Utf8, false),
+            Field::new("author", DataType::Utf8, false),
+        ]));
+        let notes: ArrayRef = Arc::new(StringArray::from(vec![
+            "call 555-123-4567 or write to jane@doe.com",
+            "nothing to see [REDACTED:EMAIL]",
+        ]));
+        let authors: ArrayRef = Arc::new(StringArray::from(vec!["jane@doe.com", "john"]));
+        let batch = RecordBatch::try_new(schema, vec![notes, authors]).unwrap();
+
+        let redact = RedactColumn {
+            column: "notes".to_string(),
+            detectors: vec![
+                Detector::builtin("email").unwrap(),
+                Detector::builtin("phone").unwrap(),
+            ],
+        };
+        let redacted = redact.compile().unwrap().process(batch).unwrap();
+
+        let notes = redacted
+            .column(0)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(
+            notes.value(0),
+            "call [REDACTED:PHONE] or write to [REDACTED:EMAIL]"
+        );
+        // text looking like a marker is not taken for a detection
+        assert_eq!(notes.value(1), "nothing to see [redacted:EMAIL]");
+
+        // other columns are left as they are
+        let authors = redacted
+            .column(1)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(authors.value(0), "jane@doe.com");
+    }
+}
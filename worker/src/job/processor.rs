@@ -2,7 +2,13 @@ use crate::common::{Job as ApiJob, *};
 use crate::node::{Access, AccessResult, Backends, Node};
 use crate::Result;
 
-use super::{Asset, Foreman, Job, JobStage, QueryDoneStage, QueryInitialisedStage, QueryStages};
+use futures::future;
+
+use super::post::{self, PostProcessor};
+use super::{
+    is_valid_purpose, refused, Asset, Foreman, Job, JobStage, QueryDoneStage,
+    QueryInitialisedStage, QueryStages,
+};
 
 pub struct Processor<A> {
     access: A,
@@ -42,14 +48,29 @@ where
     pub async fn get_output_rows(&self) -> AccessResult<ContentStream<ArrowRecordBatch>> {
         debug!("fetching output rows for {}", self.id);
         if let Some(output) = self.output()? {
-            let rows = self
+            let backend = self
                 .access
                 .backend(&output.loc)
-                .map_err(|e| self.access.error(e))?
+                .map_err(|e| self.access.error(e))?;
+            let rows = backend
                 .get_records(&output.source)
                 .await
                 .map_err(|e| self.access.error(e))?;
-            Ok(rows)
+            let post_processing = self.post_processing()?;
+            if post_processing.is_empty() {
+                return Ok(rows);
+            }
+            let pipeline = post::prepare(&post_processing).map_err(|e| self.access.error(e))?;
+            let schema = backend
+                .get_schema(&output.source)
+                .await
+                .map_err(|e| self.access.error(e))?;
+            let processed = rows.and_then(move |batch| {
+                future::ready(
+                    post::process_serialized(&pipeline, &schema, batch).map_err(Status::from),
+                )
+            });
+            Ok(Box::pin(processed))
         } else {
             Err(AccessError {
                 kind: AccessErrorKind::NotFound as i32,
@@ -65,12 +86,26 @@ where
     pub fn output(&self) -> AccessResult<Option<Asset>> {
         self.state().map(|job| {
             job.state.ok().and_then(|stage| match stage {
-                JobStage::Query(QueryStages::Done(QueryDoneStage { asset })) => Some(asset),
+                JobStage::Query(QueryStages::Done(QueryDoneStage { asset, .. })) => Some(asset),
                 _ => None,
             })
         })
     }
 
+    pub fn post_processing(&self) -> AccessResult<Vec<PostProcessor>> {
+        self.state().map(|job| {
+            job.state
+                .ok()
+                .and_then(|stage| match stage {
+                    JobStage::Query(QueryStages::Done(QueryDoneStage {
+                        post_processing, ..
+                    })) => Some(post_processing),
+                    _ => None,
+                })
+                .unwrap_or_default()
+        })
+    }
+
     pub fn state(&self) -> AccessResult<Job> {
         self.access
             .job(&self.task)
@@ -119,7 +154,7 @@ where
         let mut job = self.acquire()?;
         info!("Polling job {:?}", job);
         if let Ok(state) = job.state {
-            let state = state.advance(&self.access).await;
+            let state = state.advance(&self.access, &job.id).await;
             self.mutate_state(|mut job| {
                 job.state = state;
                 Ok(())
@@ -141,14 +176,36 @@ where
             Err(self.access.error(err))
         } else {
             let query = from.query;
+            let purpose = Some(from.purpose).filter(|purpose| !purpose.is_empty());
+            if let Some(purpose) = purpose.as_ref() {
+                // purposes end up in the queries sent to backends
+                if !is_valid_purpose(purpose) {
+                    state.abort(lock).unwrap();
+                    let err = ScopeError {
+                        kind: ScopeErrorKind::BadObject as i32,
+                        source: purpose.to_string(),
+                        description: "purposes may only contain letters, digits, \
+                                      spaces, `-` and `_`"
+                            .to_string(),
+                    };
+                    self.access
+                        .audit(refused(&self.task, &query, Some(purpose), &err))
+                        .map_err(|e| self.access.error(e))?;
+                    return Err(self.access.error(err));
+                }
+            }
 
             let init = Job {
                 id: self.task.clone(),
                 timestamp: Utc::now(),
                 state: Ok(JobStage::Query(QueryStages::Initialised(
-                    QueryInitialisedStage { query },
+                    QueryInitialisedStage {
+                        query,
+                        purpose: purpose.clone(),
+                    },
                 ))),
                 user: self.access.who_am_i().to_string(),
+                purpose,
                 foreman: None,
             };
 
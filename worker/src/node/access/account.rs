@@ -11,9 +11,9 @@ use crate::Result;
 use super::{get_token_for_req, Access, AccessProvider, AccessResult};
 use crate::backends::Backend;
 use crate::job::{Job, Processor};
-use crate::node::{Peer, Shared};
+use crate::node::{ops, AuditHead, AuditRecord, Peer, QueryHistory, Release, Shared};
 use crate::opt::PolicyBinding;
-use crate::opt::{Context, TableMeta};
+use crate::opt::{Context, Exclusion, TableMeta};
 use regex::Regex;
 
 const PEM_REGEX: &'static str = r"(-----BEGIN .*-----\n)((?:(?:[A-Za-z0-9+/]{4})*(?:[A-Za-z0-9+/]{2}==|[A-Za-z0-9+/]{3}=)*\n)+)(-----END .*-----)";
@@ -180,13 +180,73 @@ where
         self.inner.backend(rt)
     }
 
+    fn audit_head(&self) -> Result<Shared<AuditHead>> {
+        self.inner.audit_head()
+    }
+
+    fn audit_record(&self, sequence: u64) -> Result<Shared<AuditRecord>> {
+        self.inner.audit_record(sequence)
+    }
+
+    fn audit_records(&self) -> Result<Vec<AuditRecord>> {
+        self.ensure_super()?;
+        self.inner.audit_records()
+    }
+
+    fn query_history(&self, user: &str) -> Result<Shared<QueryHistory>> {
+        self.inner.query_history(user)
+    }
+
+    fn release(&self, key: &str) -> Result<Shared<Release>> {
+        self.inner.release(key)
+    }
+
     fn resource(&self, resource_ty: &BlockType) -> Result<Shared<Resource>> {
         self.ensure_super()?;
         self.inner.resource(resource_ty)
     }
 
+    /// Secrets and exclusion lists are only listed to super users, other users
+    /// get everything else matching `pat`
     fn resources(&self, pat: &BlockType) -> Result<Vec<Resource>> {
-        self.inner.resources(pat)
+        let resources = self.inner.resources(pat)?;
+        if self.super_user {
+            return Ok(resources);
+        }
+        Ok(resources
+            .into_iter()
+            .filter(|resource| match resource.resource {
+                Some(ResourceEnum::Secret(..)) | Some(ResourceEnum::ExclusionList(..)) => false,
+                _ => true,
+            })
+            .collect())
+    }
+
+    fn secret(&self, secret_ty: &BlockType) -> Result<Option<crate::common::Secret>> {
+        self.inner.secret(secret_ty)
+    }
+
+    /// Exclusion lists apply to the queries of every user, who may not read
+    /// them otherwise
+    fn exclusions(&self) -> Result<Vec<Exclusion>> {
+        self.inner.exclusions()
+    }
+
+    /// Statistics disclose values of the data, so they are for super users
+    async fn column_statistics(
+        &self,
+        dataset: &str,
+        table: &str,
+        column: &str,
+    ) -> Result<ColumnStatistics> {
+        self.ensure_super()?;
+        ops::column_statistics(self, dataset, table, column).await
+    }
+
+    /// Traces read the audit log, so they are for super users
+    async fn trace(&self, values: Vec<String>, from: &str, to: &str) -> Result<Vec<TracedJob>> {
+        self.ensure_super()?;
+        ops::trace(self, values, from, to).await
     }
 
     fn peer(&self) -> Result<Peer> {
@@ -215,13 +275,25 @@ where
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
     use crate::node::ops::create_resource;
-    use crate::node::tests::mk_random_node;
+    use crate::node::tests::{mk_manifest_node, mk_random_node};
 
     use crate::common::Block;
     use std::sync::Arc;
+    use tokio::runtime::Runtime;
+
+    /// The access of `unit-tester`, a member of `wheel` in the test
+    /// manifest, through `inner`
+    pub fn unit_tester_access<A>(inner: A) -> AccountAccess<A> {
+        AccountAccess {
+            user: block_type!("resource"."user"."unit-tester"),
+            primary_group: block_type!("resource"."group"."wheel"),
+            super_user: true,
+            inner,
+        }
+    }
 
     #[test]
     fn access_account_enforce_super_user() {
@@ -262,4 +334,112 @@ mod tests {
             Ok(_) => panic!("should not have access to this"),
         }
     }
+
+    #[test]
+    fn access_account_hides_secrets() {
+        let node = Arc::new(mk_random_node());
+
+        let secret = Resource {
+            resource: Some(ResourceEnum::Secret(crate::common::Secret {
+                name: "key".to_string(),
+                kms: "gcp-kms://projects/p/locations/l/keyRings/r/cryptoKeys/k".to_string(),
+                wrapped: "d3JhcHBlZA==".to_string(),
+                ..Default::default()
+            })),
+        };
+        create_resource(&node, secret.clone()).unwrap();
+        let secret_ty = secret.block_type().unwrap();
+
+        let unpriv_access = AccountAccess {
+            user: block_type!("resource"."user"."lambda"),
+            primary_group: block_type!("resource"."group"."users"),
+            super_user: false,
+            inner: node,
+        };
+
+        assert!(unpriv_access
+            .resources(&block_type!("resource"."secret"."*"))
+            .unwrap()
+            .is_empty());
+        // policies referencing the secret can still be resolved
+        assert_eq!(
+            unpriv_access.secret(&secret_ty).unwrap().unwrap().wrapped,
+            "d3JhcHBlZA=="
+        );
+    }
+
+    #[test]
+    fn access_account_hides_exclusion_lists() {
+        let node = mk_manifest_node();
+
+        let unpriv_access = AccountAccess {
+            user: block_type!("resource"."user"."lambda"),
+            primary_group: block_type!("resource"."group"."users"),
+            super_user: false,
+            inner: node,
+        };
+
+        assert!(unpriv_access
+            .resources(&block_type!("resource"."exclusion_list"."*"))
+            .unwrap()
+            .is_empty());
+        // the excluded entities are still left out of the queries
+        let exclusions = unpriv_access.exclusions().unwrap();
+        assert_eq!(exclusions[0].identifiers, vec!["42".to_string()]);
+    }
+
+    #[test]
+    fn access_account_statistics_are_privileged() {
+        let unpriv_access = AccountAccess {
+            user: block_type!("resource"."user"."lambda"),
+            primary_group: block_type!("resource"."group"."users"),
+            super_user: false,
+            inner: Arc::new(mk_random_node()),
+        };
+        Runtime::new().unwrap().block_on(async move {
+            match unpriv_access
+                .column_statistics("patient_data", "person", "year_of_birth")
+                .await
+            {
+                Err(_) => {}
+                Ok(_) => panic!("should not have access to this"),
+            }
+        })
+    }
+
+    #[test]
+    fn access_account_detokenize_is_audited() {
+        let node = mk_manifest_node();
+
+        // `unit-tester` is a member of `wheel`, the detokenizer group
+        let access = AccountAccess {
+            user: block_type!("resource"."user"."unit-tester"),
+            primary_group: block_type!("resource"."group"."wheel"),
+            super_user: false,
+            inner: node.clone(),
+        };
+        let detokenize = |justification: &'static str| {
+            let access = access.clone();
+            Runtime::new().unwrap().block_on(async move {
+                access
+                    .detokenize(
+                        "patient_data",
+                        "policy.tokenize.location_sources_are_tokenized",
+                        Vec::new(),
+                        justification,
+                    )
+                    .await
+            })
+        };
+        let last_outcome = || match node.audit_entries(0).unwrap().pop().unwrap().decision {
+            Some(AuditDecision::Detokenize(decision)) => decision.outcome,
+            _ => panic!("the attempt was not audited"),
+        };
+
+        assert!(detokenize("re-contacting trial participants").is_ok());
+        assert_eq!(last_outcome(), "authorized");
+
+        assert!(detokenize("").is_err());
+        assert_eq!(last_outcome(), "refused");
+    }
 }
@@ -1,11 +1,13 @@
 use crate::common::*;
 use crate::Result;
 
-pub use super::{Backends, Node, Peer, Scope, Shared};
+pub use super::{
+    AuditHead, AuditRecord, Backends, Node, Peer, QueryHistory, Release, Scope, Shared,
+};
 
-use crate::backends::Backend;
+use crate::backends::{Backend, LastUpdated};
 use crate::job::{Job, Processor};
-use crate::opt::PolicyBinding;
+use crate::opt::{Consent, Cost, Exclusion, Fingerprint, PolicyBinding, Template};
 use crate::opt::{Context, ContextKey, TableMeta};
 
 macro_rules! access_error {
@@ -41,6 +43,8 @@ macro_rules! access_error {
 }
 
 mod account;
+#[cfg(test)]
+pub(crate) use account::tests::unit_tester_access;
 pub use account::{AccountAccess, AccountAccessProvider};
 
 mod bootstrap;
@@ -89,6 +93,11 @@ pub trait Access: Sized + Send + Sync + 'static {
     fn acquire_lock(&self) -> Result<String>;
     fn release_lock(&self, lock_id: &str) -> Result<()>;
     fn list_jobs(&self) -> Result<Vec<Job>>;
+    fn audit_head(&self) -> Result<Shared<AuditHead>>;
+    fn audit_record(&self, sequence: u64) -> Result<Shared<AuditRecord>>;
+    fn audit_records(&self) -> Result<Vec<AuditRecord>>;
+    fn query_history(&self, user: &str) -> Result<Shared<QueryHistory>>;
+    fn release(&self, key: &str) -> Result<Shared<Release>>;
 
     fn job(&self, job_id: &str) -> Result<Option<Job>> {
         self.shared_job(job_id).and_then(|job| {
@@ -98,18 +107,74 @@ pub trait Access: Sized + Send + Sync + 'static {
         })
     }
 
-    fn expend_to_budget(&self, cost: HashMap<ContextKey, f64>) -> Result<()> {
+    fn expend_to_budget(&self, cost: HashMap<ContextKey, Cost>) -> Result<()> {
         ops::expend_to_budget(self, cost)
     }
 
-    fn policies_for_group(&self, audience: &str) -> Result<Context<PolicyBinding>> {
-        ops::policies_for_group(self, audience)
+    fn policies_for_group(
+        &self,
+        audience: &str,
+        purpose: Option<&str>,
+    ) -> Result<Context<PolicyBinding>> {
+        ops::policies_for_group(self, audience, purpose)
+    }
+
+    fn exclusions(&self) -> Result<Vec<Exclusion>> {
+        ops::exclusions(self)
+    }
+
+    fn consents(&self) -> Result<Vec<Consent>> {
+        ops::consents(self)
+    }
+
+    fn templates(&self, audience: &str, purpose: Option<&str>) -> Result<Vec<Template>> {
+        ops::templates(self, audience, purpose)
+    }
+
+    fn detect_differencing(
+        &self,
+        fingerprint: &Fingerprint,
+    ) -> Result<Vec<(String, DifferencingDetection, usize)>> {
+        ops::detect_differencing(self, fingerprint)
+    }
+
+    fn remember_fingerprint(&self, fingerprint: &Fingerprint) -> Result<()> {
+        ops::remember_fingerprint(self, fingerprint)
+    }
+
+    fn audit(&self, decision: AuditDecision) -> Result<()> {
+        ops::audit(self, decision)
+    }
+
+    fn audit_entries(&self, from: u64) -> Result<Vec<AuditEntry>> {
+        ops::audit_entries(self, from)
+    }
+
+    fn released(
+        &self,
+        key: &str,
+        last_updated: &HashMap<String, LastUpdated>,
+    ) -> Result<Option<Release>> {
+        ops::released(self, key, last_updated)
+    }
+
+    fn remember_release(&self, release: Release) -> Result<()> {
+        ops::remember_release(self, release)
     }
 
     async fn context(&self) -> Result<Context<TableMeta>> {
         ops::context(self).await
     }
 
+    async fn column_statistics(
+        &self,
+        dataset: &str,
+        table: &str,
+        column: &str,
+    ) -> Result<ColumnStatistics> {
+        ops::column_statistics(self, dataset, table, column).await
+    }
+
     fn into_task(self, task_id: &str) -> Processor<Self> {
         Processor::new(self, task_id.to_string())
     }
@@ -126,6 +191,24 @@ pub trait Access: Sized + Send + Sync + 'static {
         ops::group(self, group_id)
     }
 
+    fn secret(&self, secret_ty: &BlockType) -> Result<Option<Secret>> {
+        ops::secret(self, secret_ty)
+    }
+
+    async fn detokenize(
+        &self,
+        dataset: &str,
+        policy: &str,
+        tokens: Vec<String>,
+        justification: &str,
+    ) -> Result<Vec<String>> {
+        ops::detokenize(self, dataset, policy, tokens, justification).await
+    }
+
+    async fn trace(&self, values: Vec<String>, from: &str, to: &str) -> Result<Vec<TracedJob>> {
+        ops::trace(self, values, from, to).await
+    }
+
     fn groups_for_user(&self, user_id: &str) -> Result<Vec<BlockType>> {
         ops::groups_for_user(self, user_id)
     }
@@ -0,0 +1,39 @@
+use crate::common::*;
+
+/// An entry of the audit log, at its position in the log
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(transparent)]
+pub struct AuditRecord(pub AuditEntry);
+
+impl Block for AuditRecord {
+    fn block_type(&self) -> std::result::Result<BlockType, parallax_api::swamp::TypeError> {
+        Ok(block_type!("entry".(&self.0.sequence.to_string())))
+    }
+
+    fn parse_block_type(
+        stream: TokenStream,
+    ) -> std::result::Result<(BlockType, TokenStream), parallax_api::swamp::TypeError> {
+        stream.parse("entry")?.take()?.done()
+    }
+}
+
+/// The end of the audit log, which entries are appended to
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct AuditHead {
+    /// The position of the next entry
+    pub sequence: u64,
+    /// The hash of the last entry, empty if there is none
+    pub hash: String,
+}
+
+impl Block for AuditHead {
+    fn block_type(&self) -> std::result::Result<BlockType, parallax_api::swamp::TypeError> {
+        Ok(block_type!("head"."log"))
+    }
+
+    fn parse_block_type(
+        stream: TokenStream,
+    ) -> std::result::Result<(BlockType, TokenStream), parallax_api::swamp::TypeError> {
+        stream.parse("head")?.parse("log")?.done()
+    }
+}
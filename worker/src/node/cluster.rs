@@ -1,7 +1,10 @@
 use crate::common::*;
 use crate::Result;
 
-use super::{BlockStore, BlockStoreExt, RedisBlockStore, SharedScope};
+use super::{
+    AuditHead, AuditRecord, BlockStore, BlockStoreExt, QueryHistory, RedisBlockStore, Release,
+    SharedScope,
+};
 use crate::job::Job;
 use std::net::IpAddr;
 
@@ -29,6 +32,13 @@ pub struct Cluster {
     /// The other (including us) peers in the pool
     pub peers: SharedScope<Peer>,
     pub jobs: SharedScope<Job>,
+    /// The entries of the audit log, by position
+    pub audit_log: SharedScope<AuditRecord>,
+    pub audit_heads: SharedScope<AuditHead>,
+    /// The fingerprints of the queries of each user
+    pub histories: SharedScope<QueryHistory>,
+    /// The results of differentially private queries, by query
+    pub releases: SharedScope<Release>,
 }
 
 impl Block for Peer {
@@ -59,12 +69,24 @@ impl Cluster {
             unimplemented!("recover from poison")
         }
 
-        let jobs = RedisBlockStore::with_prefix(store, "jobs").into_shared();
+        let jobs = RedisBlockStore::with_prefix(store.clone(), "jobs").into_shared();
+
+        let audit_log = RedisBlockStore::with_prefix(store.clone(), "audit").into_shared();
+
+        let audit_heads = RedisBlockStore::with_prefix(store.clone(), "audit_heads").into_shared();
+
+        let histories = RedisBlockStore::with_prefix(store.clone(), "histories").into_shared();
+
+        let releases = RedisBlockStore::with_prefix(store, "releases").into_shared();
 
         Ok(Self {
             advertised,
             peers,
             jobs,
+            audit_log,
+            audit_heads,
+            histories,
+            releases,
         })
     }
 
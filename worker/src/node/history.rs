@@ -0,0 +1,24 @@
+use crate::common::*;
+
+use crate::opt::Fingerprint;
+
+/// The fingerprints of the queries a user was authorized to run, by dataset
+/// and most recent last
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct QueryHistory {
+    /// The user who ran the queries
+    pub user: String,
+    pub fingerprints: HashMap<String, Vec<Fingerprint>>,
+}
+
+impl Block for QueryHistory {
+    fn block_type(&self) -> std::result::Result<BlockType, parallax_api::swamp::TypeError> {
+        Ok(block_type!("history".(&self.user)))
+    }
+
+    fn parse_block_type(
+        stream: TokenStream,
+    ) -> std::result::Result<(BlockType, TokenStream), parallax_api::swamp::TypeError> {
+        stream.parse("history")?.take()?.done()
+    }
+}
@@ -44,6 +44,18 @@ mod cluster;
 
 pub use cluster::{Cluster, Peer};
 
+mod audit;
+
+pub use audit::{AuditHead, AuditRecord};
+
+mod history;
+
+pub use history::QueryHistory;
+
+mod release;
+
+pub use release::Release;
+
 pub(self) mod ops;
 
 pub struct Node {
@@ -141,6 +153,43 @@ impl Access for Arc<Node> {
         self.backends.backend(resource_type)
     }
 
+    fn audit_head(&self) -> Result<Shared<AuditHead>> {
+        self.cluster
+            .audit_heads
+            .block(&block_type!("head"."log"))
+            .map_err(|e| e.into())
+    }
+
+    fn audit_record(&self, sequence: u64) -> Result<Shared<AuditRecord>> {
+        self.cluster
+            .audit_log
+            .block(&block_type!("entry".(&sequence.to_string())))
+            .map_err(|e| e.into())
+    }
+
+    fn audit_records(&self) -> Result<Vec<AuditRecord>> {
+        let records = self
+            .cluster
+            .audit_log
+            .read_all(&block_type!("entry"."*"), |block| block.clone())?
+            .collect();
+        Ok(records)
+    }
+
+    fn query_history(&self, user: &str) -> Result<Shared<QueryHistory>> {
+        self.cluster
+            .histories
+            .block(&block_type!("history".user))
+            .map_err(|e| e.into())
+    }
+
+    fn release(&self, key: &str) -> Result<Shared<Release>> {
+        self.cluster
+            .releases
+            .block(&block_type!("release".key))
+            .map_err(|e| e.into())
+    }
+
     fn peer(&self) -> Result<Peer> {
         Ok(self.cluster.advertised.clone())
     }
@@ -171,6 +220,7 @@ pub(crate) mod tests {
     use super::*;
 
     use super::state::tests::{mk_state, read_manifest};
+    use crate::opt::Cost;
 
     use ring::signature::{KeyPair, RsaKeyPair};
 
@@ -194,26 +244,214 @@ pub(crate) mod tests {
         node
     }
 
-    pub fn just_get_me_policies() -> Context<PolicyBinding> {
-        let random_scope = uuid::Uuid::new_v4().to_simple().to_string();
-        let access = Arc::new(mk_node(&random_scope));
-        for resource in read_manifest().into_iter() {
+    /// A node with the resources of the test manifest, each edited by `edit`
+    /// before it is created
+    pub fn mk_node_with<F: FnMut(&mut Resource)>(mut edit: F) -> Arc<Node> {
+        let access = Arc::new(mk_random_node());
+        for mut resource in read_manifest().into_iter() {
+            edit(&mut resource);
             access.create_resource(resource).unwrap();
         }
-        access.policies_for_group("group1").unwrap()
+        access
+    }
+
+    /// A node with the resources of the test manifest
+    pub fn mk_manifest_node() -> Arc<Node> {
+        mk_node_with(|_| ())
+    }
+
+    pub fn just_get_me_policies() -> Context<PolicyBinding> {
+        let access = mk_manifest_node();
+        access.policies_for_group("group1", None).unwrap()
     }
 
     pub fn just_get_me_a_context() -> Context<TableMeta> {
-        let random_scope = uuid::Uuid::new_v4().to_simple().to_string();
-        let access = Arc::new(mk_node(&random_scope));
-        for resource in read_manifest().into_iter() {
-            access.create_resource(resource).unwrap();
-        }
+        let access = mk_manifest_node();
         Runtime::new()
             .unwrap()
             .block_on(async { access.context().await.unwrap() })
     }
 
+    #[test]
+    fn policies_for_group_checks_purposes() {
+        let access = mk_manifest_node();
+        assert!(access.policies_for_group("wheel", Some("billing")).is_ok());
+        assert!(access.policies_for_group("wheel", None).is_ok());
+        // members of `wheel` may not declare purposes the group does not list
+        assert!(access
+            .policies_for_group("wheel", Some("marketing"))
+            .is_err());
+    }
+
+    #[test]
+    fn detokenize_requires_detokenizer_group() {
+        let access = mk_manifest_node();
+        let detokenized = Runtime::new().unwrap().block_on(async {
+            access
+                .detokenize(
+                    "patient_data",
+                    "policy.tokenize.location_sources_are_tokenized",
+                    vec!["AXjmcs0=".to_string()],
+                    "testing",
+                )
+                .await
+        });
+        // `root` is not a member of `wheel`
+        assert!(detokenized.is_err());
+
+        // the refusal is recorded all the same
+        match access.audit_entries(0).unwrap().pop().unwrap().decision {
+            Some(AuditDecision::Detokenize(decision)) => {
+                assert_eq!(decision.outcome, "refused");
+                assert_eq!(decision.tokens, vec!["AXjmcs0=".to_string()]);
+            }
+            _ => panic!("the refusal was not audited"),
+        }
+    }
+
+    #[test]
+    fn audit_entries_are_chained() {
+        let access = Arc::new(mk_random_node());
+        let decision = |justification: &str| {
+            AuditDecision::Detokenize(DetokenizeDecision {
+                dataset: "patient_data".to_string(),
+                policy: "policy.tokenize.location_sources_are_tokenized".to_string(),
+                tokens: vec!["AXjmcs0=".to_string()],
+                justification: justification.to_string(),
+                ..Default::default()
+            })
+        };
+        access.audit(decision("first")).unwrap();
+        access.audit(decision("second")).unwrap();
+
+        let entries = access.audit_entries(0).unwrap();
+        let mut previous = None;
+        for entry in entries.iter() {
+            assert!(entry.follows(previous));
+            previous = Some(entry);
+        }
+
+        let mut tampered = entries.last().unwrap().clone();
+        tampered.decision = Some(decision("tampered"));
+        assert!(!tampered.follows(entries.iter().rev().nth(1)));
+    }
+
+    #[test]
+    fn audit_entries_are_read_within_a_window() {
+        let access = Arc::new(mk_random_node());
+        let decision = || {
+            AuditDecision::Detokenize(DetokenizeDecision {
+                dataset: "patient_data".to_string(),
+                ..Default::default()
+            })
+        };
+        let before = Utc::now();
+        for _ in 0..5 {
+            access.audit(decision()).unwrap();
+        }
+        let after = Utc::now();
+        access.audit(decision()).unwrap();
+
+        let sequences = |from: &DateTime<Utc>, to: &DateTime<Utc>| {
+            ops::audit_entries_between(&access, from, to)
+                .unwrap()
+                .into_iter()
+                .map(|entry| entry.sequence)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(sequences(&before, &after), vec![0, 1, 2, 3, 4]);
+        assert!(sequences(&Utc::now(), &Utc::now()).is_empty());
+    }
+
+    /// A node with the test manifest, whose policy bindings spend from a
+    /// budget of `maximum` epsilon and of unlimited delta
+    fn mk_budgeted_node(maximum: f64) -> Arc<Node> {
+        mk_node_with(|resource| {
+            if let Some(ResourceEnum::Dataset(dataset)) = resource.resource.as_mut() {
+                for binding in dataset.policy_bindings.iter_mut() {
+                    binding.budget = Some(PolicyBudget {
+                        maximum,
+                        ..Default::default()
+                    });
+                }
+            }
+        })
+    }
+
+    /// The budgets of the binding `wheel_policies` and of the unit `patients`
+    fn budgets(access: &Arc<Node>) -> (PolicyBudget, PolicyBudget) {
+        let dataset = access
+            .resources(&block_type!("resource"."dataset"."patient_data"))
+            .unwrap()
+            .pop()
+            .unwrap()
+            .try_downcast::<Dataset>()
+            .unwrap();
+        let binding = dataset
+            .policy_bindings
+            .into_iter()
+            .find(|binding| binding.name == "wheel_policies")
+            .unwrap();
+        let unit = access
+            .resources(&block_type!("resource"."privacy_unit"."patients"))
+            .unwrap()
+            .pop()
+            .unwrap()
+            .try_downcast::<PrivacyUnit>()
+            .unwrap();
+        (binding.budget.unwrap(), unit.budget.unwrap())
+    }
+
+    fn wheel_expense(epsilon: f64, delta: f64) -> HashMap<ContextKey, Cost> {
+        let key = ContextKey::with_name("wheel_policies").and_prefix("patient_data");
+        vec![(key, Cost { epsilon, delta })].into_iter().collect()
+    }
+
+    #[test]
+    fn expend_to_budget_charges_bindings_and_units() {
+        let access = mk_budgeted_node(2.);
+
+        access.expend_to_budget(wheel_expense(1., 1e-5)).unwrap();
+        let (binding, unit) = budgets(&access);
+        assert_eq!(binding.used, 1.);
+        assert_eq!(binding.used_delta, 1e-5);
+        // the unit protects the entity of the differential privacy policy
+        assert_eq!(unit.used, 1.);
+        assert_eq!(unit.used_delta, 1e-5);
+
+        // the budget may be spent up to its maximum
+        access.expend_to_budget(wheel_expense(1., 0.)).unwrap();
+        assert_eq!(budgets(&access).0.used, 2.);
+    }
+
+    #[test]
+    fn expend_to_budget_refuses_exhausted_budgets() {
+        let access = mk_budgeted_node(2.);
+        access.expend_to_budget(wheel_expense(1.5, 0.)).unwrap();
+
+        let err = access.expend_to_budget(wheel_expense(1., 0.)).unwrap_err();
+        assert!(err.to_string().contains("patient_data.wheel_policies"));
+
+        // nothing is charged when a query is refused
+        let (binding, unit) = budgets(&access);
+        assert_eq!(binding.used, 1.5);
+        assert_eq!(unit.used, 1.5);
+    }
+
+    #[test]
+    fn expend_to_budget_refuses_exhausted_units() {
+        // the binding does not limit delta, but the unit does
+        let access = mk_budgeted_node(2.);
+        let err = access
+            .expend_to_budget(wheel_expense(1., 0.01))
+            .unwrap_err();
+        assert!(err.to_string().contains("patients"));
+
+        let (binding, unit) = budgets(&access);
+        assert_eq!(binding.used, 0.);
+        assert_eq!(unit.used_delta, 0.);
+    }
+
     #[test]
     fn cluster_added_then_released_on_drop() {
         let peer = Peer::default();
@@ -1,16 +1,21 @@
 use futures::future;
+use futures::try_join;
 
-use crate::common::{Policy as ApiPolicy, *};
+use crate::common::{Policy as ApiPolicy, PolicyBinding as ApiPolicyBinding, *};
 use crate::Result;
 
-use super::Access;
+use super::{Access, AuditHead, AuditRecord, QueryHistory, Release};
 
-use crate::backends::Backend;
+use crate::backends::{Backend, LastUpdated};
 use crate::job::Job;
 use crate::node::resource::{BlockStore, BlockStoreExt, RedisBlockStore, SharedScope};
 use crate::node::state::Scope;
 use crate::opt::validate::Validate;
-use crate::opt::{Context, ContextKey, DataType, ExprMeta, Policy, PolicyBinding, TableMeta};
+use crate::opt::{
+    Consent, Context, ContextKey, Cost, DataType, DeclaredDomains, EntityFields, Exclusion,
+    ExprMeta, Fingerprint, Keying, Policy, PolicyBinding, TableMeta, Template, Watermark,
+    WrappedKeyset,
+};
 use crate::Opt;
 
 lazy_static! {
@@ -137,6 +142,64 @@ pub async fn context<A: Access>(access: &A) -> Result<Context<TableMeta>> {
     Ok(ctx)
 }
 
+/// The number of buckets of the histograms of columns
+const HISTOGRAM_BUCKETS: u64 = 10;
+
+/// The number of most frequent values reported for columns
+const TOP_VALUES: u64 = 10;
+
+/// Approximate statistics of the values of the column `column_name` of the
+/// table `table_name` of the dataset `dataset_name`, read from the data
+pub async fn column_statistics<A: Access>(
+    access: &A,
+    dataset_name: &str,
+    table_name: &str,
+    column_name: &str,
+) -> Result<ColumnStatistics> {
+    let ctx = access.context().await?;
+    let table_meta = ctx
+        .get_table(&ContextKey::with_name(table_name).and_prefix(dataset_name))
+        .map_err(|e| e.into_error())?;
+    let column = ContextKey::with_name(column_name);
+    table_meta
+        .columns
+        .get(&column)
+        .map_err(|e| e.into_column_error().into_error())?;
+
+    let loc = table_meta
+        .loc
+        .as_ref()
+        .ok_or(Error::new("table does not have a backend location"))?;
+    let source = table_meta
+        .source
+        .as_ref()
+        .ok_or(Error::new("table does not have a source key"))?;
+    let backend = access.backend(loc)?;
+    let probe = backend.probe(source).await?;
+
+    let (distinct_count, null_fraction, histogram, top_k) = try_join!(
+        probe.distinct_count(&column),
+        probe.null_fraction(&column),
+        probe.histogram(&column, &HISTOGRAM_BUCKETS),
+        probe.top_k(&column, &TOP_VALUES)
+    )?;
+
+    Ok(ColumnStatistics {
+        distinct_count: distinct_count.0,
+        null_fraction: null_fraction.0,
+        histogram: histogram.0,
+        top_values: top_k
+            .0
+            .into_iter()
+            .map(|(value, count)| ValueFrequency {
+                is_null: value.is_none(),
+                value: value.unwrap_or_default(),
+                count,
+            })
+            .collect(),
+    })
+}
+
 pub fn user<A: Access>(access: &A, user_id: &str) -> Result<Option<User>> {
     let maybe_user = access
         .resource(&block_type!("resource"."user".user_id))?
@@ -155,18 +218,27 @@ pub fn group<A: Access>(access: &A, group_id: &str) -> Result<Option<Group>> {
         .transpose()
 }
 
+/// Secrets are read on behalf of the policies referencing them, so this does not
+/// require the privileges `Access::resource` does.
+pub fn secret<A: Access>(access: &A, secret_ty: &BlockType) -> Result<Option<Secret>> {
+    access
+        .resources(secret_ty)?
+        .into_iter()
+        .next()
+        .map(|secret| secret.try_into().map_err(|e| Error::from(e)))
+        .transpose()
+}
+
 pub fn groups_for_user<A: Access>(access: &A, user_id: &str) -> Result<Vec<BlockType>> {
     debug!("getting groups for {}", user_id);
     let user_type = block_type!("resource"."user".user_id);
     let user = access.user(user_id)?.ok_or(ScopeError::not_found("user"))?;
 
+    let now = Utc::now();
     let mut groups = Vec::new();
     for group in access.resources(&block_type!("resource"."group"."*"))? {
-        for member in group.clone().try_downcast::<Group>()?.members.into_iter() {
-            // FIXME: should be checking tokenstream is empty
-            if user_type == BlockType::parse::<Resource>(&member)?.0 {
-                groups.push(group.block_type()?);
-            }
+        if is_member(&group.clone().try_downcast::<Group>()?, &user_type, &now)? {
+            groups.push(group.block_type()?);
         }
     }
 
@@ -176,7 +248,7 @@ pub fn groups_for_user<A: Access>(access: &A, user_id: &str) -> Result<Vec<Block
 pub fn expend_to_budget<A, I>(access: &A, expense: I) -> Result<()>
 where
     A: Access,
-    I: IntoIterator<Item = (ContextKey, f64)>,
+    I: IntoIterator<Item = (ContextKey, Cost)>,
 {
     // FIXME: refactor, this is really dirty (though correct AFAIK)
     let mut closure = HashMap::new();
@@ -193,35 +265,71 @@ where
         entry.1.push((key, cost));
     }
 
+    let units = access
+        .resources(&block_type!("resource"."privacy_unit"."*"))?
+        .into_iter()
+        .map(|resource| resource.try_downcast::<PrivacyUnit>())
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+
     let mut locks = HashMap::new();
-    let mut feasible = true;
+    let mut unit_expenses: HashMap<String, Cost> = HashMap::new();
+    let mut exhausted = Vec::new();
     for (block_type, (block, expenses)) in closure.iter() {
         let lock = block.write().unwrap();
         let dataset: Dataset = block
             .read_with(|dataset| dataset.clone())?
-            .unwrap()
+            .ok_or(ScopeError::not_found(block_type))?
             .try_into()?;
         for expense in expenses.iter() {
             let matching = dataset
                 .policy_bindings
                 .iter()
-                .find(|binding| &binding.name == expense.0.name())
-                .and_then(|binding| binding.budget.as_ref());
-            if let Some(budget) = matching {
-                feasible = budget.maximum >= budget.used + expense.1;
+                .find(|binding| &binding.name == expense.0.name());
+            if let Some(budget) = matching.and_then(|binding| binding.budget.as_ref()) {
+                if !expense.1.fits(budget) {
+                    exhausted.push(format!("{}.{}", dataset.name, expense.0.name()));
+                }
+            }
+            if let Some(binding) = matching {
+                let entities = charged_entities(&dataset, binding)?;
+                for unit in units.iter() {
+                    if unit.entities.iter().any(|entity| entities.contains(entity)) {
+                        *unit_expenses.entry(unit.name.clone()).or_default() += expense.1;
+                    }
+                }
             }
         }
         locks.insert(block_type.clone(), (lock, dataset));
     }
 
-    if feasible {
+    // the units are charged once per query, however many of their datasets
+    // it touches
+    let mut unit_locks = HashMap::new();
+    for (name, cost) in unit_expenses.iter() {
+        let unit_type = block_type!("resource"."privacy_unit".name);
+        let block = access.resource(&unit_type)?;
+        let lock = block.write().map_err(|e| ScopeError::from(e))?;
+        let unit: PrivacyUnit = block
+            .read_with(|unit| unit.clone())?
+            .ok_or(ScopeError::not_found(&unit_type))?
+            .try_into()?;
+        if let Some(budget) = unit.budget.as_ref() {
+            if !cost.fits(budget) {
+                exhausted.push(unit_type.to_string());
+            }
+        }
+        unit_locks.insert(unit_type, (block, lock, unit));
+    }
+
+    if exhausted.is_empty() {
         for (block_type, (mut lock, mut dataset)) in locks.into_iter() {
             let (block, expenses) = closure.get(&block_type).unwrap();
             for (context_key, cost) in expenses.iter() {
                 for binding in dataset.policy_bindings.iter_mut() {
                     if &binding.name == context_key.name() {
                         if let Some(budget) = binding.budget.as_mut() {
-                            budget.used += *cost;
+                            budget.used += cost.epsilon;
+                            budget.used_delta += cost.delta;
                         }
                     }
                 }
@@ -231,36 +339,926 @@ where
             });
             block.push(lock).unwrap();
         }
+        for (unit_type, (block, mut lock, mut unit)) in unit_locks.into_iter() {
+            let cost = unit_expenses.get(&unit.name).unwrap();
+            if let Some(budget) = unit.budget.as_mut() {
+                budget.used += cost.epsilon;
+                budget.used_delta += cost.delta;
+            }
+            *lock = Some(Resource {
+                resource: Some(unit.into()),
+            });
+            block
+                .push(lock)
+                .map_err(|_| ScopeError::changed(&unit_type))?;
+        }
         Ok(())
     } else {
         locks.into_iter().for_each(|(block_type, lock)| {
             closure.get(&block_type).unwrap().0.abort(lock.0).unwrap()
         });
-        Err(Error::new("wip"))
+        for (unit_type, (block, lock, _)) in unit_locks.into_iter() {
+            block
+                .abort(lock)
+                .map_err(|_| ScopeError::changed(&unit_type))?;
+        }
+        exhausted.sort();
+        Err(Error::new(format!(
+            "the query would exceed the privacy budget of {}",
+            exhausted.join(", ")
+        )))
+    }
+}
+
+/// The entities, of the form `dataset.entity`, that the policies of a binding
+/// spend privacy on
+fn charged_entities(dataset: &Dataset, binding: &ApiPolicyBinding) -> Result<HashSet<String>> {
+    let mut entities = HashSet::new();
+    for policy_ref in binding.policies.iter() {
+        let policy_ty = BlockType::parse::<ApiPolicy>(policy_ref)?.0;
+        for policy in dataset.policies.iter() {
+            if policy.block_type()? != policy_ty {
+                continue;
+            }
+            if let Some(PolicyEnum::DifferentialPrivacy(DifferentialPrivacyPolicy {
+                entity, ..
+            })) = policy.policy.as_ref()
+            {
+                entities.insert(format!("{}.{}", dataset.name, entity));
+            }
+        }
     }
+    Ok(entities)
 }
 
-pub fn policies_for_group<A: Access>(access: &A, audience: &str) -> Result<Context<PolicyBinding>> {
+pub fn policies_for_group<A: Access>(
+    access: &A,
+    audience: &str,
+    purpose: Option<&str>,
+) -> Result<Context<PolicyBinding>> {
+    let now = Utc::now();
     let mut context = Context::new();
+
+    // members whose membership of the group lapsed are not subject to any of
+    // its bindings
+    if !in_audience(access, audience, purpose, &now)? {
+        return Ok(context);
+    }
+
     for resource in access.resources(&block_type!("resource"."dataset"."*"))? {
         let dataset = resource.try_downcast::<Dataset>()?;
-        let ctx = dataset_as_policy_context(dataset, audience)?;
-        context.extend(ctx);
+        let ctx = dataset_as_policy_context(dataset, audience, purpose, &now)?;
+        for (context_key, mut binding) in ctx.into_iter() {
+            for policy in binding.policies.iter_mut() {
+                resolve_policy_key(access, policy, audience)?;
+            }
+            context.insert(context_key, binding);
+        }
     }
     Ok(context)
 }
 
+/// Whether the caller is still a member of `audience`, for jobs declaring
+/// `purpose`. Fails if members of the group may not declare `purpose`.
+fn in_audience<A: Access>(
+    access: &A,
+    audience: &str,
+    purpose: Option<&str>,
+    now: &DateTime<Utc>,
+) -> Result<bool> {
+    let user_ty = block_type!("resource"."user".(access.who_am_i()));
+    for group in access.resources(&block_type!("resource"."group".audience))? {
+        let group = group.try_downcast::<Group>()?;
+        if let Some(purpose) = purpose {
+            if !group.purposes.is_empty() && !group.purposes.iter().any(|p| p == purpose) {
+                return Err(Error::new(format!(
+                    "members of `{}` may not declare the purpose `{}`",
+                    audience, purpose
+                )));
+            }
+        }
+        let mut expiring = false;
+        for membership in group.expiring_members.iter() {
+            expiring |= BlockType::parse::<Resource>(&membership.member)?.0 == user_ty;
+        }
+        if expiring && !is_member(&group, &user_ty, now)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Resolves the entities of every exclusion list into the columns identifying
+/// them in their datasets
+pub fn exclusions<A: Access>(access: &A) -> Result<Vec<Exclusion>> {
+    let mut exclusions = Vec::new();
+    for resource in access.resources(&block_type!("resource"."exclusion_list"."*"))? {
+        let list = resource.try_downcast::<ExclusionList>()?;
+        for entity_ref in list.entities.iter() {
+            let mut parts = entity_ref.splitn(2, '.');
+            let (dataset_name, entity_name) = match (parts.next(), parts.next()) {
+                (Some(dataset_name), Some(entity_name)) => (dataset_name, entity_name),
+                _ => {
+                    let err = ScopeError {
+                        kind: ScopeErrorKind::BadObject as i32,
+                        source: list.name.clone(),
+                        description: format!(
+                            "`{}` is not of the form `dataset.entity`",
+                            entity_ref
+                        ),
+                    };
+                    return Err(err.into());
+                }
+            };
+            let dataset_ty = block_type!("resource"."dataset".dataset_name);
+            let dataset = access
+                .resources(&dataset_ty)?
+                .into_iter()
+                .next()
+                .ok_or(ScopeError::not_found(&dataset_ty))?
+                .try_downcast::<Dataset>()?;
+            let entity = match dataset
+                .entities
+                .iter()
+                .find(|declared| declared.name == entity_name)
+            {
+                Some(declared) => {
+                    EntityFields::from_entity(declared).map_err(|err| ScopeError {
+                        kind: ScopeErrorKind::BadObject as i32,
+                        source: entity_ref.to_string(),
+                        description: err.to_string(),
+                    })?
+                }
+                None => EntityFields::from_column(entity_name),
+            };
+            exclusions.push(Exclusion {
+                list: (list.name.clone(), list.version),
+                dataset: dataset_name.to_string(),
+                entity,
+                identifiers: list.identifiers.clone(),
+                table: list
+                    .table
+                    .as_ref()
+                    .map(|table| (table.source.clone(), table.column.clone())),
+            });
+        }
+    }
+    Ok(exclusions)
+}
+
+/// Resolves the consent policies of every dataset into the tables holding the
+/// consents of their entities
+pub fn consents<A: Access>(access: &A) -> Result<Vec<Consent>> {
+    let mut consents = Vec::new();
+    for resource in access.resources(&block_type!("resource"."dataset"."*"))? {
+        let dataset = resource.try_downcast::<Dataset>()?;
+        for policy in dataset.policies.iter() {
+            if let Some(PolicyEnum::Consent(consent)) = policy.policy.as_ref() {
+                let entity = match dataset
+                    .entities
+                    .iter()
+                    .find(|declared| declared.name == consent.entity)
+                {
+                    Some(declared) => {
+                        EntityFields::from_entity(declared).map_err(|err| ScopeError {
+                            kind: ScopeErrorKind::BadObject as i32,
+                            source: consent.name.clone(),
+                            description: err.to_string(),
+                        })?
+                    }
+                    None => EntityFields::from_column(&consent.entity),
+                };
+                consents.push(Consent {
+                    dataset: dataset.name.clone(),
+                    entity,
+                    table: consent.table.clone(),
+                    key: consent.key.clone(),
+                    purpose: consent.purpose.clone(),
+                });
+            }
+        }
+    }
+    Ok(consents)
+}
+
+/// The templates of the datasets restricted to them, with the queries they
+/// authorize for `audience` in jobs declaring `purpose`
+pub fn templates<A: Access>(
+    access: &A,
+    audience: &str,
+    purpose: Option<&str>,
+) -> Result<Vec<Template>> {
+    let now = Utc::now();
+    let audience_ty = block_type!("resource"."group".audience);
+    // datasets stay restricted to their templates for members whose
+    // membership lapsed, but none of the queries are authorized for them
+    let member = in_audience(access, audience, purpose, &now)?;
+    let mut templates = Vec::new();
+    for resource in access.resources(&block_type!("resource"."dataset"."*"))? {
+        let dataset = resource.try_downcast::<Dataset>()?;
+        for policy in dataset.policies.iter() {
+            if let Some(PolicyEnum::Template(template)) = policy.policy.as_ref() {
+                let mut queries = Vec::new();
+                for query in template.templates.iter() {
+                    let grants = grants(
+                        &query.audience,
+                        &query.purposes,
+                        query.validity.as_ref(),
+                        &audience_ty,
+                        purpose,
+                        &now,
+                    )?;
+                    if member && grants {
+                        queries.push(query.sql.clone());
+                    }
+                }
+                templates.push(Template {
+                    dataset: dataset.name.clone(),
+                    queries,
+                });
+            }
+        }
+    }
+    Ok(templates)
+}
+
+fn differencing_detection<A: Access>(
+    access: &A,
+    dataset: &str,
+) -> Result<Option<DifferencingDetection>> {
+    Ok(access
+        .resources(&block_type!("resource"."dataset".dataset))?
+        .into_iter()
+        .next()
+        .map(|resource| resource.try_downcast::<Dataset>())
+        .transpose()?
+        .and_then(|dataset| dataset.differencing)
+        .map(|mut detection| {
+            // an unset history would compare queries with none of the
+            // previous ones
+            if detection.history == 0 {
+                detection.history = 100;
+            }
+            detection
+        }))
+}
+
+/// The datasets on which the query of `fingerprint` is suspected of being
+/// part of a differencing attack, with how they detect them and the smallest
+/// number of conditions it differs by from a previous query of the user
+pub fn detect_differencing<A: Access>(
+    access: &A,
+    fingerprint: &Fingerprint,
+) -> Result<Vec<(String, DifferencingDetection, usize)>> {
+    let history = access
+        .query_history(access.who_am_i())?
+        .clone_inner()
+        .map_err(|e| ScopeError::from(e))?
+        .unwrap_or_default();
+
+    let mut suspected = Vec::new();
+    for dataset in fingerprint.datasets.iter() {
+        let detection = match differencing_detection(access, dataset)? {
+            Some(detection) => detection,
+            None => continue,
+        };
+        let previous = history
+            .fingerprints
+            .get(dataset)
+            .map(|previous| previous.as_slice())
+            .unwrap_or(&[]);
+        let difference = previous
+            .iter()
+            .rev()
+            .take(detection.history as usize)
+            .filter_map(|previous| fingerprint.difference(previous))
+            .filter(|difference| *difference > 0 && *difference as u64 <= detection.max_difference)
+            .min();
+        if let Some(difference) = difference {
+            suspected.push((dataset.clone(), detection, difference));
+        }
+    }
+    Ok(suspected)
+}
+
+/// Adds `fingerprint` to the history of the user on the datasets its query
+/// reads from which detect differencing attacks
+pub fn remember_fingerprint<A: Access>(access: &A, fingerprint: &Fingerprint) -> Result<()> {
+    let mut detections = Vec::new();
+    for dataset in fingerprint.datasets.iter() {
+        if let Some(detection) = differencing_detection(access, dataset)? {
+            detections.push((dataset, detection));
+        }
+    }
+    if detections.is_empty() {
+        return Ok(());
+    }
+
+    let user = access.who_am_i();
+    let block = access.query_history(user)?;
+    let mut lock = block.write().map_err(|e| ScopeError::from(e))?;
+    let mut history = (*lock).clone().unwrap_or_else(|| QueryHistory {
+        user: user.to_string(),
+        ..Default::default()
+    });
+    for (dataset, detection) in detections.into_iter() {
+        let previous = history.fingerprints.entry(dataset.clone()).or_default();
+        previous.push(fingerprint.clone());
+        let expired = previous.len().saturating_sub(detection.history as usize);
+        previous.drain(..expired);
+    }
+    *lock = Some(history);
+    block
+        .push(lock)
+        .map_err(|_| ScopeError::changed(&format!("query history of {}", user)))?;
+    Ok(())
+}
+
+/// The result released for the query with `key`, if the tables it reads from
+/// were not updated since
+pub fn released<A: Access>(
+    access: &A,
+    key: &str,
+    last_updated: &HashMap<String, LastUpdated>,
+) -> Result<Option<Release>> {
+    if !last_updated
+        .values()
+        .all(|last_updated| last_updated.is_known())
+    {
+        return Ok(None);
+    }
+    let release = access
+        .release(key)?
+        .clone_inner()
+        .map_err(|e| ScopeError::from(e))?;
+    Ok(release.filter(|release| release.asset.is_some() && release.last_updated == *last_updated))
+}
+
+/// Keeps the result of a query for it to be released again, replacing the
+/// result of the same query on older data
+pub fn remember_release<A: Access>(access: &A, release: Release) -> Result<()> {
+    let block = access.release(&release.key)?;
+    let mut lock = block.write().map_err(|e| ScopeError::from(e))?;
+    let key = release.key.clone();
+    *lock = Some(release);
+    block
+        .push(lock)
+        .map_err(|_| ScopeError::changed(&format!("release {}", key)))?;
+    Ok(())
+}
+
+/// Appends an entry recording `decision`, taken on behalf of the user, to the
+/// audit log
+pub fn audit<A: Access>(access: &A, decision: AuditDecision) -> Result<()> {
+    let head = access.audit_head()?;
+    let mut lock = head.write().map_err(|e| ScopeError::from(e))?;
+    let AuditHead { sequence, hash } = (*lock).clone().unwrap_or_default();
+
+    let mut entry = AuditEntry {
+        sequence,
+        previous_hash: hash,
+        hash: String::new(),
+        timestamp: Utc::now().to_rfc3339(),
+        user: access.who_am_i().to_string(),
+        decision: Some(decision),
+    };
+    entry.hash = entry.digest();
+    debug!("auditing entry {} ({})", entry.sequence, entry.hash);
+
+    let source = format!("audit entry {}", sequence);
+    let record = access.audit_record(sequence)?;
+    let mut record_lock = record.write().map_err(|e| ScopeError::from(e))?;
+    if record_lock.is_some() {
+        record.abort(record_lock);
+        head.abort(lock);
+        let err = ScopeError {
+            kind: ScopeErrorKind::AlreadyExists as i32,
+            source,
+            description: "audit entries cannot be overwritten".to_string(),
+        };
+        return Err(err.into());
+    }
+    *record_lock = Some(AuditRecord(entry.clone()));
+    if record.push(record_lock).is_err() {
+        head.abort(lock);
+        return Err(ScopeError::changed(&source).into());
+    }
+
+    *lock = Some(AuditHead {
+        sequence: sequence + 1,
+        hash: entry.hash,
+    });
+    if head.push(lock).is_err() {
+        // Another entry took the head in the meantime: take back ours, so
+        // that the chain carries on from the head that was written
+        let mut record_lock = record.write().map_err(|e| ScopeError::from(e))?;
+        *record_lock = None;
+        record
+            .push(record_lock)
+            .map_err(|_| ScopeError::changed(&source))?;
+        return Err(ScopeError::changed("the audit log").into());
+    }
+    Ok(())
+}
+
+/// The entries of the audit log from position `from` on, in order
+pub fn audit_entries<A: Access>(access: &A, from: u64) -> Result<Vec<AuditEntry>> {
+    let mut entries: Vec<_> = access
+        .audit_records()?
+        .into_iter()
+        .map(|AuditRecord(entry)| entry)
+        .filter(|entry| entry.sequence >= from)
+        .collect();
+    entries.sort_by_key(|entry| entry.sequence);
+    Ok(entries)
+}
+
+/// The entries of the audit log timestamped between `from` and `to`, in order.
+/// Entries are appended in the order of their timestamps, so the first of them
+/// is bisected for instead of loading the whole log.
+pub fn audit_entries_between<A: Access>(
+    access: &A,
+    from: &DateTime<Utc>,
+    to: &DateTime<Utc>,
+) -> Result<Vec<AuditEntry>> {
+    let entry_at = |sequence: u64| -> Result<AuditEntry> {
+        let record = access
+            .audit_record(sequence)?
+            .clone_inner()
+            .map_err(|e| ScopeError::from(e))?
+            .ok_or_else(|| ScopeError::not_found(&format!("audit entry {}", sequence)))?;
+        Ok(record.0)
+    };
+    let timestamp_of = |entry: &AuditEntry| {
+        DateTime::parse_from_rfc3339(&entry.timestamp)
+            .map(|timestamp| timestamp.with_timezone(&Utc))
+            .ok()
+    };
+
+    let AuditHead { sequence: end, .. } = access
+        .audit_head()?
+        .clone_inner()
+        .map_err(|e| ScopeError::from(e))?
+        .unwrap_or_default();
+    let (mut start, mut high) = (0, end);
+    while start < high {
+        let middle = start + (high - start) / 2;
+        if timestamp_of(&entry_at(middle)?).map_or(true, |timestamp| &timestamp < from) {
+            start = middle + 1;
+        } else {
+            high = middle;
+        }
+    }
+
+    let mut entries = Vec::new();
+    for sequence in start..end {
+        let entry = entry_at(sequence)?;
+        if timestamp_of(&entry).map_or(false, |timestamp| &timestamp > to) {
+            break;
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Fetches the key material of policies that reference a secret, and of their
+/// watermark. Pseudonyms of per-audience policies are bound to the audience,
+/// so that they cannot be joined across groups.
+fn resolve_policy_key<A: Access>(access: &A, policy: &mut Policy, audience: &str) -> Result<()> {
+    if let Some(watermark) = policy.watermark.as_mut() {
+        watermark.keyset = Some(secret_keyset(access, &watermark.key)?);
+    }
+    match &policy.policy {
+        policy::Policy::Hash(HashPolicy {
+            key: Some(hash_key),
+            ..
+        }) => {
+            let keyset = secret_keyset(access, &hash_key.secret)?;
+            let context = if hash_key.per_audience {
+                audience.to_string()
+            } else {
+                String::new()
+            };
+            policy.keying = Some(Keying::Keyset { keyset, context });
+        }
+        policy::Policy::Tokenize(tokenize) => {
+            let keyset = secret_keyset(access, &tokenize.key)?;
+            let context = tokenize.context.clone();
+            policy.keying = Some(Keying::Keyset { keyset, context });
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// The keyset of a secret kept in a KMS. Keys are never resolved from plain
+/// text secrets, as they would end up in the queries sent to backends.
+fn secret_keyset<A: Access>(access: &A, secret_ref: &str) -> Result<WrappedKeyset> {
+    let secret_ty = BlockType::parse::<Resource>(secret_ref)?.0;
+    let secret = access
+        .secret(&secret_ty)?
+        .ok_or(ScopeError::not_found(&secret_ty))?;
+    if secret.kms.is_empty() || secret.wrapped.is_empty() {
+        return Err(Error::new(format!(
+            "the secret `{}` needs to be wrapped by a KMS key to be used as a key",
+            secret_ref
+        )));
+    }
+    let keyset = base64::decode(&secret.wrapped).map_err(|e| {
+        Error::new(format!(
+            "the wrapped secret `{}` is not valid base64: {}",
+            secret_ref, e
+        ))
+    })?;
+    Ok(WrappedKeyset {
+        kms_key: secret.kms,
+        keyset,
+    })
+}
+
+/// Reverses tokens produced by a tokenize policy of a dataset. Only members of
+/// the policy's detokenizer groups are allowed to do so and every attempt is
+/// recorded, whether it is authorized or not.
+pub async fn detokenize<A: Access>(
+    access: &A,
+    dataset_name: &str,
+    policy_ref: &str,
+    tokens: Vec<String>,
+    justification: &str,
+) -> Result<Vec<String>> {
+    let mut decision = DetokenizeDecision {
+        dataset: dataset_name.to_string(),
+        policy: policy_ref.to_string(),
+        tokens: tokens.clone(),
+        justification: justification.to_string(),
+        ..Default::default()
+    };
+
+    let authorized = authorize_detokenize(access, dataset_name, policy_ref, justification);
+    match authorized.as_ref() {
+        Ok(_) => decision.outcome = "authorized".to_string(),
+        Err(err) => {
+            decision.outcome = "refused".to_string();
+            decision.reason = err.to_string();
+        }
+    }
+    access.audit(AuditDecision::Detokenize(decision))?;
+    let (tokenize, backend_ty, keyset) = authorized?;
+
+    if tokens.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    access
+        .backend(&backend_ty)?
+        .detokenize(&keyset, &tokenize.context, &tokens)
+        .await
+}
+
+/// The tokenize policy `policy_ref` of the dataset `dataset_name`, the backend
+/// of the dataset and the keyset of the policy, if the user may reverse its
+/// tokens
+fn authorize_detokenize<A: Access>(
+    access: &A,
+    dataset_name: &str,
+    policy_ref: &str,
+    justification: &str,
+) -> Result<(TokenizePolicy, BlockType, WrappedKeyset)> {
+    let dataset_ty = block_type!("resource"."dataset".dataset_name);
+    let dataset = access
+        .resources(&dataset_ty)?
+        .into_iter()
+        .next()
+        .ok_or(ScopeError::not_found(&dataset_ty))?
+        .try_downcast::<Dataset>()?;
+
+    let policy_ty = BlockType::parse::<ApiPolicy>(policy_ref)?.0;
+    let mut tokenize = None;
+    for policy in dataset.policies.iter() {
+        if policy.block_type()? == policy_ty {
+            tokenize = Some(policy.clone().try_downcast::<TokenizePolicy>()?);
+        }
+    }
+    let tokenize = tokenize.ok_or(ScopeError::not_found(&policy_ty))?;
+
+    // Group membership is checked directly, as users are not privileged
+    // enough to look themselves up
+    let user_ty = block_type!("resource"."user".(access.who_am_i()));
+    let now = Utc::now();
+    let mut authorized = false;
+    for group_ref in tokenize.detokenizers.iter() {
+        let group_ty = BlockType::parse::<Resource>(group_ref)?.0;
+        for group in access.resources(&group_ty)?.into_iter() {
+            if is_member(&group.try_downcast::<Group>()?, &user_ty, &now)? {
+                authorized = true;
+            }
+        }
+    }
+    if !authorized {
+        return Err(access.forbidden().into());
+    }
+
+    if justification.is_empty() {
+        return Err(Error::new(
+            "reversing tokens requires a justification".to_string(),
+        ));
+    }
+
+    let backend_ty = dataset_backend(&dataset)?;
+    let keyset = secret_keyset(access, &tokenize.key)?;
+    Ok((tokenize, backend_ty, keyset))
+}
+
+/// The backend the data of `dataset` is in
+fn dataset_backend(dataset: &Dataset) -> Result<BlockType> {
+    // All the data of a dataset is expected to be in the same backend
+    let backend_ty = dataset
+        .data
+        .first()
+        .and_then(|data| DATA_RE.captures_iter(data).next())
+        .and_then(|captures| Some(captures.name("resource")?.as_str().to_string()))
+        .ok_or(ScopeError {
+            kind: ScopeErrorKind::BadSplat as i32,
+            source: dataset.name.clone(),
+            ..Default::default()
+        })?;
+    Ok(BlockType::parse::<Resource>(&backend_ty)?.0)
+}
+
+/// The number of characters of pseudonyms marked by `watermarking`
+fn watermark_width(watermarking: &Watermarking) -> u64 {
+    if watermarking.width == 0 {
+        2
+    } else {
+        watermarking.width
+    }
+}
+
+/// The jobs a leaked extract of watermarked results likely comes from, with
+/// the number of its `values` carrying their marks. Only the jobs authorized
+/// on watermarked datasets between `from` and `to` are looked for.
+pub async fn trace<A: Access>(
+    access: &A,
+    values: Vec<String>,
+    from: &str,
+    to: &str,
+) -> Result<Vec<TracedJob>> {
+    let parse_bound = |bound: &str| {
+        DateTime::parse_from_rfc3339(bound)
+            .map(|bound| bound.with_timezone(&Utc))
+            .map_err(|e| Error::new(format!("`{}` is not an RFC 3339 timestamp: {}", bound, e)))
+    };
+    let from = parse_bound(from)?;
+    let to = if to.is_empty() {
+        Utc::now()
+    } else {
+        parse_bound(to)?
+    };
+
+    let entries = audit_entries_between(access, &from, &to)?;
+
+    let mut traced: HashMap<String, TracedJob> = HashMap::new();
+    for resource in access.resources(&block_type!("resource"."dataset"."*"))? {
+        let dataset = resource.try_downcast::<Dataset>()?;
+        let watermarking = match dataset.watermarking.as_ref() {
+            Some(watermarking) => watermarking,
+            None => continue,
+        };
+        let width = watermark_width(watermarking);
+
+        let jobs = traceable_jobs(&entries, &dataset.name, &from, &to);
+        let marked: Vec<_> = values
+            .iter()
+            .filter_map(|value| split_mark(value, width as usize))
+            .map(|(stem, mark)| (stem.to_string(), mark.to_string()))
+            .collect();
+        if jobs.is_empty() || marked.is_empty() {
+            continue;
+        }
+
+        let seeds: Vec<_> = jobs.iter().map(|(job_id, _)| job_id.clone()).collect();
+        let keyset = secret_keyset(access, &watermarking.key)?;
+        let counts = access
+            .backend(&dataset_backend(&dataset)?)?
+            .watermark_matches(&keyset, width, &seeds, &marked)
+            .await?;
+
+        for ((job_id, user), matches) in jobs.into_iter().zip(counts.into_iter()) {
+            traced
+                .entry(job_id.clone())
+                .or_insert(TracedJob {
+                    job_id,
+                    user,
+                    matches: 0,
+                })
+                .matches += matches;
+        }
+    }
+
+    let mut traced: Vec<_> = traced
+        .into_iter()
+        .map(|(_, job)| job)
+        .filter(|job| job.matches > 0)
+        .collect();
+    traced.sort_by(|a, b| {
+        b.matches
+            .cmp(&a.matches)
+            .then_with(|| a.job_id.cmp(&b.job_id))
+    });
+    Ok(traced)
+}
+
+/// The jobs authorized between `from` and `to` on the dataset `dataset_name`,
+/// along with the users they were authorized for
+fn traceable_jobs(
+    entries: &[AuditEntry],
+    dataset_name: &str,
+    from: &DateTime<Utc>,
+    to: &DateTime<Utc>,
+) -> Vec<(String, String)> {
+    let mut jobs: Vec<(String, String)> = Vec::new();
+    for entry in entries.iter() {
+        let decision = match entry.decision.as_ref() {
+            Some(AuditDecision::Query(decision)) if decision.outcome == "authorized" => decision,
+            _ => continue,
+        };
+        let in_window = DateTime::parse_from_rfc3339(&entry.timestamp)
+            .map(|timestamp| timestamp.with_timezone(&Utc))
+            .map_or(false, |timestamp| from <= &timestamp && &timestamp <= to);
+        let on_dataset = decision
+            .bindings
+            .iter()
+            .any(|binding| binding.split('.').next() == Some(dataset_name));
+        if in_window && on_dataset && !jobs.iter().any(|(job_id, _)| job_id == &decision.job_id) {
+            jobs.push((decision.job_id.clone(), entry.user.clone()));
+        }
+    }
+    jobs
+}
+
+/// Splits `value` into the stem and the mark of a watermarked pseudonym, if it
+/// may be one. Pseudonyms are hex or base64, hence ASCII.
+fn split_mark(value: &str, width: usize) -> Option<(&str, &str)> {
+    if value.len() <= width || !value.is_ascii() {
+        None
+    } else {
+        Some(value.split_at(value.len() - width))
+    }
+}
+
+/// Whether `validity` is in force at `now`.
+fn is_in_force(
+    validity: &ValidityWindow,
+    now: &DateTime<Utc>,
+) -> std::result::Result<bool, ScopeError> {
+    let parse_bound = |bound: &str| {
+        if bound.is_empty() {
+            return Ok(None);
+        }
+        DateTime::parse_from_rfc3339(bound)
+            .map(|at| Some(at.with_timezone(&Utc)))
+            .map_err(|err| ScopeError {
+                kind: ScopeErrorKind::BadObject as i32,
+                source: bound.to_string(),
+                description: err.to_string(),
+            })
+    };
+    let valid_from = parse_bound(&validity.valid_from)?;
+    let valid_until = parse_bound(&validity.valid_until)?;
+    Ok(valid_from.map(|from| &from <= now).unwrap_or(true)
+        && valid_until.map(|until| now < &until).unwrap_or(true))
+}
+
+/// Whether `member` belongs to `group` at `now`, either permanently or
+/// through a membership which is in force.
+fn is_member(group: &Group, member: &BlockType, now: &DateTime<Utc>) -> Result<bool> {
+    for permanent in group.members.iter() {
+        // FIXME: should be checking tokenstream is empty
+        if &BlockType::parse::<Resource>(permanent)?.0 == member {
+            return Ok(true);
+        }
+    }
+    for membership in group.expiring_members.iter() {
+        if &BlockType::parse::<Resource>(&membership.member)?.0 == member {
+            let in_force = membership
+                .validity
+                .as_ref()
+                .map(|validity| is_in_force(validity, now))
+                .transpose()?
+                .unwrap_or(true);
+            if in_force {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// Whether something granted to `groups`, for `purposes` and within
+/// `validity`, is granted to `audience` for jobs declaring `purpose`
+fn grants(
+    groups: &[String],
+    purposes: &[String],
+    validity: Option<&ValidityWindow>,
+    audience: &BlockType,
+    purpose: Option<&str>,
+    now: &DateTime<Utc>,
+) -> std::result::Result<bool, ScopeError> {
+    let target_audience = groups
+        .iter()
+        .map(|aud| Ok(BlockType::parse::<Resource>(aud)?.0))
+        .collect::<std::result::Result<HashSet<_>, TypeError>>()?;
+
+    // grants scoped to purposes only apply to jobs which declared one of
+    // them
+    let for_purpose = purposes.is_empty()
+        || purpose
+            .map(|purpose| purposes.iter().any(|p| p == purpose))
+            .unwrap_or(false);
+
+    let in_force = validity
+        .map(|validity| is_in_force(validity, now))
+        .transpose()?
+        .unwrap_or(true);
+
+    Ok(target_audience.contains(audience) && for_purpose && in_force)
+}
+
 fn dataset_as_policy_context(
     dataset: Dataset,
     audience: &str,
+    purpose: Option<&str>,
+    now: &DateTime<Utc>,
 ) -> std::result::Result<Context<PolicyBinding>, ScopeError> {
     let audience_ty = block_type!("resource"."group".audience);
     let dataset_name = &dataset.name;
+    let entities = &dataset.entities;
+    let domains = dataset
+        .domains
+        .as_ref()
+        .map(DeclaredDomains::from_public_domains)
+        .transpose()
+        .map_err(|err| ScopeError {
+            kind: ScopeErrorKind::BadObject as i32,
+            source: dataset_name.to_string(),
+            description: err.to_string(),
+        })?
+        .unwrap_or_default();
+    let watermark = dataset
+        .watermarking
+        .as_ref()
+        .map(|watermarking| match watermarking.mode.as_str() {
+            _ if watermarking.key.is_empty() => Err(ScopeError {
+                kind: ScopeErrorKind::BadObject as i32,
+                source: dataset_name.to_string(),
+                description: "watermarks need to be keyed".to_string(),
+            }),
+            "" | "pseudonyms" => Ok(Watermark {
+                // seeded by each job the policies are used in
+                seed: String::new(),
+                width: watermark_width(watermarking),
+                key: watermarking.key.clone(),
+                keyset: None,
+            }),
+            _ => Err(ScopeError {
+                kind: ScopeErrorKind::BadObject as i32,
+                source: dataset_name.to_string(),
+                description: "the mode of watermarking needs to be `pseudonyms`".to_string(),
+            }),
+        })
+        .transpose()?;
 
     let policies_scope = dataset
         .policies
         .into_iter()
-        .map(|policy| Ok((policy.block_type()?, Policy(policy.try_unwrap()?))))
+        .map(|policy| {
+            let policy_ty = policy.block_type()?;
+            let mut policy = Policy::new(policy.try_unwrap()?);
+            // DP and aggregation policies protect entities, which may be
+            // declared by the dataset
+            policy.entity = match &policy.policy {
+                PolicyEnum::DifferentialPrivacy(DifferentialPrivacyPolicy { entity, .. })
+                | PolicyEnum::Aggregation(AggregationPolicy { entity, .. }) => entities
+                    .iter()
+                    .find(|declared| &declared.name == entity)
+                    .map(EntityFields::from_entity)
+                    .transpose()
+                    .map_err(|err| ScopeError {
+                        kind: ScopeErrorKind::BadObject as i32,
+                        source: entity.to_string(),
+                        description: err.to_string(),
+                    })?,
+                _ => None,
+            };
+            policy.domains = domains.clone();
+            policy.watermark = watermark.clone();
+            policy.parse_templates().map_err(|err| ScopeError {
+                kind: ScopeErrorKind::BadObject as i32,
+                source: policy_ty.to_string(),
+                description: err.to_string(),
+            })?;
+            Ok((policy_ty, policy))
+        })
         .collect::<std::result::Result<Scope<Policy>, ScopeError>>()?;
 
     let mut ctx = Context::new();
@@ -268,16 +1266,17 @@ fn dataset_as_policy_context(
     for binding in dataset.policy_bindings.into_iter() {
         let name = &binding.name;
 
-        let target_audience = binding
-            .groups
-            .into_iter()
-            .map(|aud| Ok(BlockType::parse::<Resource>(&aud)?.0))
-            .collect::<std::result::Result<HashSet<_>, TypeError>>()?;
-
-        if target_audience.contains(&audience_ty) {
+        if grants(
+            &binding.groups,
+            &binding.purposes,
+            binding.validity.as_ref(),
+            &audience_ty,
+            purpose,
+            now,
+        )? {
             let mut policies = Vec::new();
-            for policy_ref in binding.policies.into_iter() {
-                let policy_type = BlockType::parse::<ApiPolicy>(&policy_ref)?.0;
+            for policy_ref in binding.policies.iter() {
+                let policy_type = BlockType::parse::<ApiPolicy>(policy_ref)?.0;
 
                 let policy = policies_scope
                     .get(&policy_type)
@@ -289,6 +1288,7 @@ fn dataset_as_policy_context(
 
             let binding = PolicyBinding {
                 policies,
+                references: binding.policies,
                 priority: binding.priority,
                 budget: binding.budget,
             };
@@ -302,3 +1302,162 @@ fn dataset_as_policy_context(
 
     Ok(ctx)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::state::tests::read_manifest;
+
+    use chrono::TimeZone;
+
+    fn window(valid_from: &str, valid_until: &str) -> ValidityWindow {
+        ValidityWindow {
+            valid_from: valid_from.to_string(),
+            valid_until: valid_until.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn validity_windows() {
+        let now = Utc.ymd(2021, 6, 1).and_hms(0, 0, 0);
+        assert!(is_in_force(&window("", ""), &now).unwrap());
+        assert!(is_in_force(
+            &window("2021-01-01T00:00:00Z", "2022-01-01T00:00:00Z"),
+            &now
+        )
+        .unwrap());
+        assert!(!is_in_force(&window("", "2021-06-01T00:00:00Z"), &now).unwrap());
+        assert!(!is_in_force(&window("2021-06-01T00:00:01Z", ""), &now).unwrap());
+
+        // bounds need to be RFC 3339 timestamps
+        assert!(is_in_force(&window("2021-01-01", ""), &now).is_err());
+        assert!(is_in_force(&window("", "next year"), &now).is_err());
+    }
+
+    #[test]
+    fn expiring_memberships() {
+        let now = Utc.ymd(2021, 6, 1).and_hms(0, 0, 0);
+        let group = Group {
+            name: "contractors".to_string(),
+            members: vec!["resource.user.alice".to_string()],
+            expiring_members: vec![
+                GroupMembership {
+                    member: "resource.user.bob".to_string(),
+                    validity: Some(window("", "2022-01-01T00:00:00Z")),
+                    ..Default::default()
+                },
+                GroupMembership {
+                    member: "resource.user.carol".to_string(),
+                    validity: Some(window("", "2021-01-01T00:00:00Z")),
+                    ..Default::default()
+                },
+                GroupMembership {
+                    member: "resource.user.dave".to_string(),
+                    validity: Some(window("tomorrow", "")),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let is = |user: &str| is_member(&group, &block_type!("resource"."user".user), &now);
+        assert!(is("alice").unwrap());
+        assert!(is("bob").unwrap());
+        // carol's membership has lapsed
+        assert!(!is("carol").unwrap());
+        assert!(!is("eve").unwrap());
+        assert!(is("dave").is_err());
+    }
+
+    #[test]
+    fn bindings_apply_within_their_validity() {
+        let dataset = read_manifest()
+            .into_iter()
+            .filter_map(|resource| resource.try_downcast::<Dataset>().ok())
+            .find(|dataset| dataset.name == "patient_data")
+            .unwrap();
+        let bindings_at = |now: DateTime<Utc>| {
+            dataset_as_policy_context(dataset.clone(), "wheel", None, &now)
+                .unwrap()
+                .into_iter()
+                .map(|(key, _)| key.name().to_string())
+                .collect::<HashSet<_>>()
+        };
+        // the agreement was in force until 2020
+        assert!(bindings_at(Utc.ymd(2019, 6, 1).and_hms(0, 0, 0)).contains("expired_agreement"));
+        assert!(!bindings_at(Utc::now()).contains("expired_agreement"));
+        // bindings without a validity window are always in force
+        assert!(bindings_at(Utc::now()).contains("wheel_policies"));
+    }
+
+    fn query_entry(timestamp: &str, job_id: &str, bindings: &[&str], outcome: &str) -> AuditEntry {
+        AuditEntry {
+            timestamp: timestamp.to_string(),
+            user: "alice".to_string(),
+            decision: Some(AuditDecision::Query(QueryDecision {
+                job_id: job_id.to_string(),
+                bindings: bindings.iter().map(|binding| binding.to_string()).collect(),
+                outcome: outcome.to_string(),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn traceable_jobs() {
+        let entries = vec![
+            query_entry(
+                "2021-05-01T00:00:00Z",
+                "early",
+                &["patient_data.wheel_policies"],
+                "authorized",
+            ),
+            query_entry(
+                "2021-06-01T00:00:00Z",
+                "marked",
+                &["patient_data.wheel_policies"],
+                "authorized",
+            ),
+            query_entry(
+                "2021-06-01T00:00:00Z",
+                "refused",
+                &["patient_data.wheel_policies"],
+                "refused",
+            ),
+            query_entry(
+                "2021-06-01T00:00:00Z",
+                "released",
+                &["patient_data.wheel_policies"],
+                "released",
+            ),
+            query_entry(
+                "2021-06-01T00:00:00Z",
+                "unmarked",
+                &["other_data.wheel_policies"],
+                "authorized",
+            ),
+            query_entry(
+                "2021-08-01T00:00:00Z",
+                "late",
+                &["patient_data.wheel_policies"],
+                "authorized",
+            ),
+        ];
+        let from = Utc.ymd(2021, 5, 15).and_hms(0, 0, 0);
+        let to = Utc.ymd(2021, 7, 1).and_hms(0, 0, 0);
+        // only jobs authorized on the dataset within the window are traced
+        assert_eq!(
+            traceable_jobs(&entries, "patient_data", &from, &to),
+            vec![("marked".to_string(), "alice".to_string())]
+        );
+        assert!(traceable_jobs(&entries, "patient", &from, &to).is_empty());
+    }
+
+    #[test]
+    fn marks_are_split_from_values() {
+        assert_eq!(split_mark("0123abcd", 2), Some(("0123ab", "cd")));
+        assert_eq!(split_mark("cd", 2), None);
+        assert_eq!(split_mark("0123abçd", 2), None);
+    }
+}
@@ -0,0 +1,28 @@
+use crate::common::*;
+
+use crate::backends::LastUpdated;
+use crate::job::Asset;
+
+/// The result of a differentially private query, returned again when the
+/// same query runs on unchanged data so that it does not expend more budget
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Release {
+    /// Digest of the optimized query and of the closures it was optimized with
+    pub key: String,
+    /// When the tables the query reads from were last updated, by table
+    pub last_updated: HashMap<String, LastUpdated>,
+    /// The released result, once the query has run
+    pub asset: Option<Asset>,
+}
+
+impl Block for Release {
+    fn block_type(&self) -> std::result::Result<BlockType, parallax_api::swamp::TypeError> {
+        Ok(block_type!("release".(&self.key)))
+    }
+
+    fn parse_block_type(
+        stream: TokenStream,
+    ) -> std::result::Result<(BlockType, TokenStream), parallax_api::swamp::TypeError> {
+        stream.parse("release")?.take()?.done()
+    }
+}
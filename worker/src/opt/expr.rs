@@ -23,7 +23,7 @@ macro_rules! sql_parser_expr {
 }
 
 copy_ast_enum!(
-    #[derive(Serialize, Deserialize, Debug, Clone,)]
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq,)]
     pub enum ast::BinaryOperator as BinaryOperator {
         Plus,
         Minus,
@@ -43,13 +43,16 @@ copy_ast_enum!(
     }
 );
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum LiteralValue {
     Long(i64),
     Boolean(bool),
     Double(f64),
     StringLiteral(String),
     Null,
+    /// A placeholder of a query template, named, which stands for any
+    /// literal of its type
+    Placeholder(String, DataType),
 }
 
 impl<'a> TryFrom<&'a ast::Value> for LiteralValue {
@@ -63,13 +66,35 @@ impl<'a> TryFrom<&'a ast::Value> for LiteralValue {
             ast::Value::Boolean(b) => Ok(LiteralValue::Boolean(*b)),
             ast::Value::Null => Ok(LiteralValue::Null),
             ast::Value::SingleQuotedString(s) => Ok(LiteralValue::StringLiteral(s.to_string())),
+            // only templates have placeholders
+            ast::Value::NationalStringLiteral(..) => Err(ValidateError::NotSupported(format!(
+                "national string literal {}",
+                value
+            ))),
             _ => Err(ValidateError::Wip(format!("literal {}", value))),
         }
     }
 }
 
+impl LiteralValue {
+    /// The placeholder written `N'name:type'` in a validated template
+    pub fn placeholder(placeholder: &str) -> ValidateResult<Self> {
+        let mut parts = placeholder.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some(name), Some(ty)) => Ok(LiteralValue::Placeholder(
+                name.to_string(),
+                DataType::from_str(ty)?,
+            )),
+            _ => Err(ValidateError::InvalidLiteral(format!(
+                "placeholder {}",
+                placeholder
+            ))),
+        }
+    }
+}
+
 copy_ast_enum!(
-    #[derive(Serialize, Deserialize, Debug, Clone,)]
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq,)]
     pub enum ast::UnaryOperator as UnaryOperator {
         Plus,
         Minus,
@@ -77,6 +102,18 @@ copy_ast_enum!(
     }
 );
 
+copy_ast_enum!(
+    #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq,)]
+    pub enum ast::DateTimeField as DateTimeField {
+        Year,
+        Month,
+        Day,
+        Hour,
+        Minute,
+        Second,
+    }
+);
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub enum FunctionName {
     Sum,
@@ -86,6 +123,9 @@ pub enum FunctionName {
     Max,
     Min,
     Concat,
+    Median,
+    /// `QUANTILE(expr, q)`, for `q` between 0 and 1
+    Quantile,
 }
 
 impl<'a> TryFrom<&'a ast::ObjectName> for FunctionName {
@@ -114,6 +154,8 @@ impl std::fmt::Display for FunctionName {
                 Self::Max => "MAX",
                 Self::Min => "MIN",
                 Self::Concat => "CONCAT",
+                Self::Median => "MEDIAN",
+                Self::Quantile => "QUANTILE",
             }
         )
     }
@@ -130,30 +172,384 @@ impl FromStr for FunctionName {
             "MAX" => Ok(FunctionName::Max),
             "MIN" => Ok(FunctionName::Min),
             "CONCAT" => Ok(FunctionName::Concat),
+            "MEDIAN" => Ok(FunctionName::Median),
+            "QUANTILE" => Ok(FunctionName::Quantile),
             _ => Err(ValidateError::InvalidFunctionName(s.to_string())),
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum HashAlgorithm {
-    SHA256, // FIXME
+    SHA256,
+    SHA512,
 }
 
 impl std::default::Default for HashAlgorithm {
     fn default() -> Self {
-        Self::SHA256 // FIXME
+        Self::SHA256
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = ValidateError;
+    fn from_str(s: &str) -> ValidateResult<Self> {
+        match s.to_lowercase().as_ref() {
+            "" | "sha256" | "hmac_sha256" => Ok(Self::SHA256),
+            "sha512" | "hmac_sha512" => Ok(Self::SHA512),
+            _ => Err(ValidateError::Expected(format!(
+                "one of `sha256` or `sha512`, got `{}`",
+                s
+            ))),
+        }
+    }
+}
+
+/// A keyset encrypted by a key of a KMS. Backends unwrap it themselves, so
+/// that no key material ever shows up in queries.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct WrappedKeyset {
+    /// The URI of the KMS key the keyset is wrapped with
+    pub kms_key: String,
+    pub keyset: Vec<u8>,
+}
+
+/// What pseudonyms are keyed with
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum Keying {
+    /// A salt prefixing the values, for plain hashes
+    Salt(Vec<u8>),
+    /// Values are encrypted deterministically with `keyset` before being
+    /// hashed, bound to `context`
+    Keyset {
+        keyset: WrappedKeyset,
+        context: String,
+    },
+}
+
+/// A mark replacing the last characters of pseudonyms, to trace them back to
+/// the job which produced them
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Watermark {
+    /// What the mark is derived from, along with the rest of the pseudonym.
+    /// This is the id of the job the pseudonyms are produced by.
+    pub seed: String,
+    /// The number of characters of the pseudonyms replaced by the mark
+    pub width: u64,
+    /// The secret the mark is keyed with
+    pub key: String,
+    /// The keyset of `key`, once resolved. Pseudonyms are not marked before.
+    pub keyset: Option<WrappedKeyset>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashEncoding {
+    Base64,
+    Hex,
+}
+
+impl std::default::Default for HashEncoding {
+    fn default() -> Self {
+        Self::Base64
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+impl FromStr for HashEncoding {
+    type Err = ValidateError;
+    fn from_str(s: &str) -> ValidateResult<Self> {
+        match s.to_lowercase().as_ref() {
+            "" | "base64" => Ok(Self::Base64),
+            "hex" => Ok(Self::Hex),
+            _ => Err(ValidateError::Expected(format!(
+                "one of `base64` or `hex`, got `{}`",
+                s
+            ))),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
 pub enum Distribution {
     Laplace { mean: f64, variance: f64 }, // FIXME
 }
 
+/// How a string value is masked.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Masking {
+    KeepFirst {
+        count: u64,
+        mask: String,
+    },
+    KeepLast {
+        count: u64,
+        mask: String,
+    },
+    RegexReplace {
+        pattern: String,
+        replacement: String,
+    },
+    /// Every `#` reveals the character at that position, any other character
+    /// is emitted as is.
+    Format(String),
+}
+
+impl Masking {
+    /// Split a format mask into its literal runs (`Err`) and the (1-indexed)
+    /// positions it reveals (`Ok`).
+    pub fn format_pieces(format: &str) -> Vec<Result<usize, String>> {
+        let mut pieces = Vec::new();
+        let mut literal = String::new();
+        for (i, c) in format.chars().enumerate() {
+            if c == '#' {
+                if !literal.is_empty() {
+                    pieces.push(Err(literal.split_off(0)));
+                }
+                pieces.push(Ok(i + 1));
+            } else {
+                literal.push(c);
+            }
+        }
+        if !literal.is_empty() {
+            pieces.push(Err(literal));
+        }
+        pieces
+    }
+}
+
+/// Checks that `pattern` means the same under RE2, which backends run regular
+/// expressions with, as it does here. Anything the `regex` crate cannot parse
+/// is rejected, as well as the constructs RE2 does not support or reads
+/// differently: nested classes and class set operations, flags other than
+/// `imsU`, repetitions of more than 1000 and the Perl classes and word
+/// boundaries, which are Unicode-aware here and ASCII-only in RE2.
+pub fn validate_re2(pattern: &str) -> ValidateResult<()> {
+    use regex_syntax::ast::{
+        Ast, Class, ClassSet, ClassSetItem, Flag, FlagsItemKind, GroupKind, RepetitionKind,
+        RepetitionRange,
+    };
+
+    fn unsupported(what: &str) -> ValidateError {
+        ValidateError::Expected(format!(
+            "a regular expression RE2 supports, but {} are not",
+            what
+        ))
+    }
+
+    fn check_flags(flags: &regex_syntax::ast::Flags) -> ValidateResult<()> {
+        for item in flags.items.iter() {
+            match &item.kind {
+                FlagsItemKind::Flag(Flag::Unicode)
+                | FlagsItemKind::Flag(Flag::IgnoreWhitespace) => {
+                    return Err(unsupported("flags other than `imsU`"))
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn check_set_item(item: &ClassSetItem) -> ValidateResult<()> {
+        match item {
+            ClassSetItem::Perl(_) => Err(unsupported("Perl classes such as `\\d`")),
+            ClassSetItem::Bracketed(_) => Err(unsupported("nested classes")),
+            ClassSetItem::Union(union) => union.items.iter().map(check_set_item).collect(),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_set(set: &ClassSet) -> ValidateResult<()> {
+        match set {
+            ClassSet::Item(item) => check_set_item(item),
+            ClassSet::BinaryOp(_) => Err(unsupported("class set operations")),
+        }
+    }
+
+    fn check(ast: &Ast) -> ValidateResult<()> {
+        match ast {
+            Ast::Flags(set_flags) => check_flags(&set_flags.flags),
+            Ast::Assertion(assertion) => match assertion.kind {
+                regex_syntax::ast::AssertionKind::WordBoundary
+                | regex_syntax::ast::AssertionKind::NotWordBoundary => {
+                    Err(unsupported("word boundaries"))
+                }
+                _ => Ok(()),
+            },
+            Ast::Class(Class::Perl(_)) => Err(unsupported("Perl classes such as `\\d`")),
+            Ast::Class(Class::Bracketed(bracketed)) => check_set(&bracketed.kind),
+            Ast::Class(Class::Unicode(_)) => Ok(()),
+            Ast::Repetition(repetition) => {
+                let max = match &repetition.op.kind {
+                    RepetitionKind::Range(RepetitionRange::Exactly(n))
+                    | RepetitionKind::Range(RepetitionRange::AtLeast(n))
+                    | RepetitionKind::Range(RepetitionRange::Bounded(_, n)) => *n,
+                    _ => 0,
+                };
+                if max > 1000 {
+                    return Err(unsupported("repetitions of more than 1000"));
+                }
+                check(&repetition.ast)
+            }
+            Ast::Group(group) => {
+                if let GroupKind::NonCapturing(flags) = &group.kind {
+                    check_flags(flags)?;
+                }
+                check(&group.ast)
+            }
+            Ast::Alternation(alternation) => alternation.asts.iter().map(check).collect(),
+            Ast::Concat(concat) => concat.asts.iter().map(check).collect(),
+            Ast::Empty(_) | Ast::Literal(_) | Ast::Dot(_) => Ok(()),
+        }
+    }
+
+    let ast = regex_syntax::ast::parse::Parser::new()
+        .parse(pattern)
+        .map_err(|err| ValidateError::Expected(format!("a valid regular expression: {}", err)))?;
+    check(&ast)?;
+    // the translation catches what the syntax alone does not, e.g. invalid
+    // ranges
+    regex_syntax::hir::translate::Translator::new()
+        .translate(pattern, &ast)
+        .map_err(|err| ValidateError::Expected(format!("a valid regular expression: {}", err)))?;
+    Ok(())
+}
+
+/// A detector of personal information in free text, whose detections are
+/// replaced by a marker naming it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Detector {
+    pub name: String,
+    /// A regular expression in the RE2 syntax
+    pub pattern: String,
+}
+
+impl Detector {
+    /// The names of the built-in detectors, in the order they are applied in
+    pub const BUILTINS: [&'static str; 4] = ["email", "national_id", "date", "phone"];
+
+    /// The built-in detector called `name`. Their patterns steer clear of
+    /// backslashes, which would need escaping in SQL string literals.
+    pub fn builtin(name: &str) -> ValidateResult<Self> {
+        let pattern = match name {
+            "email" => "[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+[.][A-Za-z]{2,}",
+            // US social security numbers
+            "national_id" => "[0-9]{3}-[0-9]{2}-[0-9]{4}",
+            "date" => "[0-9]{1,4}[-/.][0-9]{1,2}[-/.][0-9]{1,4}",
+            "phone" => "[+(]?[0-9][0-9 ().-]{6,}[0-9]",
+            _ => {
+                return Err(ValidateError::Expected(format!(
+                    "one of `email`, `national_id`, `date` or `phone`, got `{}`",
+                    name
+                )))
+            }
+        };
+        Ok(Self {
+            name: name.to_string(),
+            pattern: pattern.to_string(),
+        })
+    }
+
+    /// Text of the values to redact which looks like a marker, replaced by
+    /// `MARKER_LOOKALIKE_REPLACEMENT` before detectors are applied, so that
+    /// markers only ever stand for detections
+    pub const MARKER_LOOKALIKE: &'static str = "[[]REDACTED:";
+    pub const MARKER_LOOKALIKE_REPLACEMENT: &'static str = "[redacted:";
+
+    /// What the detections are replaced with
+    pub fn marker(&self) -> String {
+        format!("[REDACTED:{}]", self.name.to_uppercase())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DatePart {
+    Week,
+    Month,
+    Year,
+}
+
+impl FromStr for DatePart {
+    type Err = ValidateError;
+    fn from_str(s: &str) -> ValidateResult<Self> {
+        match s.to_uppercase().as_ref() {
+            "WEEK" => Ok(Self::Week),
+            "MONTH" => Ok(Self::Month),
+            "YEAR" => Ok(Self::Year),
+            _ => Err(ValidateError::Expected(format!(
+                "one of `week`, `month` or `year`, got `{}`",
+                s
+            ))),
+        }
+    }
+}
+
+impl std::fmt::Display for DatePart {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Week => write!(f, "WEEK"),
+            Self::Month => write!(f, "MONTH"),
+            Self::Year => write!(f, "YEAR"),
+        }
+    }
+}
+
+/// How a value is replaced by its bucket.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Bucketing {
+    /// Values are mapped to the lower bound of their bin
+    FixedWidth {
+        width: f64,
+        origin: f64,
+    },
+    /// Sorted lower bounds of the bins. Values are mapped to the lower bound
+    /// of their bin, values below the first bound to the first bin.
+    Boundaries(Vec<f64>),
+    Truncate(DatePart),
+}
+
+impl Bucketing {
+    pub fn fixed_width_bucket(value: f64, width: f64, origin: f64) -> f64 {
+        ((value - origin) / width).floor() * width + origin
+    }
+
+    /// Whether the bins of numeric values only have integer bounds, in which
+    /// case generalized values are integers whatever the input
+    pub fn is_integral(&self) -> bool {
+        match self {
+            Self::FixedWidth { width, origin } => width.fract() == 0. && origin.fract() == 0.,
+            Self::Boundaries(boundaries) => boundaries.iter().all(|b| b.fract() == 0.),
+            Self::Truncate(..) => false,
+        }
+    }
+}
+
+pub(crate) fn sql_function(name: &str, args: Vec<ast::Expr>) -> ast::Expr {
+    ast::Expr::Function(ast::Function {
+        name: ast::ObjectName(vec![name.to_string()]),
+        args,
+        over: None,
+        distinct: false,
+    })
+}
+
+pub(crate) fn sql_number<N: ToString>(num: N) -> ast::Expr {
+    ast::Expr::Value(ast::Value::Number(num.to_string()))
+}
+
+pub(crate) fn sql_binary_op(
+    left: ast::Expr,
+    op: ast::BinaryOperator,
+    right: ast::Expr,
+) -> ast::Expr {
+    ast::Expr::BinaryOp {
+        left: Box::new(left),
+        op,
+        right: Box::new(right),
+    }
+}
+
 entish! {
     #[derive(Map, MapOwned, From, TryInto, IntoResult, IntoOption)]
-    #[derive(Serialize, Deserialize, Debug, Clone)]
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
     #[entish(variants_as_structs)]
     pub enum Expr {
         IsNull(pub Self),
@@ -164,6 +560,16 @@ entish! {
             pub negated: bool
         },
         // suppressed InSubquery
+        // whether `expr` is among the values of the column `column` of the
+        // backend table `table`, in the rows where the column `filter.0` is
+        // `filter.1` if any
+        InTable {
+            pub expr: Self,
+            pub table: String,
+            pub column: String,
+            pub filter: Option<(String, String)>,
+            pub negated: bool
+        },
         Between {
             pub expr: Self,
             pub negated: bool,
@@ -185,6 +591,10 @@ entish! {
             pub args: Vec<Self>,
             pub distinct: bool
         },
+        Extract {
+            pub field: DateTimeField,
+            pub expr: Self
+        },
         Case {
             pub operand: Option<Self>,
             pub conditions: Vec<Self>,
@@ -199,7 +609,10 @@ entish! {
         Hash {
             pub algo: HashAlgorithm,
             pub expr: Self,
-            pub salt: Vec<u8>
+            pub key: Keying,
+            pub encoding: HashEncoding,
+            pub truncate: Option<u64>,
+            pub watermark: Option<Watermark>
         },
         Replace {
             pub expr: Self,
@@ -209,6 +622,42 @@ entish! {
             pub expr: Self,
             pub distribution: Distribution
         },
+        // the quantile `quantile` of the values of `expr`, chosen with the
+        // exponential mechanism among the values from `min` to `max` by `step`
+        NoisyQuantile {
+            pub expr: Self,
+            pub quantile: f64,
+            pub min: f64,
+            pub max: f64,
+            pub step: f64,
+            pub epsilon: f64,
+            pub sensitivity: f64
+        },
+        // the rank of the values of `partition` among those found in the rows
+        // of the entity `entity`, in an order arbitrary but fixed for each
+        // entity
+        PartitionRank {
+            pub entity: Self,
+            pub partition: Vec<Self>
+        },
+        Mask {
+            pub expr: Self,
+            pub masking: Masking
+        },
+        // `expr` with the detections of each of `detectors` replaced in turn
+        Redact {
+            pub expr: Self,
+            pub detectors: Vec<Detector>
+        },
+        Generalize {
+            pub expr: Self,
+            pub bucketing: Bucketing
+        },
+        Tokenize {
+            pub expr: Self,
+            pub keyset: WrappedKeyset,
+            pub context: String
+        },
     }
 }
 
@@ -228,10 +677,20 @@ to_ansatz! {
                 LiteralValue::Boolean(b) => ast::Value::Boolean(b),
                 LiteralValue::Double(v) => ast::Value::Number(v.to_string()),
                 LiteralValue::StringLiteral(lit) => ast::Value::SingleQuotedString(lit),
-                LiteralValue::Null => ast::Value::Null
+                LiteralValue::Null => ast::Value::Null,
+                LiteralValue::Placeholder(name, ty) => {
+                    let ty = ty.to_string().to_uppercase();
+                    ast::Value::NationalStringLiteral(format!("{}:{}", name, ty))
+                }
             };
             ast::Expr::Value(value)
         },
+        Extract<> { field, expr } => {
+            ast::Expr::Extract {
+                field: field.into(),
+                expr: Box::new(expr.into())
+            }
+        },
         UnaryOp<> { op, expr } => {
             ast::Expr::UnaryOp {
                 op: op.into(),
@@ -270,13 +729,14 @@ to_ansatz! {
             ast::Expr::IsNull(Box::new(expr.into()))
         },
         Function<> { name, args, distinct } => {
+            let args: Vec<ast::Expr> = args
+                .into_iter()
+                .map(|arg| arg.into())
+                .collect();
             ast::Expr::Function(
                 ast::Function {
                     name: ast::ObjectName(vec![name.to_string()]),
-                    args: args
-                        .into_iter()
-                        .map(|arg| arg.into())
-                        .collect(),
+                    args,
                     over: None,
                     distinct
                 }
@@ -309,6 +769,53 @@ to_ansatz! {
                 },
             }
         },
+        Generalize<> { expr, bucketing } => {
+            let expr: ast::Expr = expr.into();
+            let is_integral = bucketing.is_integral();
+            let generalized = match bucketing {
+                Bucketing::FixedWidth { width, origin } => {
+                    let shifted = ast::Expr::Nested(Box::new(
+                        sql_binary_op(expr, ast::BinaryOperator::Minus, sql_number(origin))
+                    ));
+                    let bin = sql_function("FLOOR", vec![
+                        sql_binary_op(shifted, ast::BinaryOperator::Divide, sql_number(width))
+                    ]);
+                    sql_binary_op(
+                        sql_binary_op(bin, ast::BinaryOperator::Multiply, sql_number(width)),
+                        ast::BinaryOperator::Plus,
+                        sql_number(origin)
+                    )
+                },
+                Bucketing::Boundaries(boundaries) => {
+                    // NULLs would otherwise end up in the last bin
+                    let mut conditions = vec![ast::Expr::IsNull(Box::new(expr.clone()))];
+                    let mut results = vec![ast::Expr::Value(ast::Value::Null)];
+                    for (lower, upper) in boundaries.iter().zip(boundaries.iter().skip(1)) {
+                        conditions.push(sql_binary_op(expr.clone(), ast::BinaryOperator::Lt, sql_number(upper)));
+                        results.push(sql_number(lower));
+                    }
+                    ast::Expr::Case {
+                        operand: None,
+                        conditions,
+                        results,
+                        else_result: boundaries.last().map(|last| Box::new(sql_number(last)))
+                    }
+                },
+                Bucketing::Truncate(part) => sql_function("DATE_TRUNC", vec![
+                    ast::Expr::Value(ast::Value::SingleQuotedString(part.to_string().to_lowercase())),
+                    expr
+                ]),
+            };
+            // `FLOOR` and divisions always yield floating point numbers
+            if is_integral {
+                ast::Expr::Cast {
+                    expr: Box::new(generalized),
+                    data_type: ast::DataType::BigInt
+                }
+            } else {
+                generalized
+            }
+        },
     }
 }
 
@@ -451,6 +958,13 @@ impl<C> Expr<C> {
                     list: { join_all(list.iter().map(|elt| f(elt))).await },
                     negated: { *negated },
                 },
+                InTable => {
+                    expr: { f(expr).await },
+                    table: { table.clone() },
+                    column: { column.clone() },
+                    filter: { filter.clone() },
+                    negated: { *negated },
+                },
                 Between => {
                     expr: { f(expr).await },
                     negated: { *negated },
@@ -493,10 +1007,17 @@ impl<C> Expr<C> {
                     expr: { f(expr).await },
                     alias: { alias.clone() },
                 },
+                Extract => {
+                    field: { field.clone() },
+                    expr: { f(expr).await },
+                },
                 Hash => {
                     algo: { algo.clone() },
                     expr: { f(expr).await },
-                    salt: { salt.clone() },
+                    key: { key.clone() },
+                    encoding: { encoding.clone() },
+                    truncate: { truncate.clone() },
+                    watermark: { watermark.clone() },
                 },
                 Replace => {
                     expr: { f(expr).await },
@@ -506,6 +1027,36 @@ impl<C> Expr<C> {
                     expr: { f(expr).await },
                     distribution: { distribution.clone() },
                 },
+                NoisyQuantile => {
+                    expr: { f(expr).await },
+                    quantile: { quantile.clone() },
+                    min: { min.clone() },
+                    max: { max.clone() },
+                    step: { step.clone() },
+                    epsilon: { epsilon.clone() },
+                    sensitivity: { sensitivity.clone() },
+                },
+                PartitionRank => {
+                    entity: { f(entity).await },
+                    partition: { join_all(partition.iter().map(|elt| f(elt))).await },
+                },
+                Mask => {
+                    expr: { f(expr).await },
+                    masking: { masking.clone() },
+                },
+                Redact => {
+                    expr: { f(expr).await },
+                    detectors: { detectors.clone() },
+                },
+                Generalize => {
+                    expr: { f(expr).await },
+                    bucketing: { bucketing.clone() },
+                },
+                Tokenize => {
+                    expr: { f(expr).await },
+                    keyset: { keyset.clone() },
+                    context: { context.clone() },
+                },
                 #[unnamed] Column => { context_key: { context_key.clone() }, },
                 #[unnamed] Literal => { lit: { lit.clone() }, },
                 #[unnamed] IsNull => { from: { f(from).await }, },
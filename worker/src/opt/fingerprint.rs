@@ -0,0 +1,199 @@
+//! Normalized fingerprints of queries, compared across the history of a user
+//! to detect differencing attacks
+
+use crate::common::*;
+
+use super::{
+    BinaryOp, BinaryOperator, Expr, ExprT, Rel, RelT, Selection, Table, ValidateError,
+    ValidateResult,
+};
+
+/// What to do with a query suspected of being part of a differencing attack
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DifferencingAction {
+    Refuse,
+    Noise,
+    Flag,
+}
+
+impl FromStr for DifferencingAction {
+    type Err = ValidateError;
+    fn from_str(s: &str) -> ValidateResult<Self> {
+        match s.to_lowercase().as_ref() {
+            "" | "refuse" => Ok(Self::Refuse),
+            "noise" => Ok(Self::Noise),
+            "flag" => Ok(Self::Flag),
+            _ => Err(ValidateError::Expected(format!(
+                "one of `refuse`, `noise` or `flag`, got `{}`",
+                s
+            ))),
+        }
+    }
+}
+
+impl std::fmt::Display for DifferencingAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Refuse => write!(f, "refuse"),
+            Self::Noise => write!(f, "noise"),
+            Self::Flag => write!(f, "flag"),
+        }
+    }
+}
+
+/// A normalized summary of a query: its shape, with its filters left out,
+/// and the conditions of its filters
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Fingerprint {
+    /// Digest of the query with its filters left out
+    pub shape: String,
+    /// Digests of the conditions of the filters of the query, sorted
+    pub conditions: Vec<String>,
+    /// The datasets the query reads from
+    pub datasets: Vec<String>,
+}
+
+impl Fingerprint {
+    pub fn of(rel_t: &RelT) -> ValidateResult<Self> {
+        let mut conditions = Vec::new();
+        let mut datasets = Vec::new();
+        let shape = digest(&without_filters(rel_t, &mut conditions, &mut datasets)?)?;
+        conditions.sort();
+        conditions.dedup();
+        datasets.sort();
+        datasets.dedup();
+        Ok(Self {
+            shape,
+            conditions,
+            datasets,
+        })
+    }
+
+    /// The number of conditions by which the filters of the two queries
+    /// differ, if they have the same shape
+    pub fn difference(&self, other: &Self) -> Option<usize> {
+        if self.shape != other.shape {
+            return None;
+        }
+        let left: HashSet<_> = self.conditions.iter().collect();
+        let right: HashSet<_> = other.conditions.iter().collect();
+        Some(left.symmetric_difference(&right).count())
+    }
+}
+
+/// `rel_t` without its selections, whose conditions are collected into
+/// `conditions` instead
+fn without_filters(
+    rel_t: &RelT,
+    conditions: &mut Vec<String>,
+    datasets: &mut Vec<String>,
+) -> ValidateResult<RelT> {
+    match &rel_t.root {
+        Rel::Selection(Selection { from, where_ }) => {
+            conjuncts(where_, conditions)?;
+            without_filters(from, conditions, datasets)
+        }
+        Rel::Table(Table(context_key)) => {
+            if let Some(dataset) = context_key.prefix().next() {
+                datasets.push(dataset.to_string());
+            }
+            Ok(rel_t.clone())
+        }
+        root => Ok(RelT::from(
+            root.map(&mut |child| without_filters(child, conditions, datasets))
+                .into_result()?,
+        )),
+    }
+}
+
+/// Collect the digests of the conditions `expr_t` is a conjunction of
+fn conjuncts(expr_t: &ExprT, conditions: &mut Vec<String>) -> ValidateResult<()> {
+    match expr_t.as_ref() {
+        Expr::BinaryOp(BinaryOp {
+            left,
+            op: BinaryOperator::And,
+            right,
+        }) => {
+            conjuncts(left, conditions)?;
+            conjuncts(right, conditions)
+        }
+        _ => {
+            conditions.push(digest(expr_t)?);
+            Ok(())
+        }
+    }
+}
+
+/// Digest of a tree, leaving its boards out as they are derived from it
+fn digest<T: Serialize>(tree: &T) -> ValidateResult<String> {
+    Ok(base64::encode(sha256(tree)?.as_ref()))
+}
+
+/// Hexadecimal digest of a tree, leaving its boards out, fit for keying
+/// blocks with
+pub(crate) fn hex_digest<T: Serialize>(tree: &T) -> ValidateResult<String> {
+    Ok(sha256(tree)?
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+fn sha256<T: Serialize>(tree: &T) -> ValidateResult<ring::digest::Digest> {
+    fn strip_boards(value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::Object(map) => {
+                map.remove("board");
+                map.values_mut().for_each(strip_boards);
+            }
+            serde_json::Value::Array(values) => values.iter_mut().for_each(strip_boards),
+            _ => {}
+        }
+    }
+    let mut value = serde_json::to_value(tree)
+        .map_err(|e| ValidateError::Internal(format!("could not serialize a query tree: {}", e)))?;
+    strip_boards(&mut value);
+    Ok(ring::digest::digest(
+        &ring::digest::SHA256,
+        value.to_string().as_bytes(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::node::tests::just_get_me_a_context;
+    use crate::opt::validate::Validator;
+
+    fn fingerprints_of(queries: &[&str]) -> Vec<Fingerprint> {
+        let ctx = just_get_me_a_context();
+        let validator = Validator::new(&ctx);
+        queries
+            .iter()
+            .map(|query| Fingerprint::of(&validator.validate_str(query).unwrap()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn fingerprint_difference() {
+        let fingerprints = fingerprints_of(&[
+            "SELECT COUNT(person_id) FROM patient_data.person \
+             WHERE year_of_birth = 1950",
+            "SELECT COUNT(person_id) FROM patient_data.person \
+             WHERE year_of_birth = 1950 AND month_of_birth = 3",
+            "SELECT COUNT(person_id) FROM patient_data.person \
+             WHERE month_of_birth = 3 AND year_of_birth = 1950",
+            "SELECT COUNT(care_site_id) FROM patient_data.person \
+             WHERE year_of_birth = 1950",
+        ]);
+        assert_eq!(fingerprints[0].datasets, vec!["patient_data".to_string()]);
+
+        // adding a condition to the filter
+        assert_eq!(fingerprints[0].difference(&fingerprints[1]), Some(1));
+        // the order of the conditions does not matter
+        assert_eq!(fingerprints[1].difference(&fingerprints[2]), Some(0));
+        // and queries of another shape are not compared
+        assert_eq!(fingerprints[0].difference(&fingerprints[3]), None);
+    }
+}
@@ -177,8 +177,21 @@ impl ExprRepr for DataType {
                 LiteralValue::Boolean(..) => Ok(DataType::Boolean),
                 LiteralValue::StringLiteral(..) => Ok(DataType::String),
                 LiteralValue::Null => Ok(DataType::Null),
+                LiteralValue::Placeholder(_, ty) => Ok(*ty),
             },
             Expr::As(As { expr, .. }) => Ok(expr.clone()),
+            Expr::Function(Function {
+                name: FunctionName::Quantile,
+                args,
+                ..
+            }) => match args.as_slice() {
+                [expr, DataType::Float] if expr.is_numeric() => Ok(**expr),
+                _ => error!(
+                    Expected,
+                    "in an expression of the form `QUANTILE(a, q)`, `a` to be numeric and `q` \
+                     to be a float"
+                ),
+            },
             Expr::Function(Function { name, mut args, .. }) => {
                 let fst = args.pop().ok_or(ValidateError::Expected(
                     "function to have at least one argument".to_string(),
@@ -186,7 +199,11 @@ impl ExprRepr for DataType {
                 if args.into_iter().all(|arg| arg == fst) {
                     match name {
                         FunctionName::Count => Ok(DataType::Integer),
-                        FunctionName::Sum | FunctionName::Max | FunctionName::Min => {
+                        FunctionName::Sum
+                        | FunctionName::Max
+                        | FunctionName::Min
+                        | FunctionName::Median
+                        | FunctionName::Quantile => {
                             if fst.is_numeric() {
                                 Ok(*fst)
                             } else {
@@ -209,7 +226,7 @@ impl ExprRepr for DataType {
                     error!(Expected, "all arguments of functions to have the same type")
                 }
             }
-            Expr::IsNull(..) | Expr::IsNotNull(..) => Ok(DataType::Boolean),
+            Expr::IsNull(..) | Expr::IsNotNull(..) | Expr::InTable(..) => Ok(DataType::Boolean),
             Expr::InList(InList { expr, list, .. }) => {
                 if list.into_iter().any(|elt| elt != expr) {
                     error!(
@@ -325,9 +342,38 @@ impl ExprRepr for DataType {
                     )
                 }
             }
-            Expr::Hash(Hash { .. }) => Ok(DataType::Bytes),
+            Expr::Extract(Extract { field, expr }) => match (field, expr) {
+                (DateTimeField::Year, DataType::Date)
+                | (DateTimeField::Month, DataType::Date)
+                | (DateTimeField::Day, DataType::Date)
+                | (_, DataType::Timestamp) => Ok(DataType::Integer),
+                _ => error!(InvalidType, "date or timestamp type", expr),
+            },
+            Expr::Hash(Hash { .. }) => Ok(DataType::String),
+            Expr::Tokenize(Tokenize { .. }) => Ok(DataType::String),
             Expr::Replace(Replace { with, .. }) => Ok(*with),
             Expr::Noisy(Noisy { expr, .. }) => Ok(*expr),
+            Expr::NoisyQuantile(NoisyQuantile { expr, .. }) => Ok(*expr),
+            Expr::PartitionRank(..) => Ok(DataType::Integer),
+            Expr::Mask(Mask { expr, .. }) | Expr::Redact(Redact { expr, .. }) => match expr {
+                DataType::String => Ok(DataType::String),
+                _ => error!(InvalidType, "string type", expr),
+            },
+            Expr::Generalize(Generalize { expr, bucketing }) => match bucketing {
+                Bucketing::FixedWidth { .. } | Bucketing::Boundaries(..) => {
+                    if expr.is_numeric() && bucketing.is_integral() {
+                        Ok(DataType::Integer)
+                    } else if expr.is_numeric() {
+                        Ok(DataType::Float)
+                    } else {
+                        error!(InvalidType, "numeric type", expr)
+                    }
+                }
+                Bucketing::Truncate(..) => match expr {
+                    DataType::Date | DataType::Timestamp => Ok(DataType::Date),
+                    _ => error!(InvalidType, "date or timestamp type", expr),
+                },
+            },
         }
     }
 }
@@ -171,7 +171,9 @@ pub use ansatz::{CompositionError, ExprAnsatz, RelAnsatz, ToAnsatz};
 pub mod plan;
 
 pub mod transform;
-pub use transform::{Policy, PolicyBinding, RelTransformer, Transformed};
+pub use transform::{
+    Consent, Cost, Exclusion, Policy, PolicyBinding, RelTransformer, Template, Transformed,
+};
 
 pub mod rel;
 pub use rel::*;
@@ -182,6 +184,9 @@ pub use expr::*;
 pub mod privacy;
 pub use privacy::*;
 
+pub mod fingerprint;
+pub use fingerprint::{DifferencingAction, Fingerprint};
+
 /// A key for something in a given context. This is basically a wrapper around
 /// `str::split(".")`.
 #[derive(Debug, Clone, Serialize, Deserialize, Hash, PartialEq, Eq)]
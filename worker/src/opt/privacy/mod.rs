@@ -5,19 +5,95 @@ use futures::try_join;
 use crate::node::Access;
 
 use super::{
-    Aggregation, As, BinaryOp, BinaryOperator, Column, Context, ContextKey, Distinct, Expr,
-    ExprMeta, ExprRepr, ExprT, Function, FunctionName, GenericRel, Join, Limit, Named, Offset,
-    OrderBy, Projection, RebaseExpr, RebaseRel, RelRepr, RelT, Relation, Replace, Repr, Selection,
-    Set, SetOperator, Table, TableMeta, Taint, ToContext, ValidateError, ValidateResult, WithAlias,
+    Aggregation, As, BinaryOp, BinaryOperator, Bucketing, Column, Context, ContextKey,
+    DateTimeField, Distinct, Expr, ExprMeta, ExprRepr, ExprT, Extract, Function, FunctionName,
+    Generalize, GenericRel, Join, JoinConstraint, Limit, Literal, LiteralValue, Named,
+    NoisyQuantile, Offset, OrderBy, Projection, RebaseExpr, RebaseRel, RelRepr, RelT, Relation,
+    Replace, Repr, Selection, Set, SetOperator, Table, TableMeta, Taint, ToContext, ValidateError,
+    ValidateResult, WithAlias,
 };
 
-derive_rel_repr! {
-    over FlexExprMeta derive
-    #[derive(Serialize, Deserialize, Debug, Clone,)]
-    pub struct FlexTableMeta {
-        pub row_count: RowCount,
-        pub primary: PrimaryMeta,
-        pub columns: Context<FlexExprMeta>,
+/// An entity (e.g. a patient) together with the columns identifying it in the
+/// tables of a dataset
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntityFields {
+    pub name: String,
+    /// Patterns of the form `[table.]column`
+    pub fields: Vec<ContextKey>,
+}
+
+impl EntityFields {
+    /// An entity identified by the column `name` in every table
+    pub fn from_column(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            fields: vec![ContextKey::with_name(name)],
+        }
+    }
+
+    pub fn from_entity(entity: &Entity) -> ValidateResult<Self> {
+        let fields = entity
+            .fields
+            .iter()
+            .map(|field| field.parse())
+            .collect::<ValidateResult<_>>()?;
+        Ok(Self {
+            name: entity.name.clone(),
+            fields,
+        })
+    }
+
+    /// The column of the table `table` identifying the entity, if any
+    pub fn column_in<M>(&self, table: &ContextKey, columns: &Context<M>) -> Option<ContextKey> {
+        self.fields_of(table)
+            .map(|field| ContextKey::with_name(field.name()))
+            .find(|column| columns.get(column).is_ok())
+    }
+
+    /// Whether the column `column` of the table `table` identifies the entity
+    pub fn identified_by(&self, table: &ContextKey, column: &str) -> bool {
+        self.fields_of(table).any(|field| field.name() == column)
+    }
+
+    /// The fields whose table pattern matches `table`
+    fn fields_of<'a>(&'a self, table: &'a ContextKey) -> impl Iterator<Item = &'a ContextKey> {
+        self.fields.iter().filter(move |field| {
+            field
+                .prefix()
+                .zip(table.iter())
+                .all(|(pattern, part)| pattern == "*" || pattern == part)
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FlexTableMeta {
+    pub row_count: RowCount,
+    pub primary: PrimaryMeta,
+    pub columns: Context<FlexExprMeta>,
+}
+
+impl RelRepr<FlexExprMeta> for FlexTableMeta {
+    fn dot(node: GenericRel<&FlexExprMeta, &Self>) -> ValidateResult<Self> {
+        let row_count = RowCount::dot(node.map(&mut |child| &child.row_count))?;
+        let primary = PrimaryMeta::dot(node.map(&mut |child| &child.primary))?;
+        let mut columns = Context::<FlexExprMeta>::dot(node.map(&mut |child| &child.columns))?;
+        if let GenericRel::Join(..) = node {
+            // the rows of an entity on one side are repeated for every row
+            // they are joined with on the other side
+            columns = columns
+                .into_iter()
+                .map(|(key, mut meta)| {
+                    meta.domain_sensitivity.maximum_frequency = primary.maximum_frequency;
+                    (key, meta)
+                })
+                .collect();
+        }
+        Ok(Self {
+            row_count,
+            primary,
+            columns,
+        })
     }
 }
 
@@ -54,6 +130,7 @@ impl<'a, 'b> std::ops::Add<&'a RowCount> for &'b RowCount {
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PrimaryMeta {
+    /// The name of the entity
     pub key: ContextKey,
     pub maximum_frequency: MaximumFrequency,
 }
@@ -72,6 +149,38 @@ derive_expr_repr! {
     pub struct FlexExprMeta {
         pub domain_sensitivity: DomainSensitivity,
         pub taint: Taint,
+        pub entity_match: EntityMatch,
+    }
+}
+
+/// Whether an expression is a column identifying the entity, or a condition
+/// that only holds when two such columns are equal
+#[derive(Default, Debug, PartialEq, Serialize, Deserialize, Copy, Clone)]
+pub struct EntityMatch {
+    pub column: bool,
+    pub equality: bool,
+}
+
+impl ExprRepr for EntityMatch {
+    fn dot(node: Expr<&Self>) -> ValidateResult<Self> {
+        let equality = match node {
+            Expr::As(As { expr, .. }) => return Ok(*expr),
+            Expr::BinaryOp(BinaryOp {
+                left,
+                op: BinaryOperator::Eq,
+                right,
+            }) => left.column && right.column,
+            Expr::BinaryOp(BinaryOp {
+                left,
+                op: BinaryOperator::And,
+                right,
+            }) => left.equality || right.equality,
+            _ => false,
+        };
+        Ok(Self {
+            column: false,
+            equality,
+        })
     }
 }
 
@@ -139,6 +248,248 @@ impl Domain {
         };
         Sensitivity(sensitivity)
     }
+
+    /// The smallest and largest values of the domain, if it is bounded
+    fn bounds(&self) -> Option<(f64, f64)> {
+        match self {
+            Self::Discrete { min, max, .. } => Some((*min as f64, *max as f64)),
+            Self::Continuous { min, max } => Some((*min, *max)),
+            Self::Opaque => None,
+        }
+    }
+
+    /// Interval arithmetic: the domain of the values `op` can take on
+    /// operands from `left` and `right`.
+    fn arithmetic(left: &Self, op: &BinaryOperator, right: &Self) -> Self {
+        let ((a, b), (c, d)) = match (left.bounds(), right.bounds()) {
+            (Some(left), Some(right)) => (left, right),
+            _ => return Self::Opaque,
+        };
+        let steps = match (left, right) {
+            (Self::Discrete { step: s, .. }, Self::Discrete { step: t, .. }) => Some((*s, *t)),
+            _ => None,
+        };
+        let hull = |values: &[f64]| {
+            values
+                .iter()
+                .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), v| {
+                    (lo.min(*v), hi.max(*v))
+                })
+        };
+        let (lo, hi) = match op {
+            BinaryOperator::Plus => (a + c, b + d),
+            BinaryOperator::Minus => (a - d, b - c),
+            BinaryOperator::Multiply => hull(&[a * c, a * d, b * c, b * d]),
+            BinaryOperator::Divide => {
+                // Dividing by zero fails, so only the non-zero divisors
+                // matter. Integers are at least 1 away from zero, but
+                // continuous divisors get arbitrarily close to it.
+                let divisors = if steps.is_some() {
+                    let mut divisors = Vec::new();
+                    if c <= -1. {
+                        divisors.push((c, d.min(-1.)));
+                    }
+                    if d >= 1. {
+                        divisors.push((c.max(1.), d));
+                    }
+                    divisors
+                } else if c > 0. || d < 0. {
+                    vec![(c, d)]
+                } else {
+                    vec![]
+                };
+                if divisors.is_empty() {
+                    return Self::Opaque;
+                }
+                let quotients = divisors
+                    .into_iter()
+                    .flat_map(|(c, d)| vec![a / c, a / d, b / c, b / d])
+                    .collect::<Vec<_>>();
+                let (min, max) = hull(&quotients);
+                return Self::Continuous { min, max };
+            }
+            BinaryOperator::Modulus => {
+                if c == 0. && d == 0. {
+                    return Self::Opaque;
+                }
+                // the remainder has the sign of the dividend and is smaller
+                // than the divisor in absolute value
+                let bound = c.abs().max(d.abs()) - if steps.is_some() { 1. } else { 0. };
+                let lo = if a >= 0. { 0. } else { a.max(-bound) };
+                let hi = if b <= 0. { 0. } else { b.min(bound) };
+                (lo, hi)
+            }
+            _ => return Self::Opaque,
+        };
+        match (steps, op) {
+            (Some((s, t)), BinaryOperator::Plus) | (Some((s, t)), BinaryOperator::Minus) => {
+                Self::Discrete {
+                    min: lo as i64,
+                    max: hi as i64,
+                    step: max(gcd(s, t), 1),
+                }
+            }
+            (Some(..), _) => Self::Discrete {
+                min: lo as i64,
+                max: hi as i64,
+                step: 1,
+            },
+            (None, _) => Self::Continuous { min: lo, max: hi },
+        }
+    }
+
+    /// The domain of the buckets values from this domain are mapped to.
+    fn generalize(&self, bucketing: &Bucketing) -> Self {
+        match bucketing {
+            Bucketing::FixedWidth { width, origin } => {
+                let bucket = |value: f64| Bucketing::fixed_width_bucket(value, *width, *origin);
+                match self {
+                    // widths are positive, so integer widths are at least 1
+                    Self::Discrete { min, max, .. } if bucketing.is_integral() => Self::Discrete {
+                        min: bucket(*min as f64) as i64,
+                        max: bucket(*max as f64) as i64,
+                        step: *width as u64,
+                    },
+                    Self::Discrete { min, max, .. } => Self::Continuous {
+                        min: bucket(*min as f64),
+                        max: bucket(*max as f64),
+                    },
+                    Self::Continuous { min, max } if bucketing.is_integral() => Self::Discrete {
+                        min: bucket(*min) as i64,
+                        max: bucket(*max) as i64,
+                        step: *width as u64,
+                    },
+                    Self::Continuous { min, max } => Self::Continuous {
+                        min: bucket(*min),
+                        max: bucket(*max),
+                    },
+                    Self::Opaque => Self::Opaque,
+                }
+            }
+            // Whatever the input, values end up being one of the boundaries
+            Bucketing::Boundaries(boundaries) => match (boundaries.first(), boundaries.last()) {
+                (Some(first), Some(last)) => {
+                    if boundaries.iter().all(|b| b.fract() == 0.) {
+                        let step = boundaries
+                            .iter()
+                            .zip(boundaries.iter().skip(1))
+                            .map(|(lower, upper)| (upper - lower) as u64)
+                            .fold(0, gcd);
+                        Self::Discrete {
+                            min: *first as i64,
+                            max: *last as i64,
+                            step: max(step, 1),
+                        }
+                    } else {
+                        Self::Continuous {
+                            min: *first,
+                            max: *last,
+                        }
+                    }
+                }
+                _ => Self::Opaque,
+            },
+            Bucketing::Truncate(..) => Self::Opaque,
+        }
+    }
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+impl<'a> TryFrom<&'a column_domain::Domain> for Domain {
+    type Error = ValidateError;
+    fn try_from(domain: &'a column_domain::Domain) -> ValidateResult<Self> {
+        match domain {
+            column_domain::Domain::Range(NumericRange { min, max, step }) => {
+                if min > max {
+                    Err(ValidateError::Expected(format!(
+                        "the range {}..{} to be non-empty",
+                        min, max
+                    )))
+                } else if *step == 0. {
+                    Ok(Self::Continuous {
+                        min: *min,
+                        max: *max,
+                    })
+                } else if *step < 0. || step.fract() != 0. || min.fract() != 0. || max.fract() != 0.
+                {
+                    Err(ValidateError::Expected(format!(
+                        "the range {}..{} with step {} to be made of integers",
+                        min, max, step
+                    )))
+                } else if (max - min) % step != 0. {
+                    Err(ValidateError::Expected(format!(
+                        "the step {} to divide the range {}..{}",
+                        step, min, max
+                    )))
+                } else {
+                    Ok(Self::Discrete {
+                        min: *min as i64,
+                        max: *max as i64,
+                        step: *step as u64,
+                    })
+                }
+            }
+            // FIXME: the groups of categorical columns could be released
+            // without selection, provided empty ones are released too
+            column_domain::Domain::Categories(..) => Err(ValidateError::Expected(
+                "a numeric range, categorical domains are not supported yet".to_string(),
+            )),
+        }
+    }
+}
+
+/// The public domains a dataset declares for its columns
+#[derive(Debug, Clone, Default)]
+pub struct DeclaredDomains {
+    /// Patterns of the form `[table.]column` with the domain of the columns
+    /// they match
+    pub columns: Vec<(ContextKey, Domain)>,
+    /// Whether columns without a declared domain may be probed for one
+    pub probe_undeclared: bool,
+}
+
+impl DeclaredDomains {
+    pub fn from_public_domains(domains: &PublicDomains) -> ValidateResult<Self> {
+        let columns = domains
+            .columns
+            .iter()
+            .map(|column| {
+                let domain = column
+                    .domain
+                    .as_ref()
+                    .ok_or(ValidateError::Expected(format!(
+                        "a domain to be declared for {}",
+                        column.field
+                    )))?;
+                Ok((column.field.parse()?, domain.try_into()?))
+            })
+            .collect::<ValidateResult<_>>()?;
+        Ok(Self {
+            columns,
+            probe_undeclared: domains.probe_undeclared,
+        })
+    }
+
+    /// The domain declared for the column `column` of the table `table`
+    pub fn get(&self, table: &ContextKey, column: &ContextKey) -> Option<Domain> {
+        self.columns
+            .iter()
+            .find(|(field, _)| {
+                field.name() == column.name()
+                    && field
+                        .prefix()
+                        .zip(table.iter())
+                        .all(|(pattern, part)| pattern == "*" || pattern == part)
+            })
+            .map(|(_, domain)| *domain)
+    }
 }
 
 impl Default for Domain {
@@ -165,21 +516,35 @@ impl ExprRepr for Domain {
                 ck
             ))),
             Expr::As(As { expr, .. }) => Ok(expr.clone()),
-            Expr::IsNull(..) | Expr::IsNotNull(..) | Expr::InList(..) | Expr::Between(..) => {
-                Ok(Self::Discrete {
-                    max: 0,
-                    min: 1,
-                    step: 1,
-                })
-            }
-            Expr::Function(Function { name, mut args, .. }) => {
+            Expr::Literal(Literal(LiteralValue::Long(value))) => Ok(Self::Discrete {
+                min: value,
+                max: value,
+                step: 1,
+            }),
+            Expr::Literal(Literal(LiteralValue::Double(value))) => Ok(Self::Continuous {
+                min: value,
+                max: value,
+            }),
+            Expr::IsNull(..)
+            | Expr::IsNotNull(..)
+            | Expr::InList(..)
+            | Expr::InTable(..)
+            | Expr::Between(..) => Ok(Self::Discrete {
+                max: 0,
+                min: 1,
+                step: 1,
+            }),
+            Expr::Function(Function { name, args, .. }) => {
                 // TODO: refactor Function struct to not have to validate this
                 // every time.
-                let arg = args.pop().ok_or(ValidateError::Expected(
+                let arg = args.into_iter().next().ok_or(ValidateError::Expected(
                     "function to have argument".to_string(),
                 ))?;
                 match name {
-                    FunctionName::Max | FunctionName::Min => Ok(arg.clone()),
+                    FunctionName::Max
+                    | FunctionName::Min
+                    | FunctionName::Median
+                    | FunctionName::Quantile => Ok(arg.clone()),
                     FunctionName::Avg | FunctionName::StdDev => match arg {
                         Self::Discrete { min, max, .. } => Ok(Self::Continuous {
                             min: *min as f64,
@@ -191,11 +556,30 @@ impl ExprRepr for Domain {
                     FunctionName::Concat => Ok(Self::Opaque),
                 }
             }
+            Expr::NoisyQuantile(NoisyQuantile { min, max, step, .. }) => {
+                if step.fract() == 0. && min.fract() == 0. && max.fract() == 0. {
+                    Ok(Self::Discrete {
+                        min: min as i64,
+                        max: max as i64,
+                        step: step as u64,
+                    })
+                } else {
+                    Ok(Self::Continuous { min, max })
+                }
+            }
             Expr::Replace(Replace { with, .. }) => Ok(with.clone()),
-            Expr::BinaryOp(BinaryOp { left, op, right }) => {
-                // TODO: this sometimes can be inferred for the arithmetic operations
-                Ok(Self::Opaque)
+            Expr::Generalize(Generalize { expr, bucketing }) => Ok(expr.generalize(&bucketing)),
+            Expr::Extract(Extract { field, .. }) => {
+                let (min, max) = match field {
+                    DateTimeField::Year => return Ok(Self::Opaque),
+                    DateTimeField::Month => (1, 12),
+                    DateTimeField::Day => (1, 31),
+                    DateTimeField::Hour => (0, 23),
+                    DateTimeField::Minute | DateTimeField::Second => (0, 59),
+                };
+                Ok(Self::Discrete { max, min, step: 1 })
             }
+            Expr::BinaryOp(BinaryOp { left, op, right }) => Ok(Self::arithmetic(left, &op, right)),
             _ => Ok(Self::Opaque), // default is Ok(opaque)
         }
     }
@@ -232,32 +616,41 @@ impl ExprRepr for DomainSensitivity {
                     "tried to complete a column".to_string(),
                 ));
             }
-            Expr::IsNull(..) | Expr::IsNotNull(..) | Expr::InList(..) | Expr::Between(..) => {
-                Some(1.)
-            }
+            Expr::IsNull(..)
+            | Expr::IsNotNull(..)
+            | Expr::InList(..)
+            | Expr::InTable(..)
+            | Expr::Between(..) => Some(1.),
             Expr::Literal(..) => Some(0.),
             Expr::BinaryOp(BinaryOp { op, .. }) => match op {
                 BinaryOperator::Plus
                 | BinaryOperator::Minus
                 | BinaryOperator::Multiply
                 | BinaryOperator::Divide
-                | BinaryOperator::Modulus => None,
+                | BinaryOperator::Modulus => domain.to_sensitivity().0,
                 _ => Some(1.),
             },
             Expr::Replace(Replace { with, .. }) => with.0.clone(),
+            Expr::Generalize(..) | Expr::Extract(..) => domain.to_sensitivity().0,
             Expr::Function(Function { name, mut args, .. }) => {
                 // TODO
                 let arg = args.pop().ok_or(ValidateError::Expected(
                     "function to have argument".to_string(),
                 ))?;
                 match name {
-                    FunctionName::Count => {
-                        if maximum_frequency.is_some() {
-                            Some(1.)
-                        } else {
-                            None
-                        }
-                    }
+                    // every row of an entity is counted
+                    FunctionName::Count => maximum_frequency.map(|mf| mf as f64),
+                    // every row of an entity can move the sum by the largest
+                    // value of the domain
+                    FunctionName::Sum => match (arg.domain.bounds(), maximum_frequency) {
+                        (Some((min, max)), Some(mf)) => Some(min.abs().max(max.abs()) * mf as f64),
+                        _ => None,
+                    },
+                    // these stay within the domain of their argument
+                    FunctionName::Avg
+                    | FunctionName::StdDev
+                    | FunctionName::Max
+                    | FunctionName::Min => arg.domain.to_sensitivity().0,
                     _ => None,
                 }
             }
@@ -272,19 +665,35 @@ impl ExprRepr for DomainSensitivity {
     }
 }
 
-impl<E> RelRepr<E> for PrimaryMeta {
-    fn dot(node: GenericRel<&E, &Self>) -> ValidateResult<Self> {
+impl RelRepr<FlexExprMeta> for PrimaryMeta {
+    fn dot(node: GenericRel<&FlexExprMeta, &Self>) -> ValidateResult<Self> {
         let mut children = HashSet::new();
         node.map(&mut |child| children.insert(&child.key));
         if children.len() != 1 {
             Err(ValidateError::Wip(format!(
-                "relation operations involving more than one primary key: {:?}",
+                "relation operations involving more than one entity: {:?}",
                 children
             )))
         } else {
             let key = children.drain().next().unwrap().clone();
-            let maximum_frequency =
-                MaximumFrequency::dot(node.map_owned(&mut |child| &child.maximum_frequency))?;
+            let maximum_frequency = match &node {
+                GenericRel::Join(Join {
+                    left,
+                    right,
+                    operator,
+                }) => match operator.constraint() {
+                    // rows of an entity are only joined with rows of the
+                    // same entity
+                    Some(JoinConstraint::On(on)) if on.entity_match.equality => {
+                        match (left.maximum_frequency.0, right.maximum_frequency.0) {
+                            (Some(left), Some(right)) => MaximumFrequency(Some(left * right)),
+                            _ => MaximumFrequency(None),
+                        }
+                    }
+                    _ => MaximumFrequency(None),
+                },
+                _ => MaximumFrequency::dot(node.map_owned(&mut |child| &child.maximum_frequency))?,
+            };
             Ok(Self {
                 key,
                 maximum_frequency,
@@ -294,7 +703,8 @@ impl<E> RelRepr<E> for PrimaryMeta {
 }
 
 pub struct FlexTableMetaGetter<'a, A> {
-    pub primary: String,
+    pub primary: EntityFields,
+    pub domains: &'a DeclaredDomains,
     pub access: &'a A,
 }
 
@@ -302,7 +712,11 @@ impl<'a, A> FlexTableMetaGetter<'a, A>
 where
     A: Access,
 {
-    pub async fn get(&self, table_meta: &TableMeta) -> crate::Result<FlexTableMeta> {
+    pub async fn get(
+        &self,
+        table: &ContextKey,
+        table_meta: &TableMeta,
+    ) -> crate::Result<FlexTableMeta> {
         let loc = table_meta
             .loc
             .as_ref()
@@ -318,7 +732,13 @@ where
         debug!("acquiring a probe for {}", source);
         let probe = backend.probe(source).await?;
 
-        let primary_key = &ContextKey::with_name(&self.primary);
+        let primary_key = &self
+            .primary
+            .column_in(table, &table_meta.columns)
+            .ok_or(Error::new(&format!(
+                "{} does not identify the entity `{}`",
+                table, self.primary.name
+            )))?;
         debug!(
             "acquiring maximum_frequency and row_count for {}",
             primary_key
@@ -327,20 +747,29 @@ where
             try_join!(probe.maximum_frequency(&primary_key), probe.row_count())?;
 
         let primary = PrimaryMeta {
-            key: primary_key.clone(),
+            key: ContextKey::with_name(&self.primary.name),
             maximum_frequency: maximum_frequency.clone(),
         };
 
         let access = self.access;
         let probe_ref = &probe;
+        let domains = self.domains;
 
         let mut columns = Context::new();
         let columns_with_meta: Vec<(_, crate::Result<_>)> =
             future::join_all(table_meta.columns.iter().map(
                 async move |(column, previous_meta)| {
                     let meta = try {
-                        debug!("acquiring domain for {}", column);
-                        let domain = probe_ref.domain(column).await?;
+                        // bounds read from the data disclose its extreme
+                        // values, so they are only used if allowed
+                        let domain = match domains.get(table, column) {
+                            Some(domain) => domain,
+                            None if domains.probe_undeclared => {
+                                debug!("acquiring domain for {}", column);
+                                probe_ref.domain(column).await?
+                            }
+                            None => Domain::Opaque,
+                        };
 
                         let maximum_frequency = maximum_frequency.clone();
                         let sensitivity = domain.to_sensitivity();
@@ -351,11 +780,15 @@ where
                             maximum_frequency,
                         };
 
-                        let taint = column.matches(primary_key).into();
+                        let is_primary = column.matches(primary_key);
 
                         FlexExprMeta {
                             domain_sensitivity,
-                            taint,
+                            taint: is_primary.into(),
+                            entity_match: EntityMatch {
+                                column: is_primary,
+                                equality: false,
+                            },
                         }
                     };
                     (column.clone(), meta)
@@ -382,7 +815,7 @@ where
     pub async fn rebase(&self, rel: &Relation<TableMeta>) -> Relation<FlexTableMeta> {
         rebase_closure!(rel => TableMeta -> FlexTableMeta {
             async move |table_meta, ctx_key| {
-                self.get(table_meta)
+                self.get(ctx_key, table_meta)
                     .await
                     .map_err(|err| ValidateError::Internal(err.to_string()))
             }
@@ -397,8 +830,8 @@ pub mod tests {
 
     use crate::node::state::tests::read_manifest;
     use crate::node::tests::mk_node;
-    use crate::opt::Taint;
     use crate::opt::{validate::Validator, Relation};
+    use crate::opt::{DatePart, Taint};
 
     use tokio::runtime::Runtime;
 
@@ -414,13 +847,17 @@ pub mod tests {
             let rel_t = validator.validate_str(query_str).unwrap();
             let getter = FlexTableMetaGetter {
                 access: &access,
-                primary: "person_id".to_string(),
+                primary: EntityFields::from_column("person_id"),
+                domains: &DeclaredDomains {
+                    columns: vec![],
+                    probe_undeclared: true,
+                },
             };
             let getter_ref = &getter;
             let rebase_fut = rebase_closure!(
                 rel_t => TableMeta -> FlexTableMeta {
                     async move |table_meta, key| {
-                        getter_ref.get(table_meta)
+                        getter_ref.get(key, table_meta)
                             .await
                             .map_err(|e| ValidateError::Wip("generic err".to_string()))
                     }
@@ -471,4 +908,174 @@ pub mod tests {
             }
         );
     }
+
+    #[test]
+    fn generalized_domains() {
+        let years = Domain::Discrete {
+            min: 1909,
+            max: 1983,
+            step: 1,
+        };
+        assert_eq!(
+            years.generalize(&Bucketing::FixedWidth {
+                width: 10.,
+                origin: 0.
+            }),
+            Domain::Discrete {
+                min: 1900,
+                max: 1980,
+                step: 10
+            }
+        );
+        assert_eq!(
+            years.generalize(&Bucketing::FixedWidth {
+                width: 2.5,
+                origin: 0.
+            }),
+            Domain::Continuous {
+                min: 1907.5,
+                max: 1982.5
+            }
+        );
+        assert_eq!(
+            Domain::Continuous {
+                min: 35.2,
+                max: 42.1
+            }
+            .generalize(&Bucketing::FixedWidth {
+                width: 2.,
+                origin: 0.
+            }),
+            Domain::Discrete {
+                min: 34,
+                max: 42,
+                step: 2
+            }
+        );
+        assert_eq!(
+            Domain::Opaque.generalize(&Bucketing::Boundaries(vec![18., 30., 66.])),
+            Domain::Discrete {
+                min: 18,
+                max: 66,
+                step: 12
+            }
+        );
+        assert_eq!(
+            years.generalize(&Bucketing::Truncate(DatePart::Year)),
+            Domain::Opaque
+        );
+    }
+    #[test]
+    fn arithmetic_domains() {
+        let ages = Domain::Discrete {
+            min: 0,
+            max: 100,
+            step: 1,
+        };
+        let prices = Domain::Continuous { min: 0.5, max: 20. };
+        let offsets = Domain::Discrete {
+            min: -2,
+            max: 3,
+            step: 1,
+        };
+        assert_eq!(
+            Domain::arithmetic(&ages, &BinaryOperator::Minus, &offsets),
+            Domain::Discrete {
+                min: -3,
+                max: 102,
+                step: 1
+            }
+        );
+        assert_eq!(
+            Domain::arithmetic(&prices, &BinaryOperator::Multiply, &offsets),
+            Domain::Continuous {
+                min: -40.,
+                max: 60.
+            }
+        );
+        // division by zero fails, so integer divisors are at least 1 away
+        // from zero
+        assert_eq!(
+            Domain::arithmetic(&ages, &BinaryOperator::Divide, &offsets),
+            Domain::Continuous {
+                min: -100.,
+                max: 100.
+            }
+        );
+        assert_eq!(
+            Domain::arithmetic(&ages, &BinaryOperator::Divide, &prices),
+            Domain::Continuous { min: 0., max: 200. }
+        );
+        assert_eq!(
+            Domain::arithmetic(
+                &prices,
+                &BinaryOperator::Divide,
+                &Domain::Continuous { min: -1., max: 1. }
+            ),
+            Domain::Opaque
+        );
+        assert_eq!(
+            Domain::arithmetic(&ages, &BinaryOperator::Modulus, &offsets),
+            Domain::Discrete {
+                min: 0,
+                max: 2,
+                step: 1
+            }
+        );
+        assert_eq!(
+            Domain::arithmetic(&ages, &BinaryOperator::Plus, &Domain::Opaque),
+            Domain::Opaque
+        );
+    }
+
+    #[test]
+    fn declared_domains() {
+        let range = |min: f64, max: f64, step: f64| {
+            Domain::try_from(&column_domain::Domain::Range(NumericRange {
+                min,
+                max,
+                step,
+            }))
+        };
+        assert_eq!(
+            range(1900., 2020., 10.).unwrap(),
+            Domain::Discrete {
+                min: 1900,
+                max: 2020,
+                step: 10
+            }
+        );
+        assert_eq!(
+            range(0.5, 2., 0.).unwrap(),
+            Domain::Continuous { min: 0.5, max: 2. }
+        );
+        // the bounds must be values of the domain
+        assert!(range(1900., 2020., 7.).is_err());
+        assert!(range(0., 10., -1.).is_err());
+        assert!(range(0.5, 2., 1.).is_err());
+        assert!(
+            Domain::try_from(&column_domain::Domain::Categories(CategoricalValues {
+                values: vec!["F".to_string(), "M".to_string()],
+            }))
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn entity_fields_are_qualified() {
+        let patient = EntityFields::from_entity(&Entity {
+            name: "patient".to_string(),
+            fields: vec![
+                "person.person_id".to_string(),
+                "visit_occurrence.person_id".to_string(),
+            ],
+        })
+        .unwrap();
+        let table = |name: &str| ContextKey::with_name(name).and_prefix("patient_data");
+        assert!(patient.identified_by(&table("person"), "person_id"));
+        assert!(patient.identified_by(&table("visit_occurrence"), "person_id"));
+        // a column of the same name in another table is something else
+        assert!(!patient.identified_by(&table("care_site"), "person_id"));
+        assert!(!patient.identified_by(&table("person"), "care_site_id"));
+    }
 }
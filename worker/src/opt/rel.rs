@@ -111,6 +111,15 @@ impl<V, E> JoinOperator<std::result::Result<V, E>> {
 }
 
 impl<E> JoinOperator<E> {
+    pub fn constraint(&self) -> Option<&JoinConstraint<E>> {
+        match self {
+            JoinOperator::Inner(constraint)
+            | JoinOperator::LeftOuter(constraint)
+            | JoinOperator::RightOuter(constraint)
+            | JoinOperator::FullOuter(constraint) => Some(constraint),
+            JoinOperator::CrossJoin => None,
+        }
+    }
     pub async fn map_expressions_async<'a, O, F, Fut>(&'a self, f: F) -> JoinOperator<O>
     where
         F: Fn(&'a E) -> Fut,
@@ -753,6 +762,10 @@ pub struct TableMeta {
     pub source: Option<ContextKey>,
     pub provenance: Option<ContextKey>,
     pub audience: HashSet<BlockType>,
+    /// The audience allowed to use the filters and join constraints applied
+    /// below this relation, if there are any
+    #[serde(default)]
+    pub predicates: Option<HashSet<BlockType>>,
 }
 
 impl TableMeta {
@@ -806,7 +819,54 @@ impl RelRepr<ExprMeta> for TableMeta {
         };
         let provenance = ContextKey::common(provenances);
 
+        let mut predicates: Option<HashSet<BlockType>> = None;
+        let mut restrict = |audience: &HashSet<BlockType>| {
+            predicates = Some(match predicates.take() {
+                Some(predicates) => predicates.intersection(audience).cloned().collect(),
+                None => audience.clone(),
+            });
+        };
+        node.map(&mut |child| {
+            if let Some(child_predicates) = child.predicates.as_ref() {
+                restrict(child_predicates);
+            }
+        });
+        match &node {
+            GenericRel::Selection(Selection { where_, .. }) => restrict(&where_.audience),
+            GenericRel::Join(Join {
+                left,
+                right,
+                operator,
+            }) => match operator.constraint() {
+                Some(JoinConstraint::On(on)) => restrict(&on.audience),
+                Some(JoinConstraint::Using(keys)) => {
+                    for key in keys.iter() {
+                        for side in &[left, right] {
+                            match side.columns.get_column(key) {
+                                Ok(expr_meta) => restrict(&expr_meta.audience),
+                                Err(..) => restrict(&HashSet::new()),
+                            }
+                        }
+                    }
+                }
+                Some(JoinConstraint::Natural) => {
+                    for (key, expr_meta) in left.columns.iter() {
+                        let right_key = ContextKey::with_name(key.name());
+                        if let Ok(right_expr_meta) = right.columns.get_column(&right_key) {
+                            restrict(&expr_meta.audience);
+                            restrict(&right_expr_meta.audience);
+                        }
+                    }
+                }
+                None => {}
+            },
+            _ => {}
+        }
+
         let mut audiences = Vec::new();
+        if let Some(predicates) = predicates.as_ref() {
+            audiences.push(predicates);
+        }
         match node {
             GenericRel::Projection(Projection { attributes, .. }) => attributes
                 .iter()
@@ -833,6 +893,7 @@ impl RelRepr<ExprMeta> for TableMeta {
             source,
             provenance,
             audience,
+            predicates,
         })
     }
 }
@@ -1,12 +1,18 @@
 use crate::common::*;
 
+use sqlparser::ast;
+
 use super::{
-    expr::As, Aggregation, AudienceBoard, Between, BinaryOp, BinaryOperator, Column, Context,
-    ContextKey, Distribution, Expr, ExprMeta, ExprT, ExprTree, Function, FunctionName, GenericRel,
-    GenericRelTree, Hash, HashAlgorithm, Literal, LiteralValue, Noisy, Projection, Rel, RelT,
-    Selection, Table, TableMeta, ToContext, TryToContext, ValidateError,
+    expr::As, validate_re2, Aggregation, AudienceBoard, Between, BinaryOp, BinaryOperator,
+    Bucketing, Column, Context, ContextKey, DataType, Detector, Distinct, Distribution, Expr,
+    ExprMeta, ExprT, ExprTree, Function, FunctionName, Generalize, GenericRel, GenericRelTree,
+    Hash, HashAlgorithm, HashEncoding, InList, InTable, Join, JoinConstraint, Keying, Limit,
+    Literal, LiteralValue, Mask, Masking, Noisy, NoisyQuantile, Offset, Order, OrderBy,
+    PartitionRank, Projection, Redact, Rel, RelT, Selection, Set, Table, TableMeta, ToContext,
+    Tokenize, TryToContext, ValidateError, ValidateExpr, Watermark, WithAlias,
 };
 use crate::node::Access;
+use crate::opt::validate::{ExprValidator, Validator};
 use crate::opt::{ContextError, RebaseRel};
 
 use super::privacy::*;
@@ -25,16 +31,161 @@ fn matches_in<'a, I: IntoIterator<Item = &'a String>>(
 }
 
 #[derive(Debug, Clone)]
-pub struct Policy(pub policy::Policy);
+pub struct Policy {
+    pub policy: policy::Policy,
+    /// The wrapped keyset resolved from the secret the policy references, if
+    /// any, along with what pseudonyms are bound to
+    pub keying: Option<Keying>,
+    /// The entity resolved from the dataset the policy belongs to, if any
+    pub entity: Option<EntityFields>,
+    /// The public domains declared by the dataset the policy belongs to
+    pub domains: DeclaredDomains,
+    /// The watermark of the pseudonyms the policy produces, if the dataset
+    /// the policy belongs to is watermarked
+    pub watermark: Option<Watermark>,
+    /// The parsed expressions of the policy, if it is an expression policy
+    pub templates: Vec<ExprT>,
+}
+
+impl Policy {
+    pub fn new(policy: policy::Policy) -> Self {
+        Self {
+            policy,
+            keying: None,
+            entity: None,
+            domains: DeclaredDomains::default(),
+            watermark: None,
+            templates: Vec::new(),
+        }
+    }
+
+    /// Parse the expressions of the policy once, if it is an expression
+    /// policy
+    pub fn parse_templates(&mut self) -> Result<(), ValidateError> {
+        if let policy::Policy::Expression(ExpressionPolicy { expressions, .. }) = &self.policy {
+            let ctx = Context::new();
+            let validator = ExprValidator::new(&ctx);
+            self.templates = expressions
+                .iter()
+                .map(|template| validator.validate_str(template))
+                .collect::<Result<_, _>>()?;
+        }
+        Ok(())
+    }
+
+    /// Scale the noise the policy adds by `multiplier`, if it is
+    /// differentially private
+    pub fn scale_noise(&mut self, multiplier: f64) {
+        if let policy::Policy::DifferentialPrivacy(differential_privacy) = &mut self.policy {
+            differential_privacy.epsilon /= multiplier;
+        }
+    }
+
+    /// Seed the watermark of the policy with `seed`, if it has one
+    pub fn seed_watermark(&mut self, seed: &str) {
+        if let Some(watermark) = &mut self.watermark {
+            watermark.seed = seed.to_string();
+        }
+    }
+
+    /// The entity the policy protects, falling back on `column` if it was
+    /// not declared by the dataset
+    fn entity_or(&self, column: &str) -> EntityFields {
+        self.entity
+            .clone()
+            .unwrap_or_else(|| EntityFields::from_column(column))
+    }
+
+    /// Whether the policy may be applied to expressions used in `usage`
+    fn usable_in(&self, usage: Usage) -> bool {
+        let predicate_usage = match &self.policy {
+            policy::Policy::Whitelist(WhitelistPolicy { usage, .. })
+            | policy::Policy::Hash(HashPolicy { usage, .. })
+            | policy::Policy::Mask(MaskPolicy { usage, .. })
+            | policy::Policy::Generalize(GeneralizePolicy { usage, .. })
+            | policy::Policy::Tokenize(TokenizePolicy { usage, .. })
+            | policy::Policy::Expression(ExpressionPolicy { usage, .. }) => usage.as_ref(),
+            // relation policies only vouch for joins on the entity they
+            // protect
+            policy::Policy::DifferentialPrivacy(..) | policy::Policy::Aggregation(..) => {
+                return usage == Usage::Join
+            }
+            _ => None,
+        };
+        match usage {
+            Usage::Projection => true,
+            Usage::Predicate => predicate_usage
+                .map(|predicate_usage| predicate_usage.usable_in_predicates)
+                .unwrap_or(false),
+            Usage::Join => predicate_usage
+                .map(|predicate_usage| predicate_usage.usable_in_joins)
+                .unwrap_or(false),
+        }
+    }
+}
+
+/// Where in a query an expression is used
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Usage {
+    /// In the attributes of a projection
+    Projection,
+    /// In a `WHERE` clause
+    Predicate,
+    /// In a join constraint
+    Join,
+}
+
+/// The privacy spent by a transform, to be charged to the budget of the
+/// binding it was applied under
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Cost {
+    pub epsilon: f64,
+    pub delta: f64,
+}
+
+impl Cost {
+    pub fn epsilon(epsilon: f64) -> Self {
+        Self { epsilon, delta: 0. }
+    }
+
+    /// Whether this can be charged to `budget`. Budgets without a
+    /// `maximum_delta`, such as those declared before delta was accounted
+    /// for, only limit epsilon.
+    pub fn fits(&self, budget: &PolicyBudget) -> bool {
+        budget.used + self.epsilon <= budget.maximum
+            && (budget.maximum_delta == 0.
+                || budget.used_delta + self.delta <= budget.maximum_delta)
+    }
+}
+
+impl std::ops::AddAssign for Cost {
+    fn add_assign(&mut self, other: Self) {
+        self.epsilon += other.epsilon;
+        self.delta += other.delta;
+    }
+}
+
+impl std::iter::Sum for Cost {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        let mut total = Self::default();
+        for cost in iter {
+            total += cost;
+        }
+        total
+    }
+}
 
 pub struct Costly<T> {
     root: T,
-    cost: f64,
+    cost: Cost,
 }
 
 impl<T> From<T> for Costly<T> {
     fn from(root: T) -> Self {
-        Self { root, cost: 0. }
+        Self {
+            root,
+            cost: Cost::default(),
+        }
     }
 }
 
@@ -53,16 +204,85 @@ impl ExprTransform for WhitelistPolicy {
     }
 }
 
-impl ExprTransform for HashPolicy {
+/// A policy together with the key material resolved for it
+struct Keyed<'a, P> {
+    policy: &'a P,
+    keying: Option<&'a Keying>,
+    watermark: Option<&'a Watermark>,
+}
+
+impl<'a> Keyed<'a, HashPolicy> {
+    fn to_hash(&self, expr: &ExprT) -> Result<Expr<ExprT>, ValidateError> {
+        let (algo, key) = match self.policy.key.as_ref() {
+            Some(hash_key) => {
+                let keying = self.keying.ok_or(ValidateError::Expected(format!(
+                    "the secret `{}` to have been resolved",
+                    hash_key.secret
+                )))?;
+                (hash_key.algorithm.parse()?, keying.clone())
+            }
+            None => (
+                HashAlgorithm::SHA256,
+                Keying::Salt(self.policy.salt.clone()),
+            ),
+        };
+        let (encoding, truncate) = match self.policy.output.as_ref() {
+            Some(output) => (
+                output.encoding.parse()?,
+                Some(output.truncate).filter(|truncate| *truncate > 0),
+            ),
+            None => (HashEncoding::default(), None),
+        };
+        Ok(Expr::Hash(Hash {
+            algo,
+            expr: expr.clone(),
+            key,
+            encoding,
+            truncate,
+            watermark: self.watermark.cloned(),
+        }))
+    }
+}
+
+impl<'a> ExprTransform for Keyed<'a, HashPolicy> {
     fn transform_expr(&self, expr: &ExprT) -> Result<Costly<ExprT>, Error> {
         match expr.as_ref() {
             Expr::Column(Column(context_key)) => {
-                if matches_in(self.fields.iter(), &context_key)? {
+                if matches_in(self.policy.fields.iter(), &context_key)? {
+                    Ok(ExprT::from(Expr::As(As {
+                        expr: ExprT::from(self.to_hash(expr)?),
+                        alias: context_key.name().to_string(),
+                    }))
+                    .into())
+                } else {
+                    Err(Error::NoMatch)
+                }
+            }
+            _ => Err(Error::NoMatch),
+        }
+    }
+}
+
+impl<'a> ExprTransform for Keyed<'a, TokenizePolicy> {
+    fn transform_expr(&self, expr: &ExprT) -> Result<Costly<ExprT>, Error> {
+        match expr.as_ref() {
+            Expr::Column(Column(context_key)) => {
+                if matches_in(self.policy.fields.iter(), &context_key)? {
+                    let (keyset, context) = match self.keying {
+                        Some(Keying::Keyset { keyset, context }) => (keyset, context),
+                        _ => {
+                            return Err(ValidateError::Expected(format!(
+                                "the secret `{}` to have been resolved",
+                                self.policy.key
+                            ))
+                            .into())
+                        }
+                    };
                     Ok(ExprT::from(Expr::As(As {
-                        expr: ExprT::from(Expr::Hash(Hash {
-                            algo: HashAlgorithm::default(),
+                        expr: ExprT::from(Expr::Tokenize(Tokenize {
                             expr: expr.clone(),
-                            salt: self.salt.clone(),
+                            keyset: keyset.clone(),
+                            context: context.clone(),
                         })),
                         alias: context_key.name().to_string(),
                     }))
@@ -93,24 +313,475 @@ impl ExprTransform for ObfuscatePolicy {
     }
 }
 
+fn masking_for(mode: Option<&MaskMode>) -> Result<Masking, ValidateError> {
+    let mask_or_default = |mask: &String| {
+        if mask.is_empty() {
+            "*".to_string()
+        } else {
+            mask.clone()
+        }
+    };
+    match mode.and_then(|mode| mode.mode.as_ref()) {
+        Some(mask_mode::Mode::KeepFirst(keep)) => Ok(Masking::KeepFirst {
+            count: keep.count,
+            mask: mask_or_default(&keep.mask),
+        }),
+        Some(mask_mode::Mode::KeepLast(keep)) => Ok(Masking::KeepLast {
+            count: keep.count,
+            mask: mask_or_default(&keep.mask),
+        }),
+        Some(mask_mode::Mode::RegexReplace(regex_replace)) => {
+            validate_re2(&regex_replace.pattern)?;
+            Ok(Masking::RegexReplace {
+                pattern: regex_replace.pattern.clone(),
+                replacement: regex_replace.replacement.clone(),
+            })
+        }
+        Some(mask_mode::Mode::Format(format_mask)) => {
+            Ok(Masking::Format(format_mask.format.clone()))
+        }
+        None => Err(ValidateError::Expected(
+            "mask policies to specify a mode".to_string(),
+        )),
+    }
+}
+
+impl ExprTransform for MaskPolicy {
+    fn transform_expr(&self, expr: &ExprT) -> Result<Costly<ExprT>, Error> {
+        match expr.as_ref() {
+            Expr::Column(Column(context_key)) => {
+                if matches_in(self.fields.iter(), &context_key)? {
+                    let masking = masking_for(self.mode.as_ref())?;
+                    Ok(ExprT::from(Expr::As(As {
+                        expr: ExprT::from(Expr::Mask(Mask {
+                            expr: expr.clone(),
+                            masking,
+                        })),
+                        alias: context_key.name().to_string(),
+                    }))
+                    .into())
+                } else {
+                    Err(Error::NoMatch)
+                }
+            }
+            _ => Err(Error::NoMatch),
+        }
+    }
+}
+
+fn detectors_for(redact: &RedactPolicy) -> Result<Vec<Detector>, ValidateError> {
+    for listed in redact.detectors.iter() {
+        Detector::builtin(listed)?;
+    }
+    // built-in detectors are applied in their own order, whatever the order
+    // they are listed in
+    let mut detectors = Detector::BUILTINS
+        .iter()
+        .filter(|name| {
+            redact.detectors.is_empty() || redact.detectors.iter().any(|listed| listed == *name)
+        })
+        .map(|name| Detector::builtin(name))
+        .collect::<Result<Vec<_>, _>>()?;
+    for custom in redact.custom_detectors.iter() {
+        if custom.name.is_empty()
+            || !custom
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            return Err(ValidateError::Expected(format!(
+                "the name of a detector to be made of letters, digits and `_`, got `{}`",
+                custom.name
+            )));
+        }
+        validate_re2(&custom.pattern)?;
+        detectors.push(Detector {
+            name: custom.name.clone(),
+            pattern: custom.pattern.clone(),
+        });
+    }
+    Ok(detectors)
+}
+
+impl ExprTransform for RedactPolicy {
+    fn transform_expr(&self, expr: &ExprT) -> Result<Costly<ExprT>, Error> {
+        match expr.as_ref() {
+            Expr::Column(Column(context_key)) => {
+                if matches_in(self.fields.iter(), &context_key)? {
+                    let detectors = detectors_for(self)?;
+                    Ok(ExprT::from(Expr::As(As {
+                        expr: ExprT::from(Expr::Redact(Redact {
+                            expr: expr.clone(),
+                            detectors,
+                        })),
+                        alias: context_key.name().to_string(),
+                    }))
+                    .into())
+                } else {
+                    Err(Error::NoMatch)
+                }
+            }
+            _ => Err(Error::NoMatch),
+        }
+    }
+}
+
+fn generalization_for(generalization: Option<&Generalization>) -> Result<Bucketing, ValidateError> {
+    match generalization.and_then(|generalization| generalization.generalization.as_ref()) {
+        Some(generalization::Generalization::FixedWidth(fixed_width)) => {
+            if fixed_width.width > 0. {
+                Ok(Bucketing::FixedWidth {
+                    width: fixed_width.width,
+                    origin: fixed_width.origin,
+                })
+            } else {
+                Err(ValidateError::Expected(
+                    "the width of fixed-width bins to be positive".to_string(),
+                ))
+            }
+        }
+        Some(generalization::Generalization::Boundaries(boundaries)) => {
+            let boundaries = &boundaries.boundaries;
+            if boundaries.is_empty() {
+                Err(ValidateError::Expected("at least one boundary".to_string()))
+            } else if boundaries
+                .iter()
+                .zip(boundaries.iter().skip(1))
+                .any(|(lower, upper)| lower >= upper)
+            {
+                Err(ValidateError::Expected(
+                    "boundaries to be in increasing order".to_string(),
+                ))
+            } else {
+                Ok(Bucketing::Boundaries(boundaries.clone()))
+            }
+        }
+        Some(generalization::Generalization::DateTruncation(date_truncation)) => {
+            Ok(Bucketing::Truncate(date_truncation.part.parse()?))
+        }
+        None => Err(ValidateError::Expected(
+            "generalize policies to specify a generalization".to_string(),
+        )),
+    }
+}
+
+impl ExprTransform for GeneralizePolicy {
+    fn transform_expr(&self, expr: &ExprT) -> Result<Costly<ExprT>, Error> {
+        match expr.as_ref() {
+            Expr::Column(Column(context_key)) => {
+                if matches_in(self.fields.iter(), &context_key)? {
+                    let bucketing = generalization_for(self.generalization.as_ref())?;
+                    Ok(ExprT::from(Expr::As(As {
+                        expr: ExprT::from(Expr::Generalize(Generalize {
+                            expr: expr.clone(),
+                            bucketing,
+                        })),
+                        alias: context_key.name().to_string(),
+                    }))
+                    .into())
+                } else {
+                    Err(Error::NoMatch)
+                }
+            }
+            _ => Err(Error::NoMatch),
+        }
+    }
+}
+
+/// Whether `expr` has the same shape as `template`. The columns of `template`
+/// are field patterns which only match columns.
+fn matches_template(expr: &ExprT, template: &ExprT) -> bool {
+    match (expr.as_ref(), template.as_ref()) {
+        (Expr::Column(Column(key)), Expr::Column(Column(pattern))) => key.matches(pattern),
+        (_, Expr::Column(..)) | (Expr::Column(..), _) => false,
+        (node, template_node) => {
+            if node.map(&mut |_| ()) != template_node.map(&mut |_| ()) {
+                return false;
+            }
+            let mut children = Vec::new();
+            node.map(&mut |child| children.push(*child));
+            let mut template_children = Vec::new();
+            template_node.map(&mut |child| template_children.push(*child));
+            children
+                .into_iter()
+                .zip(template_children)
+                .all(|(child, template_child)| matches_template(child, template_child))
+        }
+    }
+}
+
+/// An entity together with the sides of a join, whose constraint may match
+/// the columns identifying the entity on both sides
+struct JoinedEntity<'a> {
+    entity: EntityFields,
+    left: &'a TableMeta,
+    right: &'a TableMeta,
+}
+
+impl<'a> JoinedEntity<'a> {
+    /// Whether `expr` is a column of a table of the join identifying the
+    /// entity
+    fn identifies(&self, expr: &ExprT) -> bool {
+        let column = match expr.as_ref() {
+            Expr::Column(Column(column)) => column,
+            _ => return false,
+        };
+        [self.left, self.right]
+            .iter()
+            .find(|side| side.columns.get(column).is_ok())
+            .and_then(|side| side.provenance.as_ref())
+            .map(|table| self.entity.identified_by(table, column.name()))
+            .unwrap_or(false)
+    }
+}
+
+impl<'a> ExprTransform for JoinedEntity<'a> {
+    fn transform_expr(&self, expr: &ExprT) -> Result<Costly<ExprT>, Error> {
+        match expr.as_ref() {
+            Expr::BinaryOp(BinaryOp {
+                left,
+                op: BinaryOperator::Eq,
+                right,
+            }) if self.identifies(left) && self.identifies(right) => Ok(expr.clone().into()),
+            _ => Err(Error::NoMatch),
+        }
+    }
+}
+
+impl Policy {
+    /// Transform `expr`, the constraint of a join between `left` and `right`
+    fn transform_join_expr(
+        &self,
+        expr: &ExprT,
+        left: &TableMeta,
+        right: &TableMeta,
+    ) -> Result<Costly<ExprT>, Error> {
+        match &self.policy {
+            policy::Policy::DifferentialPrivacy(DifferentialPrivacyPolicy { entity, .. })
+            | policy::Policy::Aggregation(AggregationPolicy { entity, .. }) => JoinedEntity {
+                entity: self.entity_or(entity),
+                left,
+                right,
+            }
+            .transform_expr(expr),
+            _ => self.transform_expr(expr),
+        }
+    }
+}
+
 impl ExprTransform for Policy {
     fn transform_expr(&self, expr: &ExprT) -> Result<Costly<ExprT>, Error> {
-        match &self.0 {
+        match &self.policy {
             policy::Policy::Whitelist(whitelist) => whitelist.transform_expr(expr),
-            policy::Policy::Hash(hash) => hash.transform_expr(expr),
+            policy::Policy::Hash(hash) => Keyed {
+                policy: hash,
+                keying: self.keying.as_ref(),
+                watermark: self.watermark.as_ref(),
+            }
+            .transform_expr(expr),
+            policy::Policy::Tokenize(tokenize) => Keyed {
+                policy: tokenize,
+                keying: self.keying.as_ref(),
+                watermark: None,
+            }
+            .transform_expr(expr),
             policy::Policy::Obfuscate(obfuscate) => obfuscate.transform_expr(expr),
+            policy::Policy::Mask(mask) => mask.transform_expr(expr),
+            policy::Policy::Redact(redact) => redact.transform_expr(expr),
+            policy::Policy::Generalize(generalize) => generalize.transform_expr(expr),
+            policy::Policy::Expression(..) => {
+                if self
+                    .templates
+                    .iter()
+                    .any(|template| matches_template(expr, template))
+                {
+                    Ok(expr.clone().into())
+                } else {
+                    Err(Error::NoMatch)
+                }
+            }
             _ => Err(Error::NoMatch),
         }
     }
 }
 
+/// A policy together with the entity it protects and the public domains of
+/// its dataset
+struct WithEntity<'a, P> {
+    policy: &'a P,
+    entity: EntityFields,
+    domains: &'a DeclaredDomains,
+}
+
+/// The column identifying `entity` in `rel_t`, or in the first of its
+/// descendants where it can be resolved
+fn entity_column(entity: &EntityFields, rel_t: &RelT) -> Option<ContextKey> {
+    let board = rel_t.board.as_ref().ok()?;
+    // only columns of the tables the entity is identified in, so that a
+    // column of another table with the same name is never taken for it
+    let column = board
+        .provenance
+        .as_ref()
+        .and_then(|table| entity.column_in(table, &board.columns));
+    match column {
+        Some(column) => Some(column),
+        None => {
+            let mut children = Vec::new();
+            rel_t.root.map(&mut |child| children.push(child.clone()));
+            children
+                .iter()
+                .find_map(|child| entity_column(entity, child))
+        }
+    }
+}
+
+/// Pushes the columns `expr` reads to `columns`, once each
+fn columns_of(expr: &ExprT, columns: &mut Vec<ContextKey>) {
+    match &expr.root {
+        Expr::Column(Column(key)) => {
+            if !columns.contains(key) {
+                columns.push(key.clone());
+            }
+        }
+        root => {
+            root.map(&mut |child| columns_of(child, columns));
+        }
+    }
+}
+
+/// `expr` with the columns of `names` renamed
+fn rename_columns(expr: &ExprT, names: &HashMap<ContextKey, String>) -> ExprT {
+    match &expr.root {
+        Expr::Column(Column(key)) => match names.get(key) {
+            Some(name) => ExprT::from(Expr::Column(Column(ContextKey::with_name(name)))),
+            None => expr.clone(),
+        },
+        root => ExprT::from(root.map(&mut |child| rename_columns(child, names))),
+    }
+}
+
+/// The number of values a continuous domain is split into when choosing a
+/// quantile among them, and the most candidates a discrete domain is left with
+const QUANTILE_CANDIDATES: f64 = 1000.;
+
+/// Whether `flex_expr` is an aggregate over the column identifying the entity
+fn counts_entity(flex_expr: &ExprT<FlexExprMeta>) -> bool {
+    match flex_expr.as_ref() {
+        Expr::Function(Function { args, .. }) => {
+            !args.is_empty()
+                && args.iter().all(|arg| {
+                    arg.board
+                        .as_ref()
+                        .map(|board| board.entity_match.column)
+                        .unwrap_or(false)
+                })
+        }
+        _ => false,
+    }
+}
+
+/// The groups of an aggregation with the `k` largest values of `by`, as in
+/// `ORDER BY <by> DESC LIMIT <k>`
+struct TopK {
+    by: ExprT,
+    k: u64,
+}
+
+impl TopK {
+    /// The aggregation `rel_t` selects the top groups of, if it does
+    fn of(rel_t: &RelT) -> Option<(&RelT, Self)> {
+        let (number_rows, from) = match &rel_t.root {
+            Rel::Limit(Limit { number_rows, from }) => (number_rows, from),
+            _ => return None,
+        };
+        let k = match number_rows.as_ref() {
+            Expr::Literal(Literal(LiteralValue::Long(k))) if k > 0 => k as u64,
+            _ => return None,
+        };
+        match &from.root {
+            Rel::OrderBy(OrderBy { order, by, from }) => match (order.as_slice(), by.as_slice()) {
+                ([Order::Desc], [by]) => match &from.root {
+                    Rel::Aggregation(Aggregation { attributes, .. }) => Some((
+                        &**from,
+                        Self {
+                            by: Self::resolve_alias(by, attributes),
+                            k,
+                        },
+                    )),
+                    _ => None,
+                },
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// The aggregate `by` refers to, when it is the alias of one of the
+    /// `attributes`
+    fn resolve_alias(by: &ExprT, attributes: &[ExprT]) -> ExprT {
+        if let Expr::Column(Column(key)) = by.as_ref() {
+            for attribute in attributes.iter() {
+                match attribute.as_ref() {
+                    Expr::As(As { expr, alias }) if key.name() == alias => return expr.clone(),
+                    _ => {}
+                }
+            }
+        }
+        by.clone()
+    }
+}
+
+/// Selection of the groups released by a differentially private
+/// aggregation, after "Differentially Private SQL with Bounded User
+/// Contribution" (Wilson et al., 2019). Every entity adds one to the count
+/// of at most `max_partitions_contributed` groups.
+struct PartitionSelection {
+    epsilon: f64,
+    delta: f64,
+    max_partitions_contributed: f64,
+}
+
+impl PartitionSelection {
+    fn new(epsilon: f64, delta: f64, max_partitions_contributed: u64) -> Option<Self> {
+        if epsilon > 0. && delta > 0. && delta < 1. && max_partitions_contributed > 0 {
+            Some(Self {
+                epsilon,
+                delta,
+                max_partitions_contributed: max_partitions_contributed as f64,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// The scale of the Laplace noise added to the count of entities
+    fn scale(&self) -> f64 {
+        self.max_partitions_contributed / self.epsilon
+    }
+
+    /// The noisy count of entities a group must exceed to be released, so
+    /// that the groups of a single entity are released with probability at
+    /// most `delta`
+    fn threshold(&self) -> f64 {
+        let delta_per_partition = 1. - (1. - self.delta).powf(1. / self.max_partitions_contributed);
+        1. - self.scale() * (2. * delta_per_partition).ln()
+    }
+}
+
 #[async_trait]
-impl RelTransform for DifferentialPrivacyPolicy {
+impl<'a> RelTransform for WithEntity<'a, DifferentialPrivacyPolicy> {
     async fn transform_rel<A: Access>(
         &self,
         rel: &RelT,
         access: &A,
     ) -> Result<Costly<RelT>, Error> {
+        // ordering noisy aggregates and keeping the first rows would reveal
+        // more than the noisy aggregates themselves, so the top groups are
+        // selected with their own mechanism
+        let (rel, top_k) = match TopK::of(rel) {
+            Some((aggregation, top_k)) => (aggregation, Some(top_k)),
+            None => (rel, None),
+        };
         match rel.as_ref() {
             GenericRel::Aggregation(Aggregation {
                 attributes,
@@ -120,6 +791,7 @@ impl RelTransform for DifferentialPrivacyPolicy {
                 // FIXME: This could be optimized
                 let getter = FlexTableMetaGetter {
                     primary: self.entity.clone(),
+                    domains: self.domains,
                     access,
                 };
                 let flex = getter.rebase(rel).await;
@@ -129,29 +801,19 @@ impl RelTransform for DifferentialPrivacyPolicy {
                     return Err(Error::NoMatch);
                 }
 
-                let (flex_attributes, flex_group_by, flex_from) = match flex.as_ref() {
+                let (flex_attributes, flex_group_by) = match flex.as_ref() {
                     GenericRel::Aggregation(Aggregation {
                         attributes,
                         group_by,
-                        from,
-                    }) => (attributes, group_by, from),
+                        ..
+                    }) => (attributes, group_by),
                     _ => unreachable!(),
                 };
 
-                let mut factor = 1.;
                 let mut grouping_keys = HashSet::new();
                 for (expr, flex_expr) in group_by.iter().zip(flex_group_by.iter()) {
                     if let Expr::Column(Column(column_key)) = expr.as_ref() {
                         grouping_keys.insert(column_key);
-                        let col_maximum_frequency = flex_expr
-                            .board
-                            .as_ref()
-                            .map_err(|e| e.clone())?
-                            .domain_sensitivity
-                            .maximum_frequency
-                            .0
-                            .ok_or(Error::NoMatch)?;
-                        factor *= col_maximum_frequency as f64;
                     } else {
                         return Err(Error::NoMatch);
                     }
@@ -163,36 +825,59 @@ impl RelTransform for DifferentialPrivacyPolicy {
                 let bucket_alias = "__bucket_count";
                 let bucket_key = ContextKey::with_name(bucket_alias);
 
-                let maximum_frequency = flex_from
-                    .board
-                    .as_ref()
-                    .map_err(|e| e.clone())?
-                    .primary
-                    .maximum_frequency
-                    .0
-                    .ok_or(Error::NoMatch)?;
-
-                let threshold = (self.bucket_size * maximum_frequency) as i64;
-
-                let one = ExprT::from(Expr::Literal(Literal(LiteralValue::Long(1))));
+                // groups are selected on their noisy count of entities
+                let entity_key = entity_column(&self.entity, from).ok_or(Error::NoMatch)?;
+                let selection = PartitionSelection::new(
+                    self.policy.epsilon,
+                    self.policy.delta,
+                    self.policy.max_partitions_contributed,
+                )
+                .ok_or(Error::NoMatch)?;
+
+                // an entity can add to the aggregates of as many groups as
+                // it contributes to, which the noise on them is scaled by
+                let partitions = if group_by.is_empty() {
+                    1.
+                } else {
+                    self.policy.max_partitions_contributed as f64
+                };
 
-                // this cost is per row
-                let mut cost = 0.;
+                // this cost is per row, starting with the selection of the
+                // groups
+                let mut cost = Cost {
+                    epsilon: self.policy.epsilon,
+                    delta: self.policy.delta,
+                };
                 let mut new_attributes = Vec::new();
                 let mut projection_attributes = Vec::new();
+                let score_alias = "__top_k_score";
+                let mut top_k_index = None;
                 for (i, (expr, flex_expr)) in
                     attributes.iter().zip(flex_attributes.iter()).enumerate()
                 {
+                    // aliased attributes are released under their alias
+                    let (expr, flex_expr, alias) = match (expr.as_ref(), flex_expr.as_ref()) {
+                        (
+                            Expr::As(As { expr, alias }),
+                            Expr::As(As {
+                                expr: flex_expr, ..
+                            }),
+                        ) => (expr, flex_expr, Some(alias)),
+                        _ => (expr, flex_expr, None),
+                    };
                     match expr.as_ref() {
                         Expr::Column(Column(column_key)) => {
                             if !grouping_keys.contains(&column_key) {
                                 return Err(Error::NoMatch);
                             }
+                            let alias = alias.unwrap_or_else(|| column_key.name().to_string());
                             new_attributes.push(ExprT::from(Expr::As(As {
                                 expr: expr.clone(),
-                                alias: column_key.name().to_string(),
+                                alias: alias.clone(),
                             })));
-                            projection_attributes.push(expr.clone());
+                            projection_attributes.push(ExprT::from(Expr::Column(Column(
+                                ContextKey::with_name(&alias),
+                            ))));
                         }
                         Expr::Function(Function {
                             name,
@@ -201,26 +886,108 @@ impl RelTransform for DifferentialPrivacyPolicy {
                         }) => {
                             // assuming function is aggregation
                             let board = flex_expr.board.as_ref().map_err(|e| e.clone())?;
-                            let sensitivity = board
-                                .domain_sensitivity
-                                .sensitivity
-                                .0
-                                .ok_or(Error::NoMatch)?;
-
-                            let distribution = Distribution::Laplace {
-                                mean: 0.,
-                                variance: sensitivity / self.epsilon,
+                            let noisy = match name {
+                                FunctionName::Median | FunctionName::Quantile => {
+                                    let quantile = match (&name, args.get(1).map(|q| q.as_ref())) {
+                                        (FunctionName::Median, _) => 0.5,
+                                        (
+                                            _,
+                                            Some(Expr::Literal(Literal(LiteralValue::Double(q)))),
+                                        ) if q >= 0. && q <= 1. => q,
+                                        _ => return Err(Error::NoMatch),
+                                    };
+                                    // the candidates are drawn from the
+                                    // domain, which must then be bounded
+                                    let (min, max, step) = match board.domain_sensitivity.domain {
+                                        Domain::Discrete { min, max, step } => {
+                                            (min as f64, max as f64, step as f64)
+                                        }
+                                        Domain::Continuous { min, max } if max > min => {
+                                            (min, max, (max - min) / QUANTILE_CANDIDATES)
+                                        }
+                                        _ => return Err(Error::NoMatch),
+                                    };
+                                    // candidates are widened into fewer
+                                    // ones when there would be too many
+                                    let step = step
+                                        * ((max - min) / (step * QUANTILE_CANDIDATES))
+                                            .ceil()
+                                            .max(1.);
+                                    // every row of an entity can move the
+                                    // rank of a candidate by one
+                                    let sensitivity = board
+                                        .domain_sensitivity
+                                        .maximum_frequency
+                                        .0
+                                        .ok_or(Error::NoMatch)?
+                                        as f64
+                                        * partitions;
+                                    Expr::NoisyQuantile(NoisyQuantile {
+                                        expr: args[0].clone(),
+                                        quantile,
+                                        min,
+                                        max,
+                                        step,
+                                        epsilon: self.policy.epsilon,
+                                        sensitivity,
+                                    })
+                                }
+                                _ => {
+                                    let sensitivity = if name == FunctionName::Count
+                                        && distinct
+                                        && counts_entity(flex_expr)
+                                    {
+                                        // every entity is counted once
+                                        1.
+                                    } else {
+                                        board
+                                            .domain_sensitivity
+                                            .sensitivity
+                                            .0
+                                            .ok_or(Error::NoMatch)?
+                                    } * partitions;
+                                    Expr::Noisy(Noisy {
+                                        expr: expr.clone(),
+                                        distribution: Distribution::Laplace {
+                                            mean: 0.,
+                                            variance: sensitivity / self.policy.epsilon,
+                                        },
+                                    })
+                                }
                             };
 
-                            cost += self.epsilon;
+                            cost += Cost::epsilon(self.policy.epsilon);
+
+                            if let Some(top_k) = top_k.as_ref() {
+                                if top_k_index.is_none() && matches_template(expr, &top_k.by) {
+                                    // report noisy max over the groups, which
+                                    // costs epsilon for the k of them
+                                    let sensitivity = board
+                                        .domain_sensitivity
+                                        .sensitivity
+                                        .0
+                                        .ok_or(Error::NoMatch)?
+                                        * partitions;
+                                    new_attributes.push(ExprT::from(Expr::As(As {
+                                        expr: ExprT::from(Expr::Noisy(Noisy {
+                                            expr: expr.clone(),
+                                            distribution: Distribution::Laplace {
+                                                mean: 0.,
+                                                variance: 2. * top_k.k as f64 * sensitivity
+                                                    / self.policy.epsilon,
+                                            },
+                                        })),
+                                        alias: score_alias.to_string(),
+                                    })));
+                                    cost += Cost::epsilon(self.policy.epsilon);
+                                    top_k_index = Some(i);
+                                }
+                            }
 
-                            let alias = format!("f{}_", i);
+                            let alias = alias.unwrap_or_else(|| format!("f{}_", i));
 
                             let new_expr = ExprT::from(Expr::As(As {
-                                expr: ExprT::from(Expr::Noisy(Noisy {
-                                    expr: expr.clone(),
-                                    distribution,
-                                })),
+                                expr: ExprT::from(noisy),
                                 alias: alias.clone(),
                             }));
                             new_attributes.push(new_expr);
@@ -237,40 +1004,132 @@ impl RelTransform for DifferentialPrivacyPolicy {
                     expr: ExprT::from(Expr::Noisy(Noisy {
                         expr: ExprT::from(Expr::Function(Function {
                             name: FunctionName::Count,
-                            args: vec![one.clone()],
-                            distinct: false,
+                            args: vec![ExprT::from(Expr::Column(Column(entity_key.clone())))],
+                            distinct: true,
                         })),
                         distribution: Distribution::Laplace {
                             mean: 0.,
-                            variance: 1. / self.epsilon,
+                            variance: selection.scale(),
                         },
                     })),
                     alias: bucket_alias.to_string(),
                 })));
 
+                // entities only keep their rows in their first
+                // `max_partitions_contributed` groups, which the rows are
+                // ranked on next to the columns the aggregation reads
+                let (new_attributes, new_group_by, from) = if group_by.is_empty() {
+                    (new_attributes, group_by.clone(), from.clone())
+                } else {
+                    let rank_alias = "__partition_rank";
+                    let mut columns = Vec::new();
+                    for expr in new_attributes.iter().chain(group_by.iter()) {
+                        columns_of(expr, &mut columns);
+                    }
+                    let names: HashMap<_, _> = columns
+                        .iter()
+                        .enumerate()
+                        .map(|(i, key)| (key.clone(), format!("__column_{}", i)))
+                        .collect();
+                    let mut ranked_attributes: Vec<_> = columns
+                        .iter()
+                        .map(|key| {
+                            ExprT::from(Expr::As(As {
+                                expr: ExprT::from(Expr::Column(Column(key.clone()))),
+                                alias: names[key].clone(),
+                            }))
+                        })
+                        .collect();
+                    ranked_attributes.push(ExprT::from(Expr::As(As {
+                        expr: ExprT::from(Expr::PartitionRank(PartitionRank {
+                            entity: ExprT::from(Expr::Column(Column(entity_key))),
+                            partition: group_by.clone(),
+                        })),
+                        alias: rank_alias.to_string(),
+                    })));
+                    let bounded = RelT::from(GenericRel::Selection(Selection {
+                        from: RelT::from(GenericRel::Projection(Projection {
+                            attributes: ranked_attributes,
+                            from: from.clone(),
+                        })),
+                        where_: ExprT::from(Expr::BinaryOp(BinaryOp {
+                            op: BinaryOperator::LtEq,
+                            left: ExprT::from(Expr::Column(Column(ContextKey::with_name(
+                                rank_alias,
+                            )))),
+                            right: ExprT::from(Expr::Literal(Literal(LiteralValue::Long(
+                                self.policy.max_partitions_contributed as i64,
+                            )))),
+                        })),
+                    }));
+                    (
+                        new_attributes
+                            .iter()
+                            .map(|expr| rename_columns(expr, &names))
+                            .collect(),
+                        group_by
+                            .iter()
+                            .map(|expr| rename_columns(expr, &names))
+                            .collect(),
+                        bounded,
+                    )
+                };
+
                 let noised_root = RelT::from(GenericRel::Aggregation(Aggregation {
                     attributes: new_attributes,
-                    group_by: group_by.clone(),
-                    from: from.clone(),
+                    group_by: new_group_by,
+                    from,
                 }));
 
                 let where_bucket_count = ExprT::from(Expr::BinaryOp(BinaryOp {
                     op: BinaryOperator::Gt,
                     left: ExprT::from(Expr::Column(Column(bucket_key))),
-                    right: { ExprT::from(Expr::Literal(Literal(LiteralValue::Long(threshold)))) },
+                    right: ExprT::from(Expr::Literal(Literal(LiteralValue::Double(
+                        selection.threshold(),
+                    )))),
                 }));
 
-                let new_root = RelT::from(GenericRel::Projection(Projection {
-                    from: RelT::from(GenericRel::Selection(Selection {
-                        from: noised_root,
-                        where_: where_bucket_count,
-                    })),
-                    attributes: projection_attributes,
+                let mut selected = RelT::from(GenericRel::Selection(Selection {
+                    from: noised_root,
+                    where_: where_bucket_count,
                 }));
 
-                let ctx = access.context().await.unwrap();
-                let new_root = RebaseRel::<'_, TableMeta>::rebase(&ctx, &new_root).await; // repair it
-
+                let mut top_k_by = None;
+                if let Some(top_k) = top_k.as_ref() {
+                    let index = top_k_index.ok_or(Error::NoMatch)?;
+                    selected = RelT::from(GenericRel::Limit(Limit {
+                        number_rows: ExprT::from(Expr::Literal(Literal(LiteralValue::Long(
+                            top_k.k as i64,
+                        )))),
+                        from: RelT::from(GenericRel::OrderBy(OrderBy {
+                            order: vec![Order::Desc],
+                            by: vec![ExprT::from(Expr::Column(Column(ContextKey::with_name(
+                                score_alias,
+                            ))))],
+                            from: selected,
+                        })),
+                    }));
+                    top_k_by = Some(projection_attributes[index].clone());
+                }
+
+                let mut new_root = RelT::from(GenericRel::Projection(Projection {
+                    from: selected,
+                    attributes: projection_attributes,
+                }));
+
+                // the order of the selection is not kept through the
+                // projection, so the released aggregate orders the groups
+                if let Some(by) = top_k_by {
+                    new_root = RelT::from(GenericRel::OrderBy(OrderBy {
+                        order: vec![Order::Desc],
+                        by: vec![by],
+                        from: new_root,
+                    }));
+                }
+
+                let ctx = access.context().await.unwrap();
+                let new_root = RebaseRel::<'_, TableMeta>::rebase(&ctx, &new_root).await; // repair it
+
                 Ok(Costly {
                     root: new_root,
                     cost,
@@ -282,7 +1141,7 @@ impl RelTransform for DifferentialPrivacyPolicy {
 }
 
 #[async_trait]
-impl RelTransform for AggregationPolicy {
+impl<'a> RelTransform for WithEntity<'a, AggregationPolicy> {
     async fn transform_rel<A: Access>(
         &self,
         rel: &RelT,
@@ -294,8 +1153,8 @@ impl RelTransform for AggregationPolicy {
                 group_by,
                 from,
             }) => {
-                let entity_key = ContextKey::with_name(&self.entity);
-                let entity_alias_str = format!("policy_{}", entity_key.name());
+                let entity_key = entity_column(&self.entity, from).ok_or(Error::NoMatch)?;
+                let entity_alias_str = format!("policy_{}", self.entity.name);
                 let entity_alias = ContextKey::with_name(&entity_alias_str);
                 let ctx = access.context().await.unwrap();
                 let rewritten: RelT = rel
@@ -304,7 +1163,7 @@ impl RelTransform for AggregationPolicy {
                         GenericRel::Table(Table(context_key)) => {
                             let table_meta = ctx.get(&context_key).unwrap();
                             let columns = table_meta.to_context();
-                            if columns.get_column(&entity_key).is_ok() {
+                            if self.entity.column_in(&context_key, &columns).is_some() {
                                 Ok(RelT {
                                     root: GenericRel::Table(Table(context_key)),
                                     board: Ok(table_meta.clone()),
@@ -366,7 +1225,7 @@ impl RelTransform for AggregationPolicy {
                         left: ExprT::from(Expr::Column(Column(entity_alias))),
                         op: BinaryOperator::Gt,
                         right: ExprT::from(Expr::Literal(Literal(LiteralValue::Long(
-                            self.minimum_bucket_size as i64,
+                            self.policy.minimum_bucket_size as i64,
                         )))),
                     }));
                     let num_cols = board.columns.len();
@@ -403,12 +1262,24 @@ impl RelTransform for Policy {
         rel: &RelT,
         access: &A,
     ) -> Result<Costly<RelT>, Error> {
-        match &self.0 {
+        match &self.policy {
             policy::Policy::DifferentialPrivacy(differential_privacy) => {
-                differential_privacy.transform_rel(rel, access).await
+                WithEntity {
+                    policy: differential_privacy,
+                    entity: self.entity_or(&differential_privacy.entity),
+                    domains: &self.domains,
+                }
+                .transform_rel(rel, access)
+                .await
             }
             policy::Policy::Aggregation(aggregation) => {
-                aggregation.transform_rel(rel, access).await
+                WithEntity {
+                    policy: aggregation,
+                    entity: self.entity_or(&aggregation.entity),
+                    domains: &self.domains,
+                }
+                .transform_rel(rel, access)
+                .await
             }
             _ => Err(Error::NoMatch),
         }
@@ -434,23 +1305,381 @@ pub trait RelTransform {
 #[derive(Clone, Debug)]
 pub struct PolicyBinding {
     pub policies: Vec<Policy>,
+    /// How the dataset refers to the policies (e.g. `policy.hash.names_are_hashed`)
+    pub references: Vec<String>,
     pub priority: u64,
     pub budget: Option<PolicyBudget>,
 }
 
 impl PolicyBinding {
-    fn is_in_budget(&self, proposed: f64) -> bool {
+    fn is_in_budget(&self, proposed: &Cost) -> bool {
         self.budget
             .as_ref()
-            .map(|PolicyBudget { maximum, used, .. }| used + proposed <= *maximum)
+            .map(|budget| proposed.fits(budget))
             .unwrap_or(true)
     }
 }
 
+/// The column identifying `entity` in the table `rel_t`, if the table belongs
+/// to `dataset` and the entity is in it
+fn entity_column_in(
+    dataset: &str,
+    entity: &EntityFields,
+    table: &ContextKey,
+    rel_t: &RelT,
+) -> Result<Option<ExprT>, Error> {
+    if table.prefix().next() != Some(dataset) {
+        return Ok(None);
+    }
+    let board = rel_t
+        .board
+        .as_ref()
+        .map_err(|e| Error::Validate(e.clone()))?;
+    let column = match entity.column_in(table, &board.columns) {
+        Some(column) => column,
+        None => return Ok(None),
+    };
+    let validator = ExprValidator::new(&board.columns);
+    let column = validator.validate_expr(&ast::Expr::Identifier(column.name().to_string()))?;
+    Ok(Some(column))
+}
+
+/// Row filters are not the audience's doing, so they do not restrict what the
+/// audience may see
+fn vouch_for(mut where_: ExprT, audience: &BlockType) -> Result<ExprT, Error> {
+    where_
+        .board
+        .as_mut()
+        .map(|board| {
+            board.audience.insert(audience.clone());
+        })
+        .map_err(|e| Error::Validate(e.clone()))?;
+    Ok(where_)
+}
+
+/// The identifiers of an entity to leave out of every query, resolved from an
+/// exclusion list
+#[derive(Clone, Debug)]
+pub struct Exclusion {
+    /// The name and version of the exclusion list
+    pub list: (String, u64),
+    /// The dataset the entity belongs to
+    pub dataset: String,
+    pub entity: EntityFields,
+    pub identifiers: Vec<String>,
+    /// A backend table holding more identifiers, and the column holding them
+    pub table: Option<(String, String)>,
+}
+
+impl Exclusion {
+    /// The filter leaving the excluded entities out of the table `rel_t`, if
+    /// the entity is in it
+    fn filter_for(
+        &self,
+        table: &ContextKey,
+        rel_t: &RelT,
+        audience: &BlockType,
+    ) -> Result<Option<ExprT>, Error> {
+        let column = match entity_column_in(&self.dataset, &self.entity, table, rel_t)? {
+            Some(column) => column,
+            None => return Ok(None),
+        };
+        let ty = column.board.as_ref().map_err(|e| e.clone())?.ty;
+
+        let mut filters = Vec::new();
+        if !self.identifiers.is_empty() {
+            let list = self
+                .identifiers
+                .iter()
+                .map(|identifier| {
+                    let value = match ty {
+                        DataType::Integer => {
+                            LiteralValue::Long(identifier.parse().map_err(|_| {
+                                ValidateError::Expected(format!(
+                                    "excluded identifiers of `{}` to be integers",
+                                    self.entity.name
+                                ))
+                            })?)
+                        }
+                        _ => LiteralValue::StringLiteral(identifier.clone()),
+                    };
+                    Ok(ExprT::from(Expr::Literal(Literal(value))))
+                })
+                .collect::<Result<_, Error>>()?;
+            filters.push(ExprT::from(Expr::InList(InList {
+                expr: column.clone(),
+                list,
+                negated: true,
+            })));
+        }
+        if let Some((source, source_column)) = self.table.as_ref() {
+            filters.push(ExprT::from(Expr::InTable(InTable {
+                expr: column.clone(),
+                table: source.clone(),
+                column: source_column.clone(),
+                filter: None,
+                negated: true,
+            })));
+        }
+
+        let where_ = match filters.pop() {
+            Some(filter) => filters.into_iter().fold(filter, |right, left| {
+                ExprT::from(Expr::BinaryOp(BinaryOp {
+                    left,
+                    op: BinaryOperator::And,
+                    right,
+                }))
+            }),
+            None => return Ok(None),
+        };
+        vouch_for(where_, audience).map(Some)
+    }
+}
+
+/// The consent table restricting the queries on a dataset to the entities
+/// who consented to their purpose, resolved from a consent policy
+#[derive(Clone, Debug)]
+pub struct Consent {
+    /// The dataset the policy belongs to
+    pub dataset: String,
+    pub entity: EntityFields,
+    /// The consent table, as the backend names it
+    pub table: String,
+    /// The column of the consent table identifying the entity
+    pub key: String,
+    /// The column of the consent table holding the purpose
+    pub purpose: String,
+}
+
+impl Consent {
+    /// The filter keeping the entities who consented to `purpose` in the table
+    /// `rel_t`, if the entity is in it
+    fn filter_for(
+        &self,
+        table: &ContextKey,
+        rel_t: &RelT,
+        audience: &BlockType,
+        purpose: Option<&str>,
+    ) -> Result<Option<ExprT>, Error> {
+        if table.prefix().next() != Some(self.dataset.as_str()) {
+            return Ok(None);
+        }
+        let purpose = purpose.ok_or_else(|| {
+            ValidateError::Insufficient(format!(
+                "queries on `{}` need to declare their purpose",
+                self.dataset
+            ))
+        })?;
+        let column = match entity_column_in(&self.dataset, &self.entity, table, rel_t)? {
+            Some(column) => column,
+            None => return Ok(None),
+        };
+        let where_ = ExprT::from(Expr::InTable(InTable {
+            expr: column,
+            table: self.table.clone(),
+            column: self.key.clone(),
+            filter: Some((self.purpose.clone(), purpose.to_string())),
+            negated: false,
+        }));
+        vouch_for(where_, audience).map(Some)
+    }
+}
+
+lazy_static! {
+    static ref PLACEHOLDER_RE: regex::Regex =
+        regex::Regex::new("\\{\\s*(?P<name>\\w+)\\s*:\\s*(?P<ty>integer|float|string)\\s*\\}")
+            .unwrap();
+}
+
+/// The pre-approved queries a dataset restricts its queries to, resolved from
+/// a template policy
+#[derive(Clone, Debug)]
+pub struct Template {
+    /// The dataset the policy belongs to
+    pub dataset: String,
+    /// The SQL of each approved query authorized for the audience
+    pub queries: Vec<String>,
+}
+
+impl Template {
+    /// Whether `rel_t` matches one of the queries of the template, once its
+    /// literals are unified with the placeholders
+    fn authorizes(&self, rel_t: &RelT, ctx: &Context<TableMeta>) -> Result<bool, Error> {
+        let query = serde_json::to_value(rel_t).map_err(|e| {
+            ValidateError::Internal(format!("could not serialize the query: {}", e))
+        })?;
+        for sql in self.queries.iter() {
+            let template = Self::validate_template(sql, ctx)?;
+            if unify(&template, &query, &mut HashMap::new()) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Validate the query `sql`, with its placeholders standing in as
+    /// placeholder literals
+    fn validate_template(sql: &str, ctx: &Context<TableMeta>) -> Result<serde_json::Value, Error> {
+        let placeholders = PLACEHOLDER_RE.replace_all(sql, |captures: &regex::Captures| {
+            format!("N'{}:{}'", &captures["name"], captures["ty"].to_uppercase())
+        });
+        let template = Validator::new(ctx)
+            .with_placeholders()
+            .validate_str(&placeholders)?;
+        serde_json::to_value(template).map_err(|e| {
+            ValidateError::Internal(format!("could not serialize a template: {}", e)).into()
+        })
+    }
+}
+
+/// Structurally match the serialized `query` against the serialized
+/// `template`, binding the literals of `query` to the placeholders they stand
+/// at. Placeholders sharing a name need to be bound to the same literal.
+fn unify(
+    template: &serde_json::Value,
+    query: &serde_json::Value,
+    bound: &mut HashMap<String, serde_json::Value>,
+) -> bool {
+    use serde_json::Value;
+    if let Some(placeholder) = template.get("Placeholder") {
+        let (name, ty) = match serde_json::from_value::<(String, DataType)>(placeholder.clone()) {
+            Ok(placeholder) => placeholder,
+            Err(_) => return false,
+        };
+        // placeholders are filled in by literals of their type
+        let literal = match ty {
+            DataType::Integer => "Long",
+            DataType::Float => "Double",
+            _ => "StringLiteral",
+        };
+        let filled = match query {
+            Value::Object(query) => query.len() == 1 && query.contains_key(literal),
+            _ => false,
+        };
+        return filled && bound.entry(name).or_insert_with(|| query.clone()) == query;
+    }
+    match (template, query) {
+        (Value::Object(template), Value::Object(query)) => {
+            template.len() == query.len()
+                && template.iter().all(|(key, template)| {
+                    // boards are derived from the trees they are on
+                    key == "board"
+                        || query
+                            .get(key)
+                            .map(|query| unify(template, query, bound))
+                            .unwrap_or(false)
+                })
+        }
+        (Value::Array(template), Value::Array(query)) => {
+            template.len() == query.len()
+                && template
+                    .iter()
+                    .zip(query.iter())
+                    .all(|(template, query)| unify(template, query, bound))
+        }
+        _ => template == query,
+    }
+}
+
+/// The tables below `rel_t`
+pub(crate) fn tables_below<'b>(rel_t: &'b RelT, tables: &mut Vec<&'b ContextKey>) {
+    if let Rel::Table(Table(context_key)) = &rel_t.root {
+        tables.push(context_key);
+    }
+    rel_t.root.map(&mut |child| tables_below(child, tables));
+}
+
+/// An output column redacted text ends up in, along with the detectors it
+/// was redacted with
+pub(crate) type Redaction = (String, Vec<Detector>);
+
+/// The output columns of `rel_t` redacted text ends up in. Redactions are
+/// traced from the final projection through the columns of the relations
+/// below, so that they are found whatever the columns are aliased to or
+/// wrapped in. Fails if the output columns cannot be told.
+pub(crate) fn redacted_columns(rel_t: &RelT) -> Result<Vec<Redaction>, ValidateError> {
+    fn detectors_in(expr_t: &ExprT, below: &[Redaction], detectors: &mut Vec<Detector>) {
+        match expr_t.as_ref() {
+            Expr::Redact(Redact {
+                detectors: redacted,
+                ..
+            }) => detectors.extend(redacted),
+            Expr::Column(Column(key)) => {
+                for (column, redacted) in below.iter() {
+                    if column == key.name() {
+                        detectors.extend(redacted.iter().cloned());
+                    }
+                }
+            }
+            _ => {}
+        }
+        expr_t
+            .root
+            .map(&mut |child| detectors_in(child, below, detectors));
+    }
+
+    match &rel_t.root {
+        Rel::Table(..) => Ok(Vec::new()),
+        Rel::Projection(Projection { attributes, from })
+        | Rel::Aggregation(Aggregation {
+            attributes, from, ..
+        }) => {
+            let below = redacted_columns(from)?;
+            let mut redactions = Vec::new();
+            for attribute in attributes.iter() {
+                let mut detectors = Vec::new();
+                detectors_in(attribute, &below, &mut detectors);
+                if detectors.is_empty() {
+                    continue;
+                }
+                let column = match attribute.as_ref() {
+                    Expr::As(As { alias, .. }) => alias,
+                    Expr::Column(Column(key)) => key.name().to_string(),
+                    _ => {
+                        return Err(ValidateError::Expected(
+                            "redacted columns to be named".to_string(),
+                        ))
+                    }
+                };
+                detectors.sort_by(|left, right| left.name.cmp(&right.name));
+                detectors.dedup_by(|left, right| left.name == right.name);
+                redactions.push((column, detectors));
+            }
+            Ok(redactions)
+        }
+        Rel::Selection(Selection { from, .. })
+        | Rel::Offset(Offset { from, .. })
+        | Rel::Limit(Limit { from, .. })
+        | Rel::OrderBy(OrderBy { from, .. })
+        | Rel::Distinct(Distinct { from })
+        | Rel::WithAlias(WithAlias { from, .. }) => redacted_columns(from),
+        Rel::Join(Join { left, right, .. }) => {
+            let mut redactions = redacted_columns(left)?;
+            redactions.extend(redacted_columns(right)?);
+            Ok(redactions)
+        }
+        // the columns of the right hand side are named by those of the left
+        // hand side, which they are not traced to
+        Rel::Set(Set { left, right, .. }) => {
+            if redacted_columns(right)?.is_empty() {
+                redacted_columns(left)
+            } else {
+                Err(ValidateError::Expected(
+                    "redacted columns to be on the left of set operations".to_string(),
+                ))
+            }
+        }
+    }
+}
+
 pub struct RelTransformer<'a, A> {
     bindings: &'a Context<PolicyBinding>,
     audience: &'a BlockType,
     access: &'a A,
+    exclusions: &'a [Exclusion],
+    consents: &'a [Consent],
+    purpose: Option<&'a str>,
+    templates: &'a [Template],
 }
 
 impl<'a, A> RelTransformer<'a, A>
@@ -470,16 +1699,105 @@ where
             bindings,
             audience,
             access,
+            exclusions: &[],
+            consents: &[],
+            purpose: None,
+            templates: &[],
+        }
+    }
+
+    pub fn with_exclusions(mut self, exclusions: &'a [Exclusion]) -> Self {
+        self.exclusions = exclusions;
+        self
+    }
+
+    pub fn with_consents(mut self, consents: &'a [Consent], purpose: Option<&'a str>) -> Self {
+        self.consents = consents;
+        self.purpose = purpose;
+        self
+    }
+
+    pub fn with_templates(mut self, templates: &'a [Template]) -> Self {
+        self.templates = templates;
+        self
+    }
+
+    /// Whether `rel_t` is authorized for the audience by the templates of the
+    /// datasets it queries. Queries on datasets restricted to templates which
+    /// match none of them are refused.
+    pub fn authorize_templates(
+        &self,
+        rel_t: &RelT,
+        ctx: &Context<TableMeta>,
+    ) -> Result<bool, Error> {
+        let mut tables = Vec::new();
+        tables_below(rel_t, &mut tables);
+        let mut restricted = self
+            .templates
+            .iter()
+            .filter(|template| {
+                tables
+                    .iter()
+                    .any(|table| table.prefix().next() == Some(template.dataset.as_str()))
+            })
+            .peekable();
+        let dataset = match restricted.peek() {
+            Some(template) => template.dataset.clone(),
+            None => return Ok(false),
+        };
+        for template in restricted {
+            if template.authorizes(rel_t, ctx)? {
+                return Ok(true);
+            }
+        }
+        Err(ValidateError::Insufficient(format!(
+            "queries on `{}` need to match one of its templates",
+            dataset
+        ))
+        .into())
+    }
+
+    /// Filter the rows of excluded entities, and of entities who did not
+    /// consent to the purpose of the query, out of the tables below `rel_t`.
+    /// This happens before any policy applies, so that none of them ever sees
+    /// the rows of those entities.
+    pub fn filter_rows(&self, rel_t: &RelT) -> Result<RelT, Error> {
+        if let Rel::Table(Table(context_key)) = &rel_t.root {
+            let mut filters = Vec::new();
+            for exclusion in self.exclusions.iter() {
+                filters.push(exclusion.filter_for(context_key, rel_t, self.audience)?);
+            }
+            for consent in self.consents.iter() {
+                filters.push(consent.filter_for(
+                    context_key,
+                    rel_t,
+                    self.audience,
+                    self.purpose,
+                )?);
+            }
+            let mut root = rel_t.clone();
+            for where_ in filters.into_iter().flatten() {
+                root = RelT::from(Rel::Selection(Selection { from: root, where_ }));
+            }
+            return Ok(root);
         }
+        Ok(RelT::from(
+            rel_t
+                .root
+                .map(&mut |child| self.filter_rows(child))
+                .into_result()?,
+        ))
     }
 
-    /// Filter the policy bindings that apply to the `context_key`
+    /// Filter the policy bindings that apply to the `context_key`. This is
+    /// either a table or, for relations over several tables, the dataset they
+    /// have in common.
     fn filter_bindings<'b>(&'b self, context_key: &'b ContextKey) -> Context<&'a PolicyBinding> {
         debug!("sifting policies for {}", context_key);
         self.bindings
             .iter()
             .filter_map(move |(key, binding)| {
-                if key.prefix_matches(context_key) {
+                if key.prefix_matches(context_key) || key.prefix().eq(context_key.iter()) {
                     Some((key.clone(), binding))
                 } else {
                     None
@@ -488,34 +1806,156 @@ where
             .collect()
     }
 
+    /// Transform the filters and join constraints of `rel_t` with the
+    /// policies usable there. Those that cannot be transformed are kept as
+    /// they are, so that the audience cannot use them.
+    fn transform_predicates(&self, rel_t: &RelT) -> Result<Transformed<RelT>, Error> {
+        if rel_t.is_leaf() {
+            return Ok(Transformed::default(rel_t.clone()));
+        }
+        let mut cost = HashMap::new();
+        let mut priority = 0;
+        let root = RelT::from(
+            rel_t
+                .root
+                .map(&mut |child| {
+                    self.transform_predicates(child).map(|transformed| {
+                        transformed.add_to(&mut cost);
+                        priority = max(priority, transformed.priority);
+                        transformed.root
+                    })
+                })
+                .into_result()?,
+        );
+        let transformed = self.transform_predicates_at(root)?;
+        transformed.add_to(&mut cost);
+        Ok(Transformed {
+            root: transformed.root,
+            cost,
+            priority: max(priority, transformed.priority),
+        })
+    }
+
+    /// Transform the filter of `rel_t` if it is a selection, or its
+    /// constraint if it is a join
+    fn transform_predicates_at(&self, rel_t: RelT) -> Result<Transformed<RelT>, Error> {
+        match &rel_t.root {
+            Rel::Selection(..) | Rel::Join(..) => {}
+            _ => return Ok(Transformed::default(rel_t)),
+        }
+        let provenance = match rel_t
+            .board
+            .as_ref()
+            .map_err(|e| Error::Validate(e.clone()))?
+            .provenance
+            .clone()
+        {
+            Some(provenance) => provenance,
+            None => return Ok(Transformed::default(rel_t)),
+        };
+        let bindings = self.filter_bindings(&provenance);
+        let transform = |expr_t: &ExprT, usage: Usage, sides: Option<(&TableMeta, &TableMeta)>| {
+            // e.g. the filters of exclusion lists
+            let usable = expr_t
+                .board
+                .as_ref()
+                .map(|board| board.audience.contains(self.audience))
+                .unwrap_or(false);
+            if usable {
+                return Ok(Transformed::default(expr_t.clone()));
+            }
+            let mut expr_transformer = ExprTransformer::new(&bindings, &self.audience, usage);
+            if let Some((left, right)) = sides {
+                expr_transformer = expr_transformer.joining(left, right);
+            }
+            match expr_transformer.transform_expr(expr_t) {
+                Err(Error::NoMatch) => Ok(Transformed::default(expr_t.clone())),
+                res => res,
+            }
+        };
+        let (root, transformed) = match rel_t.into_inner() {
+            Rel::Selection(Selection { from, where_ }) => {
+                let transformed = transform(&where_, Usage::Predicate, None)?;
+                let where_ = transformed.root.clone();
+                (
+                    Rel::Selection(Selection { from, where_ }),
+                    Some(transformed),
+                )
+            }
+            Rel::Join(Join {
+                left,
+                right,
+                operator,
+            }) => {
+                let sides = (
+                    left.board
+                        .as_ref()
+                        .map_err(|e| Error::Validate(e.clone()))?,
+                    right
+                        .board
+                        .as_ref()
+                        .map_err(|e| Error::Validate(e.clone()))?,
+                );
+                let operator = operator
+                    .map_expressions(&|on| transform(on, Usage::Join, Some(sides)))
+                    .into_result_expressions()?;
+                let transformed = match operator.constraint() {
+                    Some(JoinConstraint::On(on)) => Some(on.clone()),
+                    _ => None,
+                };
+                let operator = operator.map_expressions(&|on| on.root.clone());
+                (
+                    Rel::Join(Join {
+                        left,
+                        right,
+                        operator,
+                    }),
+                    transformed,
+                )
+            }
+            _ => unreachable!(),
+        };
+        let root = RelT::from(root);
+        Ok(match transformed {
+            Some(transformed) => Transformed {
+                root,
+                cost: transformed.cost,
+                priority: transformed.priority,
+            },
+            None => Transformed::default(root),
+        })
+    }
+
     pub fn transform_rel<'b>(
         &'b self,
         rel_t: &'b RelT,
     ) -> Pin<Box<dyn Future<Output = Result<Transformed<RelT>, Error>> + Send + 'b>> {
         async move {
             let unraveled = rel_t.root.map(&mut |child| child.as_ref());
+            let leaf_table = match &unraveled {
+                Rel::Projection(Projection { from, .. }) => filtered_table(*from),
+                _ => None,
+            };
 
-            let proposed = match unraveled {
-                Rel::Projection(Projection {
-                    mut attributes,
-                    from:
-                        RelT {
-                            root: Rel::Table(Table(context_key)),
-                            board,
-                        },
-                }) => {
+            let proposed = match (unraveled, leaf_table) {
+                (
+                    Rel::Projection(Projection {
+                        mut attributes,
+                        from,
+                    }),
+                    Some(context_key),
+                ) => {
                     debug!("potential expr leaf policy condition met");
-                    let from = RelT {
-                        root: Rel::Table(Table(context_key.clone())),
-                        board: board.clone(),
-                    };
+                    let filtered = self.transform_predicates(from)?;
+                    let mut cost = filtered.cost.clone();
+                    let mut priority = filtered.priority;
+                    let from = filtered.root;
 
                     let bindings = self.filter_bindings(context_key);
                     debug!("bindings filtered to {:?}", bindings);
 
-                    let mut cost = HashMap::new();
-                    let mut priority = 0;
-                    let expr_transformer = ExprTransformer::new(&bindings, &self.audience);
+                    let expr_transformer =
+                        ExprTransformer::new(&bindings, &self.audience, Usage::Projection);
                     for expr_t in attributes.iter_mut() {
                         match expr_transformer.transform_expr(expr_t) {
                             Ok(transformed) => {
@@ -558,20 +1998,39 @@ where
                         .provenance
                         .as_ref();
                     if let Some(provenance) = provenance {
-                        let bindings = self.filter_bindings(provenance);
+                        let filtered = self.transform_predicates(rel_t)?;
+                        // relation policies only vouch for the filters and
+                        // join constraints below them if the audience may
+                        // use them
+                        let predicates_usable = filtered
+                            .root
+                            .board
+                            .as_ref()
+                            .map_err(|e| Error::Validate(e.clone()))?
+                            .predicates
+                            .as_ref()
+                            .map(|predicates| predicates.contains(&self.audience))
+                            .unwrap_or(true);
+                        let bindings = if predicates_usable {
+                            self.filter_bindings(provenance)
+                        } else {
+                            Context::new()
+                        };
                         let mut candidates = Vec::new();
                         for (key, binding) in bindings.iter() {
                             for policy in binding.policies.iter() {
-                                match policy.transform_rel(rel_t, self.access).await {
+                                match policy.transform_rel(&filtered.root, self.access).await {
                                     Ok(Costly { mut root, cost }) => {
                                         root.board
                                             .as_mut()
                                             .map(|board| {
-                                                board.audience.insert(self.audience.clone())
+                                                board.audience.insert(self.audience.clone());
+                                                board.predicates = None;
                                             })
                                             .map_err(|e| Error::Validate(e.clone()))?;
-                                        let transformed =
+                                        let mut transformed =
                                             Transformed::new(root, key, cost, binding.priority);
+                                        filtered.add_to(&mut transformed.cost);
                                         candidates.push(transformed);
                                     }
                                     Err(Error::NoMatch) => {}
@@ -612,11 +2071,14 @@ where
                         .await
                         .into_result()?,
                 );
+                let filtered = self.transform_predicates_at(root)?;
                 let state_ = state.lock().unwrap();
+                let mut cost = state_.0.clone();
+                filtered.add_to(&mut cost);
                 let transformed = Transformed {
-                    root,
-                    cost: state_.0.clone(),
-                    priority: state_.1,
+                    root: filtered.root,
+                    cost,
+                    priority: max(state_.1, filtered.priority),
                 };
                 debug!("from level below, got best relation tree {:?}", transformed);
                 Ok(transformed)
@@ -626,21 +2088,56 @@ where
     }
 }
 
+/// The table below `rel_t` if it is a table filtered by any number of
+/// selections
+fn filtered_table(rel_t: &RelT) -> Option<&ContextKey> {
+    match &rel_t.root {
+        Rel::Table(Table(context_key)) => Some(context_key),
+        Rel::Selection(Selection { from, .. }) => filtered_table(from),
+        _ => None,
+    }
+}
+
 pub struct ExprTransformer<'a> {
     bindings: &'a Context<&'a PolicyBinding>,
     audience: &'a BlockType,
+    usage: Usage,
+    /// The sides of the join, if the expression is a join constraint
+    sides: Option<(&'a TableMeta, &'a TableMeta)>,
 }
 
 impl<'a> ExprTransformer<'a> {
-    fn new(bindings: &'a Context<&'a PolicyBinding>, audience: &'a BlockType) -> Self {
-        Self { bindings, audience }
+    fn new(
+        bindings: &'a Context<&'a PolicyBinding>,
+        audience: &'a BlockType,
+        usage: Usage,
+    ) -> Self {
+        Self {
+            bindings,
+            audience,
+            usage,
+            sides: None,
+        }
+    }
+
+    fn joining(mut self, left: &'a TableMeta, right: &'a TableMeta) -> Self {
+        self.sides = Some((left, right));
+        self
     }
+
     fn transform_expr(&self, expr_t: &ExprT) -> Result<Transformed<ExprT>, Error> {
         let mut proposed = Vec::new();
         for (key, binding) in self.bindings.iter() {
             let priority = binding.priority;
             for policy in binding.policies.iter() {
-                match policy.transform_expr(expr_t) {
+                if !policy.usable_in(self.usage) {
+                    continue;
+                }
+                let transformed = match self.sides {
+                    Some((left, right)) => policy.transform_join_expr(expr_t, left, right),
+                    None => policy.transform_expr(expr_t),
+                };
+                match transformed {
                     Ok(Costly { mut root, cost }) => {
                         root.board
                             .as_mut()
@@ -663,7 +2160,21 @@ impl<'a> ExprTransformer<'a> {
         } else {
             // no match so far, let's try deeper
             if expr_t.is_leaf() {
-                return Err(Error::NoMatch);
+                return match expr_t.as_ref() {
+                    // the literals of a filter or join constraint do not
+                    // disclose anything by themselves
+                    Expr::Literal(..) if self.usage != Usage::Projection => {
+                        let mut root = expr_t.clone();
+                        root.board
+                            .as_mut()
+                            .map(|board| {
+                                board.audience.insert(self.audience.clone());
+                            })
+                            .map_err(|e| Error::Validate(e.clone()))?;
+                        Ok(Transformed::default(root))
+                    }
+                    _ => Err(Error::NoMatch),
+                };
             }
             let mut cost = HashMap::new();
             let mut priority = 0;
@@ -691,7 +2202,7 @@ impl<'a> ExprTransformer<'a> {
 #[derive(Debug, Clone)]
 pub struct Transformed<T> {
     pub root: T,
-    pub cost: HashMap<ContextKey, f64>,
+    pub cost: HashMap<ContextKey, Cost>,
     pub priority: u64,
 }
 
@@ -703,7 +2214,7 @@ impl<T> Transformed<T> {
             priority: 0,
         }
     }
-    fn new(root: T, binding_key: &ContextKey, cost: f64, priority: u64) -> Self {
+    fn new(root: T, binding_key: &ContextKey, cost: Cost, priority: u64) -> Self {
         Self {
             root,
             cost: {
@@ -732,16 +2243,21 @@ impl<T> Transformed<T> {
             .collect::<Vec<_>>();
         let best = candidates
             .into_iter()
-            .min_by(|l, r| l.total_cost().partial_cmp(&r.total_cost()).unwrap())
+            .min_by(|l, r| {
+                let (l, r) = (l.total_cost(), r.total_cost());
+                (l.epsilon, l.delta)
+                    .partial_cmp(&(r.epsilon, r.delta))
+                    .unwrap()
+            })
             .unwrap();
         Some(best)
     }
-    fn total_cost(&self) -> f64 {
-        self.cost.values().sum()
+    fn total_cost(&self) -> Cost {
+        self.cost.values().cloned().sum()
     }
-    fn add_to(&self, costs: &mut HashMap<ContextKey, f64>) {
+    fn add_to(&self, costs: &mut HashMap<ContextKey, Cost>) {
         for (key, cost) in self.cost.iter() {
-            *costs.entry(key.clone()).or_default() += cost;
+            *costs.entry(key.clone()).or_default() += *cost;
         }
     }
 }
@@ -750,35 +2266,53 @@ impl<T> Transformed<T> {
 pub mod tests {
     use super::*;
 
-    use crate::node::state::tests::read_manifest;
-    use crate::node::tests::mk_node;
+    use crate::node::tests::{just_get_me_a_context, mk_manifest_node, mk_node_with};
+    use crate::node::Node;
     use crate::opt::validate::Validator;
     use tokio::runtime::Runtime;
 
     use parallax_api::block_type;
 
     fn test_transform_for(query: &str) -> Transformed<RelT> {
-        let random_scope = uuid::Uuid::new_v4().to_simple().to_string();
-        let access = Arc::new(mk_node(&random_scope));
-        for resource in read_manifest().into_iter() {
-            access.create_resource(resource).unwrap();
-        }
+        test_transform_with_purpose(query, Some("research")).unwrap()
+    }
+
+    fn test_transform_with_purpose(
+        query: &str,
+        purpose: Option<&str>,
+    ) -> Result<Transformed<RelT>, ValidateError> {
+        test_transform_on(&mk_manifest_node(), query, purpose)
+    }
+
+    /// Validate and transform `query` as `wheel` on the resources of `access`
+    fn test_transform_on(
+        access: &Arc<Node>,
+        query: &str,
+        purpose: Option<&str>,
+    ) -> Result<Transformed<RelT>, ValidateError> {
         Runtime::new().unwrap().block_on(async {
             let ctx = access.context().await.unwrap();
             let validator = Validator::new(&ctx);
-            let policies = access.policies_for_group("wheel").unwrap();
+            let policies = access.policies_for_group("wheel", purpose).unwrap();
             let rel_t = validator.validate_str(query).unwrap();
             let audience = block_type!("resource"."group"."wheel");
-            let transformer = RelTransformer::new(&policies, &audience, &access);
-            let rel_t = transformer
+            let exclusions = access.exclusions().unwrap();
+            let consents = access.consents().unwrap();
+            let transformer = RelTransformer::new(&policies, &audience, &access)
+                .with_exclusions(&exclusions)
+                .with_consents(&consents, purpose);
+            let rel_t = match transformer.filter_rows(&rel_t) {
+                Ok(rel_t) => rel_t,
+                Err(super::Error::Validate(err)) => return Err(err),
+                Err(super::Error::NoMatch) => unreachable!(),
+            };
+            transformer
                 .transform_rel(&rel_t)
                 .await
                 .or_else(|error| match error {
                     super::Error::NoMatch => Ok(Transformed::default(rel_t)),
                     super::Error::Validate(err) => Err(err),
                 })
-                .unwrap();
-            rel_t
         })
     }
 
@@ -862,7 +2396,33 @@ pub mod tests {
                     .map_owned(&mut |child| child.as_ref())
                 {
                     Expr::As(As { expr, .. }) => match expr {
-                        Expr::Hash(..) => {}
+                        Expr::Hash(Hash {
+                            algo,
+                            key,
+                            encoding,
+                            truncate,
+                            watermark,
+                            ..
+                        }) => {
+                            assert_eq!(*algo, HashAlgorithm::SHA256);
+                            assert_eq!(*encoding, HashEncoding::Hex);
+                            assert_eq!(*truncate, Some(16));
+                            // the dataset is watermarked, with a keyed mark
+                            let watermark = watermark.as_ref().unwrap();
+                            assert_eq!(watermark.width, 2);
+                            assert!(watermark
+                                .keyset
+                                .as_ref()
+                                .map_or(false, |keyset| keyset.kms_key.ends_with("watermarks")));
+                            match key {
+                                // pseudonyms are bound to the audience
+                                Keying::Keyset { keyset, context } => {
+                                    assert!(keyset.kms_key.starts_with("gcp-kms://"));
+                                    assert_eq!(context, "wheel");
+                                }
+                                _ => panic!("`care_site_name` was not keyed"),
+                            }
+                        }
                         _ => panic!("`care_site_name` was not hashed"),
                     },
                     _ => panic!("`care_site_name` was not hashed"),
@@ -872,6 +2432,288 @@ pub mod tests {
         }
     }
 
+    #[test]
+    fn transform_mask() {
+        let rel_t = test_transform_for(
+            "\
+            SELECT zip FROM patient_data.location
+            ",
+        )
+        .into_inner();
+
+        let table_meta = rel_t.board.unwrap();
+        assert!(table_meta
+            .audience
+            .contains(&block_type!("resource"."group"."wheel")));
+
+        match rel_t.root {
+            Rel::Projection(Projection { attributes, .. }) => {
+                match attributes[0]
+                    .as_ref()
+                    .map_owned(&mut |child| child.as_ref())
+                {
+                    Expr::As(As { expr, alias }) => {
+                        assert_eq!(alias, "zip".to_string());
+                        match expr {
+                            Expr::Mask(Mask {
+                                masking: Masking::KeepFirst { count: 3, .. },
+                                ..
+                            }) => {}
+                            _ => panic!("`zip` was not masked"),
+                        }
+                    }
+                    _ => panic!("`zip` was not masked"),
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn re2_patterns() {
+        assert!(validate_re2("[0-9]{3}-(?i:[a-z]+)$").is_ok());
+        assert!(validate_re2("\\p{Greek}+").is_ok());
+        for name in Detector::BUILTINS.iter() {
+            assert!(validate_re2(&Detector::builtin(name).unwrap().pattern).is_ok());
+        }
+        assert!(validate_re2(Detector::MARKER_LOOKALIKE).is_ok());
+
+        for pattern in &[
+            "[0-9",
+            "\\d{3}",
+            "\\bword",
+            "[[a-z]&&[^aeiou]]",
+            "[a-z--[aeiou]]",
+            "(?x)a b",
+            "a{1001}",
+        ] {
+            assert!(
+                validate_re2(pattern).is_err(),
+                "`{}` should not be valid",
+                pattern
+            );
+        }
+    }
+
+    #[test]
+    fn custom_detectors_are_re2() {
+        let redact = |pattern: &str| RedactPolicy {
+            custom_detectors: vec![RegexDetector {
+                name: "mrn".to_string(),
+                pattern: pattern.to_string(),
+            }],
+            ..Default::default()
+        };
+        assert!(detectors_for(&redact("MRN[0-9]{7}")).is_ok());
+        // Perl classes are Unicode-aware here but not in RE2
+        assert!(detectors_for(&redact("MRN\\d{7}")).is_err());
+    }
+
+    #[test]
+    fn transform_redact() {
+        let rel_t = test_transform_for(
+            "\
+            SELECT place_of_service_source_value FROM patient_data.care_site
+            ",
+        )
+        .into_inner();
+
+        match rel_t.root {
+            Rel::Projection(Projection { attributes, .. }) => {
+                match attributes[0]
+                    .as_ref()
+                    .map_owned(&mut |child| child.as_ref())
+                {
+                    Expr::As(As {
+                        expr: Expr::Redact(Redact { detectors, .. }),
+                        ..
+                    }) => {
+                        // built-in detectors come first, in their own order
+                        let names: Vec<_> = detectors.iter().map(|d| d.name.as_str()).collect();
+                        assert_eq!(names, vec!["email", "phone", "mrn"]);
+                        assert_eq!(detectors[2].marker(), "[REDACTED:MRN]");
+                    }
+                    _ => panic!("`place_of_service_source_value` was not redacted"),
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn redacted_columns_are_traced() {
+        let redacted = |query| {
+            redacted_columns(&test_transform_for(query).into_inner())
+                .unwrap()
+                .into_iter()
+                .map(|(column, detectors)| (column, detectors.len()))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            redacted(
+                "\
+                SELECT CONCAT(place_of_service_source_value, '.') AS notes \
+                FROM patient_data.care_site \
+                "
+            ),
+            vec![("notes".to_string(), 3)]
+        );
+        assert_eq!(
+            redacted(
+                "\
+                SELECT notes AS remarks \
+                FROM ( \
+                    SELECT place_of_service_source_value AS notes \
+                    FROM patient_data.care_site \
+                ) \
+                "
+            ),
+            vec![("remarks".to_string(), 3)]
+        );
+    }
+
+    #[test]
+    fn transform_tokenize() {
+        let rel_t = test_transform_for(
+            "\
+            SELECT location_source_value FROM patient_data.location
+            ",
+        )
+        .into_inner();
+
+        let table_meta = rel_t.board.unwrap();
+        assert!(table_meta
+            .audience
+            .contains(&block_type!("resource"."group"."wheel")));
+
+        match rel_t.root {
+            Rel::Projection(Projection { attributes, .. }) => {
+                match attributes[0]
+                    .as_ref()
+                    .map_owned(&mut |child| child.as_ref())
+                {
+                    Expr::As(As { expr, .. }) => match expr {
+                        Expr::Tokenize(Tokenize {
+                            keyset, context, ..
+                        }) => {
+                            assert_eq!(keyset.keyset.as_slice(), b"token-keyset".as_ref());
+                            assert!(keyset.kms_key.starts_with("gcp-kms://"));
+                            assert_eq!(context, "location");
+                        }
+                        _ => panic!("`location_source_value` was not tokenized"),
+                    },
+                    _ => panic!("`location_source_value` was not tokenized"),
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn transform_generalize() {
+        let rel_t = test_transform_for(
+            "\
+            SELECT year_of_birth FROM patient_data.person
+            ",
+        )
+        .into_inner();
+
+        let table_meta = rel_t.board.unwrap();
+        assert!(table_meta
+            .audience
+            .contains(&block_type!("resource"."group"."wheel")));
+
+        match rel_t.root {
+            Rel::Projection(Projection { attributes, .. }) => {
+                // years are binned by decades, which are still years
+                assert_eq!(attributes[0].board.as_ref().unwrap().ty, DataType::Integer);
+                match attributes[0]
+                    .as_ref()
+                    .map_owned(&mut |child| child.as_ref())
+                {
+                    Expr::As(As { expr, alias }) => {
+                        assert_eq!(alias, "year_of_birth".to_string());
+                        match expr {
+                            Expr::Generalize(Generalize {
+                                bucketing: Bucketing::FixedWidth { width, .. },
+                                ..
+                            }) => assert_eq!(*width, 10.),
+                            _ => panic!("`year_of_birth` was not generalized"),
+                        }
+                    }
+                    _ => panic!("`year_of_birth` was not generalized"),
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn transform_expression() {
+        let rel_t = test_transform_for(
+            "\
+            SELECT EXTRACT(MONTH FROM birth_datetime) AS birth_month FROM patient_data.person
+            ",
+        )
+        .into_inner();
+        assert!(rel_t
+            .board
+            .unwrap()
+            .audience
+            .contains(&block_type!("resource"."group"."wheel")));
+
+        let rel_t = test_transform_for(
+            "\
+            SELECT birth_datetime FROM patient_data.person
+            ",
+        )
+        .into_inner();
+        assert!(!rel_t
+            .board
+            .unwrap()
+            .audience
+            .contains(&block_type!("resource"."group"."wheel")));
+    }
+
+    #[test]
+    fn transform_predicates() {
+        let rel_t = test_transform_for(
+            "\
+            SELECT vocabulary_id FROM patient_data.vocabulary \
+            WHERE vocabulary_name = 'Gender'
+            ",
+        )
+        .into_inner();
+        assert!(rel_t
+            .board
+            .unwrap()
+            .audience
+            .contains(&block_type!("resource"."group"."wheel")));
+
+        let rel_t = test_transform_for(
+            "\
+            SELECT vocabulary_id FROM patient_data.vocabulary \
+            WHERE vocabulary_version = 'v5.0'
+            ",
+        )
+        .into_inner();
+        assert!(rel_t.board.unwrap().audience.is_empty());
+
+        // filtering on an unprotected column could single out a person even
+        // when the aggregate is differentially private
+        let rel_t = test_transform_for(
+            "\
+            SELECT gender_concept_id, COUNT(person_id) \
+            FROM patient_data.person \
+            WHERE person_source_value = '0001' \
+            GROUP BY gender_concept_id
+            ",
+        )
+        .into_inner();
+        assert!(rel_t.board.unwrap().audience.is_empty());
+    }
+
     #[test]
     fn transform_diff_priv() {
         let rel_t = test_transform_for(
@@ -883,7 +2725,459 @@ pub mod tests {
         );
         // For now this is enough in order to check that diff priv was triggered
         // as it is the only policy with an associated cost
-        assert!(*rel_t.cost.values().next().unwrap() > 0f64);
+        assert!(rel_t.cost.values().next().unwrap().epsilon > 0f64);
+        // the selection of the groups spends delta
+        assert!(rel_t.cost.values().next().unwrap().delta > 0f64);
+    }
+
+    #[test]
+    fn transform_diff_priv_bounds_partitions() {
+        let rel_t = test_transform_for(
+            "\
+            SELECT gender_concept_id, COUNT(person_id) \
+            FROM patient_data.person \
+            GROUP BY gender_concept_id
+            ",
+        );
+        // the released groups are selected over the aggregation of the rows
+        // entities keep in their first groups
+        let aggregated = match &rel_t.root.root {
+            Rel::Projection(Projection { from, .. }) => match &from.root {
+                Rel::Selection(Selection { from, .. }) => from,
+                _ => panic!("expected the groups to be selected"),
+            },
+            _ => panic!("expected the groups to be projected"),
+        };
+        let bounded = match &aggregated.root {
+            Rel::Aggregation(Aggregation { from, .. }) => from,
+            _ => panic!("expected the groups to be aggregated"),
+        };
+        match &bounded.root {
+            Rel::Selection(Selection { from, where_ }) => {
+                match where_.as_ref() {
+                    Expr::BinaryOp(BinaryOp {
+                        op: BinaryOperator::LtEq,
+                        right,
+                        ..
+                    }) => assert!(matches!(
+                        right.as_ref(),
+                        Expr::Literal(Literal(LiteralValue::Long(1)))
+                    )),
+                    _ => panic!("expected the rows to be bounded by their rank"),
+                }
+                match &from.root {
+                    Rel::Projection(Projection { attributes, .. }) => {
+                        assert!(attributes.iter().any(|attribute| match attribute.as_ref() {
+                            Expr::As(As { expr, .. }) => {
+                                matches!(expr.as_ref(), Expr::PartitionRank(..))
+                            }
+                            _ => false,
+                        }))
+                    }
+                    _ => panic!("expected the rows to be ranked"),
+                }
+            }
+            _ => panic!("expected the rows of entities to be bounded"),
+        }
+    }
+
+    /// The variances of the Laplace noise on the attributes of `rel_t` and
+    /// of its descendants, by alias
+    fn noisy_variances(rel_t: &RelT, found: &mut HashMap<String, f64>) {
+        let in_rel = std::cell::RefCell::new(HashMap::new());
+        rel_t.root.map_expressions(&|expr_t| {
+            if let Expr::As(As { expr, alias }) = expr_t.as_ref() {
+                if let Expr::Noisy(Noisy {
+                    distribution: Distribution::Laplace { variance, .. },
+                    ..
+                }) = expr.as_ref()
+                {
+                    in_rel.borrow_mut().insert(alias, variance);
+                }
+            }
+        });
+        found.extend(in_rel.into_inner());
+        rel_t.root.map(&mut |child| noisy_variances(child, found));
+    }
+
+    #[test]
+    fn transform_diff_priv_scales_with_partitions() {
+        let query = "\
+            SELECT gender_concept_id, COUNT(DISTINCT person_id) AS persons \
+            FROM patient_data.person \
+            GROUP BY gender_concept_id
+            ";
+        let mut variances = HashMap::new();
+        noisy_variances(&test_transform_for(query).into_inner(), &mut variances);
+        assert_eq!(variances["persons"], 1.);
+
+        // entities adding to up to 4 groups move up to 4 counts
+        let access = mk_node_with(|resource| {
+            if let Some(ResourceEnum::Dataset(dataset)) = resource.resource.as_mut() {
+                for policy in dataset.policies.iter_mut() {
+                    if let Some(policy::Policy::DifferentialPrivacy(differential_privacy)) =
+                        policy.policy.as_mut()
+                    {
+                        differential_privacy.max_partitions_contributed = 4;
+                    }
+                }
+            }
+        });
+        let rel_t = test_transform_on(&access, query, Some("research")).unwrap();
+        let mut variances = HashMap::new();
+        noisy_variances(&rel_t.into_inner(), &mut variances);
+        assert_eq!(variances["persons"], 4.);
+        assert_eq!(
+            variances["__bucket_count"],
+            PartitionSelection::new(1., 1e-5, 4).unwrap().scale()
+        );
+    }
+
+    #[test]
+    fn partition_selection_threshold() {
+        let selection = PartitionSelection::new(1., 1e-5, 1).unwrap();
+        assert!((selection.threshold() - (1. - (2e-5f64).ln())).abs() < 1e-6);
+
+        // more groups per entity need more noise, and a higher threshold
+        let wider = PartitionSelection::new(1., 1e-5, 4).unwrap();
+        assert!(wider.scale() > selection.scale());
+        assert!(wider.threshold() > selection.threshold());
+
+        assert!(PartitionSelection::new(1., 0., 1).is_none());
+        assert!(PartitionSelection::new(1., 1e-5, 0).is_none());
+    }
+
+    #[test]
+    fn transform_exclusion_list() {
+        let rel_t = test_transform_for(
+            "\
+            SELECT gender_concept_id, COUNT(person_id) \
+            FROM patient_data.person \
+            GROUP BY gender_concept_id
+            ",
+        );
+        assert!(rel_t.cost.values().next().unwrap().epsilon > 0f64);
+        // the excluded patients are filtered out below the noisy counts
+        let mut filters = Vec::new();
+        find_filters(&rel_t.root, &mut filters);
+        assert!(filters.iter().any(|filter| match filter.as_ref() {
+            Expr::InList(InList {
+                list,
+                negated: true,
+                ..
+            }) => list.iter().any(|value| matches!(
+                value.as_ref(),
+                Expr::Literal(Literal(LiteralValue::Long(42)))
+            )),
+            _ => false,
+        }));
+    }
+
+    /// The filters of the selections of `rel_t` and of its descendants
+    fn find_filters(rel_t: &RelT, found: &mut Vec<ExprT>) {
+        if let Rel::Selection(Selection { where_, .. }) = &rel_t.root {
+            found.push(where_.clone());
+        }
+        rel_t.root.map(&mut |child| find_filters(child, found));
+    }
+
+    #[test]
+    fn transform_consent() {
+        let query = "\
+            SELECT gender_concept_id, COUNT(person_id) \
+            FROM patient_data.person \
+            GROUP BY gender_concept_id
+            ";
+        // only the patients who consented to the purpose are counted
+        let rel_t = test_transform_with_purpose(query, Some("research")).unwrap();
+        let root = serde_json::to_string(&rel_t.root.root).unwrap();
+        assert!(root.contains("InTable"));
+        assert!(root.contains("research"));
+
+        // and the dataset may not be queried for no purpose
+        assert!(test_transform_with_purpose(query, None).is_err());
+    }
+
+    #[test]
+    fn transform_purpose_bindings() {
+        let query = "\
+            SELECT visit_start_date FROM patient_data.visit_occurrence
+            ";
+        // visit dates are only whitelisted for billing
+        let rel_t = test_transform_with_purpose(query, Some("billing"))
+            .unwrap()
+            .into_inner();
+        assert!(rel_t
+            .board
+            .unwrap()
+            .audience
+            .contains(&block_type!("resource"."group"."wheel")));
+
+        let rel_t = test_transform_for(query).into_inner();
+        assert!(rel_t.board.unwrap().audience.is_empty());
+    }
+
+    #[test]
+    fn transform_expired_bindings() {
+        // the agreement whitelisting visit types has expired
+        let rel_t = test_transform_for(
+            "\
+            SELECT visit_concept_id FROM patient_data.visit_occurrence
+            ",
+        )
+        .into_inner();
+        assert!(rel_t.board.unwrap().audience.is_empty());
+    }
+
+    fn test_authorize_templates_with_purpose(
+        query: &str,
+        purpose: Option<&str>,
+    ) -> Result<bool, super::Error> {
+        let access = mk_manifest_node();
+        Runtime::new().unwrap().block_on(async {
+            let ctx = access.context().await.unwrap();
+            let validator = Validator::new(&ctx);
+            let policies = access.policies_for_group("wheel", purpose).unwrap();
+            let rel_t = validator.validate_str(query).unwrap();
+            let audience = block_type!("resource"."group"."wheel");
+            let templates = access.templates("wheel", purpose).unwrap();
+            RelTransformer::new(&policies, &audience, &access)
+                .with_templates(&templates)
+                .authorize_templates(&rel_t, &ctx)
+        })
+    }
+
+    fn test_authorize_templates(query: &str) -> Result<bool, super::Error> {
+        test_authorize_templates_with_purpose(query, None)
+    }
+
+    #[test]
+    fn transform_templates() {
+        // the placeholder is filled in by a literal of its type
+        assert!(test_authorize_templates(
+            "\
+            SELECT COUNT(location_id) FROM location_data.location WHERE state = 'CA'
+            ",
+        )
+        .unwrap());
+
+        // and not by literals of another type
+        assert!(test_authorize_templates(
+            "\
+            SELECT COUNT(location_id) FROM location_data.location WHERE state = 42
+            ",
+        )
+        .is_err());
+
+        // but anything else on the dataset is refused
+        assert!(test_authorize_templates(
+            "\
+            SELECT city FROM location_data.location WHERE state = 'CA'
+            ",
+        )
+        .is_err());
+
+        // and datasets without templates are left to their bindings
+        assert!(!test_authorize_templates(
+            "\
+            SELECT vocabulary_id FROM patient_data.vocabulary
+            ",
+        )
+        .unwrap());
+    }
+
+    #[test]
+    fn transform_templates_scoped() {
+        // templates scoped to purposes only authorize jobs declaring one of
+        // them
+        let by_zip = "\
+            SELECT COUNT(location_id) FROM location_data.location WHERE zip = '94110'
+            ";
+        assert!(test_authorize_templates(by_zip).is_err());
+        assert!(test_authorize_templates_with_purpose(by_zip, Some("billing")).is_err());
+        assert!(test_authorize_templates_with_purpose(by_zip, Some("research")).unwrap());
+
+        // and expired templates do not authorize anything anymore
+        assert!(test_authorize_templates(
+            "\
+            SELECT COUNT(location_id) FROM location_data.location WHERE city = 'Oakland'
+            ",
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn placeholders_are_only_filled_by_literals() {
+        let template = serde_json::json!({"Placeholder": ["state", "String"]});
+        let mut bound = HashMap::new();
+        assert!(unify(
+            &template,
+            &serde_json::json!({"StringLiteral": "CA"}),
+            &mut bound
+        ));
+        // placeholders sharing a name take the same value
+        assert!(!unify(
+            &template,
+            &serde_json::json!({"StringLiteral": "NY"}),
+            &mut bound
+        ));
+        // and queries may not smuggle placeholders of their own
+        assert!(!unify(&template, &template, &mut HashMap::new()));
+    }
+
+    #[test]
+    fn placeholders_are_only_in_templates() {
+        let ctx = just_get_me_a_context();
+        let sql = "SELECT person_id FROM patient_data.person WHERE person_id = N'person:INTEGER'";
+        assert!(Validator::new(&ctx).validate_str(sql).is_err());
+        assert!(Validator::new(&ctx)
+            .with_placeholders()
+            .validate_str(sql)
+            .is_ok());
+    }
+
+    #[test]
+    fn transform_diff_priv_declared_domains() {
+        let rel_t = test_transform_for(
+            "\
+            SELECT gender_concept_id, AVG(year_of_birth) \
+            FROM patient_data.person \
+            GROUP BY gender_concept_id
+            ",
+        );
+        assert!(rel_t.cost.values().next().unwrap().epsilon > 0f64);
+
+        // the domain of `month_of_birth` is not declared and may not be
+        // read from the data
+        let rel_t = test_transform_for(
+            "\
+            SELECT gender_concept_id, AVG(month_of_birth) \
+            FROM patient_data.person \
+            GROUP BY gender_concept_id
+            ",
+        )
+        .into_inner();
+        assert!(rel_t.board.unwrap().audience.is_empty());
+    }
+
+    #[test]
+    fn transform_diff_priv_median() {
+        let rel_t = test_transform_for(
+            "\
+            SELECT gender_concept_id, MEDIAN(year_of_birth), COUNT(DISTINCT person_id) \
+            FROM patient_data.person \
+            GROUP BY gender_concept_id
+            ",
+        );
+        // one charge per aggregate, and one for the selection of the groups
+        assert_eq!(rel_t.cost.values().next().unwrap().epsilon, 3f64);
+        // the quantile is chosen among the years of the declared domain
+        let mut noisy_quantiles = Vec::new();
+        find_noisy_quantiles(&rel_t.root, &mut noisy_quantiles);
+        match noisy_quantiles.as_slice() {
+            [NoisyQuantile {
+                quantile,
+                min,
+                max,
+                step,
+                ..
+            }] => {
+                assert_eq!(*quantile, 0.5);
+                assert_eq!((*min, *max, *step), (1900., 2020., 1.));
+            }
+            _ => panic!("expected the median to be chosen with the exponential mechanism"),
+        }
+    }
+
+    fn find_noisy_quantiles(rel_t: &RelT, found: &mut Vec<NoisyQuantile<Arc<ExprT>>>) {
+        fn in_expr(expr_t: &ExprT, found: &mut Vec<NoisyQuantile<Arc<ExprT>>>) {
+            if let Expr::NoisyQuantile(noisy_quantile) = &expr_t.root {
+                found.push(noisy_quantile.clone());
+            }
+            expr_t.root.map(&mut |child| in_expr(child, found));
+        }
+        let in_rel = std::cell::RefCell::new(Vec::new());
+        rel_t
+            .root
+            .map_expressions(&|expr_t| in_expr(expr_t, &mut in_rel.borrow_mut()));
+        found.extend(in_rel.into_inner());
+        rel_t
+            .root
+            .map(&mut |child| find_noisy_quantiles(child, found));
+    }
+
+    #[test]
+    fn transform_diff_priv_top_k() {
+        let rel_t = test_transform_for(
+            "\
+            SELECT race_concept_id, COUNT(person_id) \
+            FROM patient_data.person \
+            GROUP BY race_concept_id \
+            ORDER BY COUNT(person_id) DESC \
+            LIMIT 3
+            ",
+        );
+        // the selection of the top groups is charged on top of the count
+        // and of the selection of the groups
+        assert_eq!(rel_t.cost.values().next().unwrap().epsilon, 3f64);
+        match &rel_t.root.root {
+            Rel::OrderBy(OrderBy { from, .. }) => match &from.root {
+                Rel::Projection(Projection { from, .. }) => {
+                    assert!(matches!(from.root, Rel::Limit(..)))
+                }
+                _ => panic!("expected the top groups to be projected"),
+            },
+            _ => panic!("expected the top groups to be ordered"),
+        }
+    }
+
+    #[test]
+    fn transform_diff_priv_top_k_alias() {
+        let rel_t = test_transform_for(
+            "\
+            SELECT race_concept_id, COUNT(person_id) AS n \
+            FROM patient_data.person \
+            GROUP BY race_concept_id \
+            ORDER BY n DESC \
+            LIMIT 3
+            ",
+        );
+        assert_eq!(rel_t.cost.values().next().unwrap().epsilon, 3f64);
+        match &rel_t.root.root {
+            Rel::OrderBy(OrderBy { by, .. }) => match by[0].as_ref() {
+                Expr::Column(Column(key)) => assert_eq!(key.name(), "n"),
+                _ => panic!("expected the top groups to be ordered by their alias"),
+            },
+            _ => panic!("expected the top groups to be ordered"),
+        }
+    }
+
+    #[test]
+    fn transform_diff_priv_join() {
+        // joining on the patient keeps the contribution of each patient
+        // bounded
+        let rel_t = test_transform_for(
+            "\
+            SELECT a.gender_concept_id, COUNT(a.person_id) \
+            FROM patient_data.person AS a \
+            JOIN patient_data.visit_occurrence AS b \
+            ON a.person_id = b.person_id \
+            GROUP BY a.gender_concept_id
+            ",
+        );
+        assert!(rel_t.cost.values().next().unwrap().epsilon > 0f64);
+
+        let rel_t = test_transform_for(
+            "\
+            SELECT a.gender_concept_id, COUNT(a.person_id) \
+            FROM patient_data.person AS a \
+            JOIN patient_data.visit_occurrence AS b \
+            ON a.person_id = b.visit_occurrence_id \
+            GROUP BY a.gender_concept_id
+            ",
+        )
+        .into_inner();
+        assert!(rel_t.board.unwrap().audience.is_empty());
     }
 
     #[test]
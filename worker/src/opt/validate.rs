@@ -7,6 +7,7 @@ use sqlparser::{
     },
     dialect::GenericDialect,
     parser::{Parser as SqlParser, ParserError},
+    tokenizer::Tokenizer,
 };
 
 use super::*;
@@ -30,11 +31,24 @@ fn maybe_wrap_alias(rel_t: RelT, alias: Option<&TableAlias>) -> ValidateResult<R
 }
 
 pub trait Validate: ToContext<M = TableMeta> {
+    /// Whether the queries validated are templates, whose placeholders are
+    /// written as national string literals
+    fn placeholders(&self) -> bool {
+        false
+    }
+
+    fn expr_validator<'b>(&self, ctx: &'b Context<ExprMeta>) -> ExprValidator<'b> {
+        ExprValidator {
+            ctx,
+            placeholders: self.placeholders(),
+        }
+    }
+
     fn validate_query(&self, query: &Query) -> ValidateResult<RelT> {
         let mut root = self.validate_set_expr(&query.body)?;
 
         let ctx = self.to_context().flatten();
-        let expr_validator = ExprValidator::new(&ctx);
+        let expr_validator = self.expr_validator(&ctx);
 
         if query.order_by.len() > 0 {
             let mut orders = Vec::new();
@@ -105,7 +119,7 @@ pub trait Validate: ToContext<M = TableMeta> {
             .ok_or(ValidateError::Wip("SELECT [..] EOF".to_string()))?;
 
         let ctx = from.try_to_context()?;
-        let expr_validator = ExprValidator::new(&ctx);
+        let expr_validator = self.expr_validator(&ctx);
 
         let mut select_items = Vec::<ExprT>::new();
         for si in select.projection.iter() {
@@ -134,7 +148,9 @@ pub trait Validate: ToContext<M = TableMeta> {
         }
 
         if let Some(slc) = &select.selection {
-            let where_ = ExprValidator::new(&root.try_to_context()?).validate_expr(slc)?;
+            let where_ = self
+                .expr_validator(&root.try_to_context()?)
+                .validate_expr(slc)?;
             root = RelT::from(Selection { from: root, where_ });
         }
 
@@ -188,7 +204,7 @@ pub trait Validate: ToContext<M = TableMeta> {
             let right = self.validate_table_factor(&join.relation)?;
             ctx.extend(right.try_to_context()?);
 
-            let expr_validator = ExprValidator::new(&ctx);
+            let expr_validator = self.expr_validator(&ctx);
 
             let m_jc = match &join.join_operator {
                 ast::JoinOperator::Inner(cst)
@@ -226,6 +242,7 @@ pub trait Validate: ToContext<M = TableMeta> {
 
 pub struct Validator<'a> {
     ctx: &'a Context<TableMeta>,
+    placeholders: bool,
 }
 
 impl<'a> ToContext for Validator<'a> {
@@ -237,7 +254,16 @@ impl<'a> ToContext for Validator<'a> {
 
 impl<'a> Validator<'a> {
     pub fn new(ctx: &'a Context<TableMeta>) -> Self {
-        Self { ctx }
+        Self {
+            ctx,
+            placeholders: false,
+        }
+    }
+
+    /// Validate templates, with their placeholders, instead of queries
+    pub fn with_placeholders(mut self) -> Self {
+        self.placeholders = true;
+        self
     }
 
     pub fn validate_str(&self, sql: &str) -> ValidateResult<RelT> {
@@ -256,9 +282,18 @@ impl<'a> Validator<'a> {
     }
 }
 
-impl<'a> Validate for Validator<'a> {}
+impl<'a> Validate for Validator<'a> {
+    fn placeholders(&self) -> bool {
+        self.placeholders
+    }
+}
 
 pub trait ValidateExpr: ToContext<M = ExprMeta> {
+    /// Whether placeholders are validated, as in templates
+    fn placeholders(&self) -> bool {
+        false
+    }
+
     fn validate_expr(&self, expr: &Expr) -> ValidateResult<ExprT> {
         match expr {
             Expr::Identifier(ident) => {
@@ -359,6 +394,17 @@ pub trait ValidateExpr: ToContext<M = ExprMeta> {
                     distinct: *distinct,
                 }))
             }
+            Expr::Extract { field, expr } => {
+                let expr = self.validate_expr(expr)?;
+                Ok(ExprT::from(Extract {
+                    field: field.into(),
+                    expr,
+                }))
+            }
+            Expr::Value(ast::Value::NationalStringLiteral(placeholder)) if self.placeholders() => {
+                let lit = LiteralValue::placeholder(placeholder)?;
+                Ok(ExprT::from(Literal(lit)))
+            }
             Expr::Value(value) => {
                 let lit = value.try_into()?;
                 Ok(ExprT::from(Literal(lit)))
@@ -433,11 +479,24 @@ pub trait ValidateExpr: ToContext<M = ExprMeta> {
 
 pub struct ExprValidator<'a> {
     ctx: &'a Context<ExprMeta>,
+    placeholders: bool,
 }
 
 impl<'a> ExprValidator<'a> {
-    fn new(ctx: &'a Context<ExprMeta>) -> Self {
-        Self { ctx }
+    pub(crate) fn new(ctx: &'a Context<ExprMeta>) -> Self {
+        Self {
+            ctx,
+            placeholders: false,
+        }
+    }
+
+    pub(crate) fn validate_str(&self, sql: &str) -> ValidateResult<ExprT> {
+        let dialect = GenericDialect {};
+        let tokens = Tokenizer::new(&dialect, sql)
+            .tokenize()
+            .map_err(|err| ParserError::TokenizerError(format!("{:?}", err)))?;
+        let expr = SqlParser::new(tokens).parse_expr()?;
+        self.validate_expr(&expr)
     }
 }
 
@@ -448,7 +507,11 @@ impl<'a> ToContext for ExprValidator<'a> {
     }
 }
 
-impl<'a> ValidateExpr for ExprValidator<'a> {}
+impl<'a> ValidateExpr for ExprValidator<'a> {
+    fn placeholders(&self) -> bool {
+        self.placeholders
+    }
+}
 
 #[cfg(test)]
 pub mod tests {
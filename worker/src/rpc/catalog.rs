@@ -84,6 +84,25 @@ where
             virtual_columns,
         }))
     }
+
+    async fn get_virtual_column_statistics(
+        &self,
+        req: Request<GetVirtualColumnStatisticsRequest>,
+    ) -> Result<Response<GetVirtualColumnStatisticsResponse>, Status> {
+        let access = self.access.elevate(&req)?;
+        let GetVirtualColumnStatisticsRequest {
+            dataset_id,
+            table_id,
+            column_id,
+        } = req.get_ref();
+        let statistics = access
+            .column_statistics(dataset_id, table_id, column_id)
+            .await?;
+
+        Ok(Response::new(GetVirtualColumnStatisticsResponse {
+            statistics: Some(statistics),
+        }))
+    }
 }
 
 /// The Catalog here is a 'naive' Catalog because it doesn't have the policy context.
@@ -252,6 +252,53 @@ where
 
         Ok(Response::new(ListJobsResponse { jobs }))
     }
+
+    /// Reverse tokens produced by a tokenize policy
+    async fn detokenize_job(
+        &self,
+        req: Request<DetokenizeJobRequest>,
+    ) -> Result<Response<DetokenizeJobResponse>, Status> {
+        let access = self.access.elevate(&req)?;
+
+        let DetokenizeJobRequest {
+            dataset,
+            policy,
+            tokens,
+            justification,
+        } = req.into_inner();
+
+        let values = access
+            .detokenize(&dataset, &policy, tokens, &justification)
+            .await?;
+
+        Ok(Response::new(DetokenizeJobResponse { values }))
+    }
+
+    /// List the entries of the audit log
+    async fn list_audit_entries(
+        &self,
+        req: Request<ListAuditEntriesRequest>,
+    ) -> Result<Response<ListAuditEntriesResponse>, Status> {
+        let access = self.access.elevate(&req)?;
+
+        let entries = access.audit_entries(req.into_inner().from)?;
+
+        Ok(Response::new(ListAuditEntriesResponse { entries }))
+    }
+
+    /// Find the jobs a leaked extract of watermarked results likely comes from
+    async fn trace_jobs(
+        &self,
+        req: Request<TraceJobsRequest>,
+    ) -> Result<Response<TraceJobsResponse>, Status> {
+        let access = self.access.elevate(&req)?;
+
+        let TraceJobsRequest { values, from, to } = req.into_inner();
+
+        let jobs = access.trace(values, &from, &to).await?;
+
+        Ok(Response::new(TraceJobsResponse { jobs }))
+    }
 }
 
 #[cfg(test)]